@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use color::Color;
+
+// Largest difference between a corner and the average of all four, in any
+// channel, before a square gets split
+pub const THRESHOLD: f64 = 0.1;
+
+// Times a pixel can be split, each time into four smaller squares
+pub const MAX_DEPTH: u32 = 2;
+
+// Adaptive supersampling by contrast. Each pixel is sampled at its corners,
+// and only split into four smaller squares when those corners differ by more
+// than the threshold, then the same check is repeated on each square. Squares
+// share the samples at their corners, and so do neighboring pixels, so flat
+// areas like the background only take about one sample per pixel while edges
+// get up to (2^max_depth + 1)^2.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Adaptive {
+    pub threshold: f64,
+    pub max_depth: u32,
+}
+
+impl Adaptive {
+    pub fn new() -> Adaptive {
+        Adaptive {
+            threshold: THRESHOLD,
+            max_depth: MAX_DEPTH,
+        }
+    }
+
+    // Averages the color over a pixel, given its column and row counting in
+    // the same directions as x and y, along with the center and size of the
+    // rectangle it covers on the image. Colors come from a function that finds
+    // the color anywhere on the image. Returns the color along with the number
+    // of points sampled that no earlier pixel sharing the corners had.
+    pub fn sample<F>(
+        &self,
+        corners: &mut Corners,
+        pixel: (u32, u32),
+        center: (f64, f64),
+        size: (f64, f64),
+        mut color_at: F,
+    ) -> (Color, u32)
+    where
+        F: FnMut(f64, f64) -> Color,
+    {
+        // Corners are numbered on a grid across the whole image, as fine as
+        // the deepest split, so neighboring squares and pixels find the
+        // samples they share
+        let cells = 1 << self.max_depth;
+        let (column, row) = (pixel.0 * cells, pixel.1 * cells);
+        let mut sampled = 0;
+
+        let color = {
+            let mut corner = |i: u32, j: u32| {
+                *corners.samples.entry((column + i, row + j)).or_insert_with(|| {
+                    sampled += 1;
+                    let u = i as f64 / cells as f64 - 0.5;
+                    let v = j as f64 / cells as f64 - 0.5;
+                    color_at(center.0 + u * size.0, center.1 + v * size.1)
+                })
+            };
+            self.subdivide(&mut corner, 0, 0, cells, 0)
+        };
+
+        (color, sampled)
+    }
+
+    // Average color of the square with its lower corner at (i, j) on the
+    // grid, splitting it further if its corners don't agree
+    fn subdivide<F>(&self, corner: &mut F, i: u32, j: u32, size: u32, depth: u32) -> Color
+    where
+        F: FnMut(u32, u32) -> Color,
+    {
+        let corners = [
+            corner(i, j),
+            corner(i + size, j),
+            corner(i, j + size),
+            corner(i + size, j + size),
+        ];
+        let average = (corners[0] + corners[1] + corners[2] + corners[3]) / 4.0;
+
+        let contrast = corners.iter().fold(0.0f64, |contrast, c| {
+            contrast
+                .max((c.r - average.r).abs())
+                .max((c.g - average.g).abs())
+                .max((c.b - average.b).abs())
+        });
+        if depth >= self.max_depth || contrast <= self.threshold {
+            return average;
+        }
+
+        let half = size / 2;
+        let total = self.subdivide(corner, i, j, half, depth + 1)
+            + self.subdivide(corner, i + half, j, half, depth + 1)
+            + self.subdivide(corner, i, j + half, half, depth + 1)
+            + self.subdivide(corner, i + half, j + half, half, depth + 1);
+        total / 4.0
+    }
+}
+
+impl Default for Adaptive {
+    fn default() -> Adaptive {
+        Adaptive::new()
+    }
+}
+
+// Colors sampled at the corners of pixels, kept while neighboring pixels are
+// sampled so they don't trace the corners they share again
+pub struct Corners {
+    samples: HashMap<(u32, u32), Color>,
+}
+
+impl Corners {
+    pub fn new() -> Corners {
+        Corners {
+            samples: HashMap::new(),
+        }
+    }
+}
+
+impl Default for Corners {
+    fn default() -> Corners {
+        Corners::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use adaptive::{Adaptive, Corners};
+    use color::Color;
+
+    // Tests that flat areas only sample the corners, and that neighboring
+    // pixels share them, so a flat region costs about one sample per pixel
+    #[test]
+    fn flat() {
+        let adaptive = Adaptive::new();
+        let flat = |_, _| Color::new(0.25, 0.5, 0.75);
+
+        let mut corners = Corners::new();
+        let (color, samples) = adaptive.sample(&mut corners, (0, 0), (0.0, 0.0), (1.0, 1.0), flat);
+        assert_eq!(Color::new(0.25, 0.5, 0.75), color);
+        assert_eq!(4, samples);
+
+        let mut corners = Corners::new();
+        let mut total = 0;
+        for row in 0..16 {
+            for column in 0..16 {
+                let center = (column as f64, row as f64);
+                let pixel = (column, row);
+                total += adaptive.sample(&mut corners, pixel, center, (1.0, 1.0), flat).1;
+            }
+        }
+        assert_eq!(17 * 17, total);
+    }
+
+    // Tests that an edge through the pixel is split down to the deepest level
+    // only where it crosses, and that shared corners are only sampled once
+    #[test]
+    fn edge() {
+        let adaptive = Adaptive {
+            threshold: 0.1,
+            max_depth: 2,
+        };
+
+        // White on the left 30% of the pixel
+        let edge = |x: f64, _: f64| {
+            if x < -0.2 {
+                Color::new(1.0, 1.0, 1.0)
+            } else {
+                Color::black()
+            }
+        };
+
+        let mut corners = Corners::new();
+        let (color, samples) = adaptive.sample(&mut corners, (0, 0), (0.0, 0.0), (1.0, 1.0), edge);
+        assert!(samples > 4 && samples < 25, "Got {} samples", samples);
+        assert!((color.r - 0.3).abs() < 0.1, "Expected about 0.3, got {}", color.r);
+
+        // Without any splits, the edge is only seen by two of the corners
+        let coarse = Adaptive {
+            max_depth: 0,
+            ..adaptive
+        };
+        let mut corners = Corners::new();
+        let (color, samples) = coarse.sample(&mut corners, (0, 0), (0.0, 0.0), (1.0, 1.0), edge);
+        assert_eq!(4, samples);
+        assert_eq!(0.5, color.r);
+    }
+}
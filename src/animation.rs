@@ -0,0 +1,212 @@
+use cgmath::{InnerSpace, Vector3};
+use motion::Transform;
+use color::Color;
+
+// Steps taken to find where a Bezier timing curve reaches a point in time.
+// Each one halves the error, so this is far finer than a frame.
+const BEZIER_STEPS: u32 = 32;
+
+// Values that can be blended between keyframes
+pub trait Interpolate: Clone {
+    // Blends toward another value, from this one at 0 to the other at 1
+    fn interpolate(&self, other: &Self, amount: f64) -> Self;
+}
+
+impl Interpolate for f64 {
+    fn interpolate(&self, other: &f64, amount: f64) -> f64 {
+        self + (other - self) * amount
+    }
+}
+
+impl Interpolate for Vector3<f64> {
+    fn interpolate(&self, other: &Vector3<f64>, amount: f64) -> Vector3<f64> {
+        self.lerp(*other, amount)
+    }
+}
+
+impl Interpolate for Color {
+    fn interpolate(&self, other: &Color, amount: f64) -> Color {
+        *self * (1.0 - amount) + *other * amount
+    }
+}
+
+impl Interpolate for Transform {
+    fn interpolate(&self, other: &Transform, amount: f64) -> Transform {
+        Transform::interpolate(self, other, amount)
+    }
+}
+
+// How a value changes on the way from one keyframe to the next
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Interpolation {
+    // At a steady rate
+    Linear,
+
+    // Along a timing curve from (0, 0) to (1, 1) through two control points,
+    // the same as CSS's cubic-bezier(). The curve maps the time between the
+    // keyframes to how far the value has changed.
+    Bezier(f64, f64, f64, f64),
+}
+
+impl Interpolation {
+    /// Creates a curve that starts slowly, speeds up and slows down again
+    pub fn ease() -> Interpolation {
+        Interpolation::Bezier(0.42, 0.0, 0.58, 1.0)
+    }
+
+    // How far the value has changed a fraction of the way between keyframes
+    pub fn amount(&self, t: f64) -> f64 {
+        match *self {
+            Interpolation::Linear => t,
+            Interpolation::Bezier(x1, y1, x2, y2) => {
+                let x1 = x1.clamp(0.0, 1.0);
+                let x2 = x2.clamp(0.0, 1.0);
+
+                // With control points inside [0, 1], time only ever moves
+                // forward along the curve, so it can be searched by halves
+                let (mut low, mut high) = (0.0, 1.0);
+                for _ in 0..BEZIER_STEPS {
+                    let middle = (low + high) / 2.0;
+                    if bezier(x1, x2, middle) < t {
+                        low = middle;
+                    } else {
+                        high = middle;
+                    }
+                }
+                bezier(y1, y2, (low + high) / 2.0)
+            }
+        }
+    }
+}
+
+// One coordinate of a cubic Bezier curve from 0 to 1, with the two control
+// points in between
+fn bezier(p1: f64, p2: f64, s: f64) -> f64 {
+    let r = 1.0 - s;
+    3.0 * r * r * s * p1 + 3.0 * r * s * s * p2 + s * s * s
+}
+
+// Value pinned at a frame, along with how it changes on the way to the next
+// keyframe
+#[derive(Clone, Debug)]
+pub struct Keyframe<T> {
+    pub frame: f64,
+    pub value: T,
+    pub interpolation: Interpolation,
+}
+
+// Value that changes over the course of an animation. Before the first
+// keyframe and after the last it holds still.
+#[derive(Clone, Debug)]
+pub struct Track<T> {
+    keys: Vec<Keyframe<T>>,
+}
+
+impl<T: Interpolate> Track<T> {
+    pub fn new() -> Track<T> {
+        Track { keys: vec![] }
+    }
+
+    /// Adds a keyframe, replacing any already at the same frame
+    pub fn key(mut self, frame: f64, value: T, interpolation: Interpolation) -> Track<T> {
+        self.keys.retain(|key| key.frame != frame);
+
+        let index = self.keys.iter().position(|key| key.frame > frame).unwrap_or(self.keys.len());
+        self.keys.insert(
+            index,
+            Keyframe {
+                frame,
+                value,
+                interpolation,
+            },
+        );
+        self
+    }
+
+    // Value at any frame, including between frames. Tracks without keyframes
+    // have no value.
+    pub fn at(&self, frame: f64) -> Option<T> {
+        let next = self.keys.iter().position(|key| key.frame > frame);
+        match next {
+            None => self.keys.last().map(|key| key.value.clone()),
+            Some(0) => Some(self.keys[0].value.clone()),
+            Some(index) => {
+                let (from, to) = (&self.keys[index - 1], &self.keys[index]);
+                let t = (frame - from.frame) / (to.frame - from.frame);
+                let amount = from.interpolation.amount(t);
+                Some(from.value.interpolate(&to.value, amount))
+            }
+        }
+    }
+}
+
+impl<T: Interpolate> Default for Track<T> {
+    fn default() -> Track<T> {
+        Track::new()
+    }
+}
+
+// Name of the image for a frame of an image sequence
+pub fn frame_path(frame: u32) -> String {
+    format!("frame_{:04}.png", frame)
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use cgmath::vec3;
+    use animation::{frame_path, Interpolation, Track};
+
+    // Tests that values blend between keyframes and hold still outside them
+    #[test]
+    fn linear() {
+        let track = Track::new()
+            .key(10.0, vec3(2.0, 0.0, 0.0), Interpolation::Linear)
+            .key(0.0, vec3(0.0, 0.0, 0.0), Interpolation::Linear)
+            .key(20.0, vec3(2.0, 4.0, 0.0), Interpolation::Linear);
+
+        assert_relative_eq!(vec3(0.0, 0.0, 0.0), track.at(-5.0).unwrap());
+        assert_relative_eq!(vec3(0.5, 0.0, 0.0), track.at(2.5).unwrap());
+        assert_relative_eq!(vec3(2.0, 0.0, 0.0), track.at(10.0).unwrap());
+        assert_relative_eq!(vec3(2.0, 1.0, 0.0), track.at(12.5).unwrap());
+        assert_relative_eq!(vec3(2.0, 4.0, 0.0), track.at(30.0).unwrap());
+
+        assert!(Track::<f64>::new().at(1.0).is_none());
+    }
+
+    // Tests that Bezier timing eases in and out but still meets the keyframes
+    #[test]
+    fn bezier() {
+        let ease = Interpolation::ease();
+        assert_relative_eq!(0.0, ease.amount(0.0), epsilon = 1e-6);
+        assert_relative_eq!(0.5, ease.amount(0.5), epsilon = 1e-6);
+        assert_relative_eq!(1.0, ease.amount(1.0), epsilon = 1e-6);
+        assert!(ease.amount(0.1) < 0.1);
+        assert!(ease.amount(0.9) > 0.9);
+
+        // Control points along the diagonal make a straight line
+        let straight = Interpolation::Bezier(0.25, 0.25, 0.75, 0.75);
+        assert_relative_eq!(0.3, straight.amount(0.3), epsilon = 1e-6);
+
+        let track = Track::new().key(0.0, 0.0, ease).key(4.0, 8.0, ease);
+        assert!(track.at(1.0).unwrap() < 2.0);
+        assert_relative_eq!(4.0, track.at(2.0).unwrap(), epsilon = 1e-6);
+    }
+
+    // Tests that keyframes at the same frame replace each other
+    #[test]
+    fn replace() {
+        let track = Track::new()
+            .key(0.0, 1.0, Interpolation::Linear)
+            .key(0.0, 3.0, Interpolation::Linear);
+        assert_relative_eq!(3.0, track.at(0.0).unwrap());
+    }
+
+    // Tests that frames are numbered with leading zeros
+    #[test]
+    fn frame_names() {
+        assert_eq!("frame_0001.png", frame_path(1));
+        assert_eq!("frame_0120.png", frame_path(120));
+    }
+}
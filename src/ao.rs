@@ -0,0 +1,142 @@
+use cgmath::{dot, Vector3};
+use integrator::Integrator;
+use sampling::cosine_hemisphere;
+use color::Color;
+use ray::Ray;
+use scene::Scene;
+use light::Rgb;
+use sampler::Sampler;
+
+const SAMPLES: u32 = 16;
+const MAX_DISTANCE: f64 = 1.0;
+
+// Estimates how much ambient light reaches a point by firing rays out across
+// the hemisphere above it. Rays that hit something within the max distance are
+// blocked; nooks and crevices block more of them and come out darker.
+//
+// Also works as an integrator on its own, shading every surface white except
+// for the occlusion, which is handy for checking the shape of a model.
+#[derive(Clone)]
+pub struct AmbientOcclusion {
+    // Rays fired per point. Zero turns occlusion off, so all ambient light
+    // gets through.
+    pub samples: u32,
+
+    // How far away something can be and still block ambient light
+    pub max_distance: f64,
+}
+
+impl AmbientOcclusion {
+    pub fn new() -> AmbientOcclusion {
+        AmbientOcclusion {
+            samples: SAMPLES,
+            max_distance: MAX_DISTANCE,
+        }
+    }
+
+    // Fraction of the ambient light that reaches the point, from 0 when it's
+    // completely enclosed to 1 when nothing is nearby. The normal has to face
+    // the side being lit.
+    pub fn visibility(
+        &self,
+        point: Vector3<f64>,
+        normal: Vector3<f64>,
+        time: f64,
+        scene: &Scene,
+        sampler: &mut Sampler,
+    ) -> f64 {
+        if self.samples == 0 {
+            return 1.0;
+        }
+
+        // Cosine weighted rays count the light from each direction by how much
+        // it would contribute to a diffuse surface
+        let open = (0..self.samples)
+            .filter(|_| {
+                let (u1, u2) = sampler.next_2d();
+                let direction = cosine_hemisphere(normal, u1, u2);
+                let mut ray = Ray::from_surface(point, normal, direction).at_time(time);
+                ray.t_max = self.max_distance;
+                !scene.occluded(&ray)
+            })
+            .count();
+
+        open as f64 / self.samples as f64
+    }
+}
+
+impl Default for AmbientOcclusion {
+    fn default() -> AmbientOcclusion {
+        AmbientOcclusion::new()
+    }
+}
+
+impl Integrator for AmbientOcclusion {
+    fn radiance(&self, ray: &Ray, scene: &Scene, sampler: &mut Sampler) -> Rgb {
+        match scene.intersect(ray) {
+            Some(intersect) => {
+                let n = if dot(intersect.normal, ray.direction()) > 0.0 {
+                    -intersect.normal
+                } else {
+                    intersect.normal
+                };
+
+                let visibility = self.visibility(intersect.point, n, ray.time, scene, sampler);
+                Color::new(visibility, visibility, visibility).to_rgb()
+            }
+            None => Rgb::new([255, 255, 255]),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use cgmath::vec3;
+    use ao::AmbientOcclusion;
+    use scene::Scene;
+    use floor::Floor;
+    use tracer::Background;
+    use light::{Material, Rgb};
+    use sampler::Independent;
+
+    // Tests that a wide wall in front of a point blocks all of its ambient
+    // light, unless it's farther away than the max distance
+    #[test]
+    fn visibility() {
+        let material = Material::new(Rgb::new([255, 255, 255]), (1.0, 1.0, 1.0), 0.0, 0.0, 0.0);
+        let mut scene = Scene::new(Background {
+            color: Rgb::new([0, 0, 0]),
+        });
+        scene.add(Box::new(Floor::new(
+            vec3(-10000.0, -10000.0, 0.5),
+            vec3(-10000.0, 10000.0, 0.5),
+            vec3(10000.0, -10000.0, 0.5),
+            vec3(10000.0, 10000.0, 0.5),
+            material.clone(),
+            material,
+        )));
+
+        let ao = AmbientOcclusion {
+            samples: 16,
+            max_distance: 1e6,
+        };
+        let (point, normal) = (vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0));
+        let sampler = &mut Independent::new(0);
+        assert_ulps_eq!(0.0, ao.visibility(point, normal, 0.0, &scene, sampler));
+        assert_ulps_eq!(1.0, ao.visibility(point, -normal, 0.0, &scene, sampler));
+
+        let far = AmbientOcclusion {
+            samples: 16,
+            max_distance: 0.25,
+        };
+        assert_ulps_eq!(1.0, far.visibility(point, normal, 0.0, &scene, sampler));
+
+        let off = AmbientOcclusion {
+            samples: 0,
+            max_distance: 1.0,
+        };
+        assert_ulps_eq!(1.0, off.visibility(point, normal, 0.0, &scene, sampler));
+    }
+}
@@ -0,0 +1,418 @@
+extern crate std;
+
+use cgmath::{vec3, Vector3};
+use environment::Projection;
+use motion::Transform;
+use sampling::{concentric_disk, uniform_polygon};
+use ray::Ray;
+
+// Distance from the camera to the image plane, which spans [-0.5, 0.5] on
+// both axes
+const IMAGE_PLANE: f64 = 0.5;
+
+// Turns points on the image into rays into the scene. Every camera looks
+// along +Z with +Y up.
+pub trait Camera: Send + Sync {
+    // Ray through a point on the image, with x and y in [-0.5, 0.5] and +Y at
+    // the top, starting from a point on the lens. None for parts of the image
+    // the camera doesn't see anything through.
+    fn ray(&self, x: f64, y: f64, lens: (f64, f64)) -> Option<Ray>;
+
+    // Picks a point on the lens from two uniform random numbers in [0, 1).
+    // Cameras without a lens see everything through a single point.
+    fn sample_lens(&self, _u: f64, _v: f64) -> (f64, f64) {
+        (0.0, 0.0)
+    }
+
+    // Direction from the left eye to the right eye for stereo renders, when
+    // looking along a ray. Flat images keep the eyes level along +X.
+    fn baseline(&self, _direction: Vector3<f64>) -> Vector3<f64> {
+        vec3(1.0, 0.0, 0.0)
+    }
+
+    // Axis that depth is measured along for a ray, so stereo eyes can line up
+    // at a distance in front of the camera. Flat images measure it straight
+    // ahead along +Z.
+    fn forward(&self, _direction: Vector3<f64>) -> Vector3<f64> {
+        vec3(0.0, 0.0, 1.0)
+    }
+}
+
+// Part of the frame that the shutter stays open for, from 0 at the start to 1
+// at the end. Anything that moves while it's open is blurred along its path.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Shutter {
+    pub open: f64,
+    pub close: f64,
+}
+
+impl Shutter {
+    // Open for the first half of the frame, like a film camera's 180 degree
+    // shutter
+    pub fn new() -> Shutter {
+        Shutter {
+            open: 0.0,
+            close: 0.5,
+        }
+    }
+
+    // Picks a moment while the shutter is open from a uniform random number
+    // in [0, 1)
+    pub fn time(&self, u: f64) -> f64 {
+        self.open + (self.close - self.open) * u
+    }
+}
+
+impl Default for Shutter {
+    fn default() -> Shutter {
+        Shutter::new()
+    }
+}
+
+// Pinhole or thin lens perspective camera.
+//
+// With an aperture, rays start from random points on the lens and all pass
+// through the same point on the focus plane, so only things at the focus
+// distance are sharp. Out of focus highlights take the shape of the aperture,
+// which is round unless it has blades.
+pub struct Perspective {
+    pub position: Vector3<f64>,
+
+    // Distance from the camera to the image plane, which sets the field of
+    // view
+    pub image_plane: f64,
+
+    // Width of the image divided by its height. The image plane is stretched
+    // sideways to match, so pixels stay square.
+    pub aspect: f64,
+
+    // Radius of the lens. Zero makes a pinhole camera, with everything in
+    // focus.
+    pub aperture: f64,
+
+    // Distance along +Z to the plane that's in perfect focus
+    pub focus_distance: f64,
+
+    // Number of straight blades forming the aperture, and how far the first
+    // one is rotated in radians. Fewer than three blades is a round aperture.
+    pub blades: u32,
+    pub blade_rotation: f64,
+}
+
+impl Perspective {
+    pub fn new() -> Perspective {
+        Perspective {
+            position: vec3(0.0, 0.0, 0.0),
+            image_plane: IMAGE_PLANE,
+            aspect: 1.0,
+            aperture: 0.0,
+            focus_distance: 1.0,
+            blades: 0,
+            blade_rotation: 0.0,
+        }
+    }
+
+    // Half the height of the view at a distance of 1 in front of the camera
+    pub fn view(&self) -> f64 {
+        0.5 / self.image_plane
+    }
+}
+
+impl Default for Perspective {
+    fn default() -> Perspective {
+        Perspective::new()
+    }
+}
+
+impl Camera for Perspective {
+    // The center of the lens is the pinhole, and lens points are in units of
+    // the aperture radius
+    fn ray(&self, x: f64, y: f64, lens: (f64, f64)) -> Option<Ray> {
+        let direction = vec3(x * self.aspect, y, self.image_plane);
+        if self.aperture <= 0.0 {
+            return Some(Ray::new(self.position, direction).at_screen(x, y));
+        }
+
+        // Where the ray through the pinhole meets the focus plane, which every
+        // other ray through the lens meets too
+        let focus = self.position + direction * (self.focus_distance / self.image_plane);
+        let origin = self.position + vec3(lens.0, lens.1, 0.0) * self.aperture;
+
+        Some(Ray::new(origin, focus - origin).at_screen(x, y))
+    }
+
+    fn sample_lens(&self, u: f64, v: f64) -> (f64, f64) {
+        if self.blades >= 3 {
+            uniform_polygon(self.blades, self.blade_rotation, u, v)
+        } else {
+            concentric_disk(u, v)
+        }
+    }
+}
+
+// Parallel rays along +Z from a rectangle centered on the camera, so things
+// stay the same size however far away they are. Useful for technical
+// drawings.
+pub struct Orthographic {
+    pub position: Vector3<f64>,
+
+    // Size of the rectangle the rays start from
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Camera for Orthographic {
+    fn ray(&self, x: f64, y: f64, _lens: (f64, f64)) -> Option<Ray> {
+        let origin = self.position + vec3(x * self.width, y * self.height, 0.0);
+        Some(Ray::new(origin, vec3(0.0, 0.0, 1.0)).at_screen(x, y))
+    }
+}
+
+// Equidistant fisheye. The angle away from +Z grows evenly with the distance
+// from the center of the image, out to a circle that touches the shorter
+// side. Corners outside of the circle are left empty.
+pub struct Fisheye {
+    pub position: Vector3<f64>,
+
+    // Angle across the circle, in radians. 2 pi sees in every direction.
+    pub field_of_view: f64,
+
+    // Width of the image divided by its height
+    pub aspect: f64,
+}
+
+impl Camera for Fisheye {
+    fn ray(&self, screen_x: f64, screen_y: f64, _lens: (f64, f64)) -> Option<Ray> {
+        let (x, y) = (screen_x * self.aspect.max(1.0), screen_y / self.aspect.min(1.0));
+        let radius = 2.0 * (x * x + y * y).sqrt();
+        if radius > 1.0 {
+            return None;
+        }
+
+        let theta = radius * self.field_of_view / 2.0;
+        let direction = if radius > 0.0 {
+            let scale = 2.0 * theta.sin() / radius;
+            vec3(x * scale, y * scale, theta.cos())
+        } else {
+            vec3(0.0, 0.0, 1.0)
+        };
+        Some(Ray::new(self.position, direction).at_screen(screen_x, screen_y))
+    }
+}
+
+// 360 degree camera that sees the whole scene around it, laid out the same
+// way as environment maps: equirectangular with +Z in the middle, or a cube
+// cross. Renders from it can be loaded back in with --environment.
+pub struct Panorama {
+    pub position: Vector3<f64>,
+    pub projection: Projection,
+}
+
+impl Camera for Panorama {
+    fn ray(&self, x: f64, y: f64, _lens: (f64, f64)) -> Option<Ray> {
+        self.projection
+            .direction(x + 0.5, 0.5 - y)
+            .map(|direction| Ray::new(self.position, direction).at_screen(x, y))
+    }
+
+    // The eyes turn with the view to stay level whichever way it looks
+    // around (omnidirectional stereo). Looking up or down there's no way to
+    // keep them level, so they come together toward the poles instead.
+    fn baseline(&self, direction: Vector3<f64>) -> Vector3<f64> {
+        vec3(direction.z, 0.0, -direction.x)
+    }
+
+    // Depth is measured level with the ground, out from the middle of the
+    // eyes toward the direction
+    fn forward(&self, direction: Vector3<f64>) -> Vector3<f64> {
+        self.baseline(direction).cross(vec3(0.0, 1.0, 0.0))
+    }
+}
+
+
+// Another camera moved and turned into place in the scene, so it can look
+// somewhere other than along +Z. The transform applies to the camera's rays,
+// so a camera offset from the origin orbits around it as the transform turns.
+pub struct Posed {
+    pub camera: Box<Camera>,
+    pub transform: Transform,
+}
+
+impl Camera for Posed {
+    fn ray(&self, x: f64, y: f64, lens: (f64, f64)) -> Option<Ray> {
+        self.camera.ray(x, y, lens).map(|r| {
+            let origin = self.transform.point(r.origin);
+            let mut posed = Ray::new(origin, self.transform.vector(r.direction()));
+            posed.screen = r.screen;
+            posed
+        })
+    }
+
+    fn sample_lens(&self, u: f64, v: f64) -> (f64, f64) {
+        self.camera.sample_lens(u, v)
+    }
+
+    fn baseline(&self, direction: Vector3<f64>) -> Vector3<f64> {
+        let baseline = self.camera.baseline(self.transform.inverse_vector(direction));
+        self.transform.vector(baseline)
+    }
+
+    fn forward(&self, direction: Vector3<f64>) -> Vector3<f64> {
+        let forward = self.camera.forward(self.transform.inverse_vector(direction));
+        self.transform.vector(forward)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    extern crate std;
+
+    use cgmath::{vec3, InnerSpace};
+    use environment::Projection;
+    use motion::Transform;
+    use camera::{Camera, Fisheye, Orthographic, Panorama, Perspective, Posed, Shutter};
+
+    // Tests that a pinhole camera fires every ray from its position
+    #[test]
+    fn pinhole() {
+        let camera = Perspective::new();
+
+        let r = camera.ray(0.25, -0.5, (0.7, -0.2)).unwrap();
+        assert_relative_eq!(vec3(0.0, 0.0, 0.0), r.origin);
+        assert_relative_eq!(vec3(0.25, -0.5, 0.5).normalize(), r.direction(), epsilon = 1e-9);
+
+        // Wide images see further to the sides
+        let wide = Perspective {
+            aspect: 2.0,
+            ..Perspective::new()
+        };
+        let r = wide.ray(0.25, -0.5, (0.0, 0.0)).unwrap();
+        assert_relative_eq!(vec3(0.5, -0.5, 0.5).normalize(), r.direction(), epsilon = 1e-9);
+    }
+
+    // Tests that rays through a pixel spread out from across the lens but
+    // meet again at the focus distance
+    #[test]
+    fn thin_lens() {
+        let camera = Perspective {
+            aperture: 0.1,
+            focus_distance: 3.0,
+            blades: 6,
+            ..Perspective::new()
+        };
+
+        let focus = vec3(0.3, 0.6, 3.0);
+        for i in 0..10 {
+            let lens = camera.sample_lens(i as f64 / 10.0, 0.3);
+            let r = camera.ray(0.05, 0.1, lens).unwrap();
+
+            assert_relative_eq!(0.0, r.origin.z);
+            assert!(r.origin.magnitude() <= 0.1 + 1e-9);
+
+            let distance = (focus - r.origin).magnitude();
+            assert_relative_eq!(focus, r.extend(distance), epsilon = 1e-9);
+        }
+    }
+
+    // Tests that ray times are spread over the part of the frame the shutter
+    // is open
+    #[test]
+    fn shutter() {
+        let shutter = Shutter {
+            open: 0.25,
+            close: 0.75,
+        };
+        assert_relative_eq!(0.25, shutter.time(0.0));
+        assert_relative_eq!(0.5, shutter.time(0.5));
+        assert_relative_eq!(0.75, shutter.time(1.0));
+    }
+
+    // Tests that orthographic rays are parallel and spread over the view
+    #[test]
+    fn orthographic() {
+        let camera = Orthographic {
+            position: vec3(1.0, 0.0, -2.0),
+            width: 4.0,
+            height: 2.0,
+        };
+
+        let r = camera.ray(0.5, -0.25, (0.0, 0.0)).unwrap();
+        assert_relative_eq!(vec3(3.0, -0.5, -2.0), r.origin);
+        assert_relative_eq!(vec3(0.0, 0.0, 1.0), r.direction());
+    }
+
+    // Tests that fisheye angles grow evenly out to the edge of the circle
+    #[test]
+    fn fisheye() {
+        let camera = Fisheye {
+            position: vec3(0.0, 0.0, 0.0),
+            field_of_view: std::f64::consts::PI,
+            aspect: 1.0,
+        };
+
+        let center = camera.ray(0.0, 0.0, (0.0, 0.0)).unwrap();
+        assert_relative_eq!(vec3(0.0, 0.0, 1.0), center.direction());
+
+        let edge = camera.ray(0.0, 0.5, (0.0, 0.0)).unwrap();
+        assert_relative_eq!(vec3(0.0, 1.0, 0.0), edge.direction(), epsilon = 1e-9);
+
+        let halfway = camera.ray(-0.25, 0.0, (0.0, 0.0)).unwrap();
+        assert_relative_eq!(vec3(-1.0, 0.0, 1.0).normalize(), halfway.direction(), epsilon = 1e-9);
+
+        assert!(camera.ray(0.45, 0.45, (0.0, 0.0)).is_none());
+    }
+
+    // Tests that panoramas look the same way environment maps are laid out
+    #[test]
+    fn panorama() {
+        let camera = Panorama {
+            position: vec3(0.0, 1.0, 0.0),
+            projection: Projection::Equirectangular,
+        };
+
+        let r = camera.ray(0.0, 0.0, (0.0, 0.0)).unwrap();
+        assert_relative_eq!(vec3(0.0, 1.0, 0.0), r.origin);
+        assert_relative_eq!(vec3(0.0, 0.0, 1.0), r.direction(), epsilon = 1e-9);
+
+        let r = camera.ray(0.25, 0.0, (0.0, 0.0)).unwrap();
+        assert_relative_eq!(vec3(1.0, 0.0, 0.0), r.direction(), epsilon = 1e-9);
+
+        let r = camera.ray(0.1, 0.5, (0.0, 0.0)).unwrap();
+        assert_relative_eq!(vec3(0.0, 1.0, 0.0), r.direction(), epsilon = 1e-6);
+
+        let cube = Panorama {
+            position: vec3(0.0, 0.0, 0.0),
+            projection: Projection::Cube,
+        };
+
+        // Middle of the +X face, right of the +Z face in the cross
+        let r = cube.ray(0.125, 0.0, (0.0, 0.0)).unwrap();
+        assert_relative_eq!(vec3(1.0, 0.0, 0.0), r.direction(), epsilon = 1e-9);
+
+        // The corners of the cross are empty
+        assert!(cube.ray(-0.45, 0.45, (0.0, 0.0)).is_none());
+    }
+
+    // Tests that a posed camera orbits around the origin as it turns
+    #[test]
+    fn posed() {
+        let camera = Posed {
+            camera: Box::new(Perspective {
+                position: vec3(0.0, 0.0, -2.0),
+                ..Perspective::new()
+            }),
+            transform: Transform::rotation(vec3(0.0, 1.0, 0.0), 90.0),
+        };
+
+        // Turned to look along +X from the -X side, still at the origin
+        let r = camera.ray(0.0, 0.0, (0.0, 0.0)).unwrap();
+        assert_relative_eq!(vec3(-2.0, 0.0, 0.0), r.origin, epsilon = 1e-9);
+        assert_relative_eq!(vec3(1.0, 0.0, 0.0), r.direction(), epsilon = 1e-9);
+        assert_relative_eq!(vec3(0.0, 0.0, -1.0), camera.baseline(r.direction()), epsilon = 1e-9);
+
+        // Rays still know where on the image they came from
+        let r = camera.ray(0.25, -0.1, (0.0, 0.0)).unwrap();
+        assert_eq!(Some((0.25, -0.1)), r.screen);
+    }
+}
@@ -0,0 +1,104 @@
+use std::ops::{Add, AddAssign, Div, Mul};
+use light::Rgb;
+
+// Linear color with floating point channels. Unlike Rgb, channels aren't
+// clamped, so light from many samples can be summed up and averaged before
+// it's converted back to a displayable color. 1.0 matches a full 255 channel.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Color {
+    pub r: f64,
+    pub g: f64,
+    pub b: f64,
+}
+
+impl Color {
+    pub fn new(r: f64, g: f64, b: f64) -> Color {
+        Color { r, g, b }
+    }
+
+    pub fn black() -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    // Converts an 8-bit color into the [0, 1] range
+    pub fn from_rgb(rgb: &Rgb) -> Color {
+        let channel = |i: usize| rgb.color.data[i] as f64 / 255.0;
+        Color::new(channel(0), channel(1), channel(2))
+    }
+
+    // Converts back to an 8-bit color, clamping anything too bright
+    pub fn to_rgb(&self) -> Rgb {
+        let channel = |c: f64| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+        Rgb::new([channel(self.r), channel(self.g), channel(self.b)])
+    }
+
+    // Brightness as perceived by the eye (Rec. 709 weights)
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
+    pub fn max_channel(&self) -> f64 {
+        self.r.max(self.g).max(self.b)
+    }
+
+    pub fn is_black(&self) -> bool {
+        self.r <= 0.0 && self.g <= 0.0 && self.b <= 0.0
+    }
+}
+
+impl Add for Color {
+    type Output = Color;
+    fn add(self, rhs: Color) -> Color {
+        Color::new(self.r + rhs.r, self.g + rhs.g, self.b + rhs.b)
+    }
+}
+
+impl AddAssign for Color {
+    fn add_assign(&mut self, rhs: Color) {
+        *self = *self + rhs;
+    }
+}
+
+impl Mul for Color {
+    type Output = Color;
+    fn mul(self, rhs: Color) -> Color {
+        Color::new(self.r * rhs.r, self.g * rhs.g, self.b * rhs.b)
+    }
+}
+
+impl Mul<f64> for Color {
+    type Output = Color;
+    fn mul(self, rhs: f64) -> Color {
+        Color::new(self.r * rhs, self.g * rhs, self.b * rhs)
+    }
+}
+
+impl Div<f64> for Color {
+    type Output = Color;
+    fn div(self, rhs: f64) -> Color {
+        self * (1.0 / rhs)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use color::Color;
+    use light::Rgb;
+
+    // Tests that 8-bit colors survive the round trip
+    #[test]
+    fn rgb_round_trip() {
+        let rgb = Rgb::new([0, 128, 255]);
+        assert_eq!(rgb, Color::from_rgb(&rgb).to_rgb());
+    }
+
+    // Tests that colors brighter than white are clamped when converted
+    #[test]
+    fn to_rgb_clamps() {
+        let color = Color::new(2.0, -1.0, 0.5) + Color::new(0.0, 0.0, 0.25);
+        assert_eq!(Rgb::new([255, 0, 191]), color.to_rgb());
+        assert_eq!(Rgb::new([128, 0, 48]), (color / 4.0).to_rgb());
+    }
+}
@@ -0,0 +1,232 @@
+extern crate std;
+
+use std::cmp::Ordering;
+use cgmath::{dot, vec2, InnerSpace, Vector3};
+use tracer::{orthonormal_basis, pair_intervals, Intersect, Interval, Shape};
+use disk::Disk;
+use ray::Ray;
+use light::Material;
+
+// Right circular cone narrowing from a round base to a point at the apex.
+// Capped cones have a disk closing off the base.
+pub struct Cone {
+    pub base: Vector3<f64>,
+    pub apex: Vector3<f64>,
+    pub r: f64,
+    pub color: Material,
+
+    // Unit vector from the apex toward the base
+    axis: Vector3<f64>,
+    height: f64,
+
+    // Ratio of the radius to the height, squared and offset by one
+    slope: f64,
+    u: Vector3<f64>,
+    v: Vector3<f64>,
+    cap: Option<Disk>,
+}
+
+impl Cone {
+    /// Creates a cone with an open base
+    pub fn new(base: Vector3<f64>, apex: Vector3<f64>, r: f64, color: Material) -> Cone {
+        let height = (base - apex).magnitude();
+        let axis = (base - apex) / height;
+        let (u, v) = orthonormal_basis(axis);
+        let k = r / height;
+
+        Cone {
+            base,
+            apex,
+            r,
+            color,
+            axis,
+            height,
+            slope: 1.0 + k * k,
+            u,
+            v,
+            cap: None,
+        }
+    }
+
+    /// Creates a solid cone with a disk closing the base
+    pub fn capped(base: Vector3<f64>, apex: Vector3<f64>, r: f64, color: Material) -> Cone {
+        let mut cone = Cone::new(base, apex, r, color);
+        cone.cap = Some(Disk::new(base, cone.axis, r, cone.color.clone()));
+        cone
+    }
+
+    // Finds where the ray's line crosses the sloped side of the cone, even
+    // behind the origin of the ray
+    fn side_crossings(&self, ray: &Ray) -> Vec<Intersect> {
+        // A point W relative to the apex is on the cone when its distance from
+        // the axis is k times its distance along the axis:
+        // |W|^2 - (1 + k^2) * (W . A)^2 = 0
+        // Substituting W = O + tD gives a quadratic in t.
+        let d = ray.direction();
+        let o = ray.origin - self.apex;
+        let d_a = dot(d, self.axis);
+        let o_a = dot(o, self.axis);
+
+        let a = dot(d, d) - self.slope * d_a * d_a;
+        let b = 2.0 * (dot(d, o) - self.slope * d_a * o_a);
+        let c = dot(o, o) - self.slope * o_a * o_a;
+
+        // A ray parallel to the side of the cone only crosses it once
+        let crossings = if a.abs() < 1e-12 {
+            if b.abs() < 1e-12 {
+                return vec![];
+            }
+            vec![-c / b]
+        } else {
+            let discriminant = b * b - 4.0 * a * c;
+            if discriminant < 0.0 {
+                return vec![];
+            }
+
+            let root = discriminant.sqrt();
+            let t1 = (-b - root) / (2.0 * a);
+            let t2 = (-b + root) / (2.0 * a);
+            vec![f64::min(t1, t2), f64::max(t1, t2)]
+        };
+
+        // The quadratic also describes the mirrored cone past the apex, which
+        // the height check filters out.
+        crossings
+            .into_iter()
+            .filter_map(|distance| {
+                let point = ray.extend(distance);
+                let w = point - self.apex;
+                let h = dot(w, self.axis);
+
+                if h < 0.0 || h > self.height {
+                    return None;
+                }
+
+                // Gradient of the cone equation points straight out of the side
+                let gradient = w - self.axis * (self.slope * h);
+                let normal = if gradient.magnitude2() > 0.0 {
+                    gradient.normalize()
+                } else {
+                    -self.axis
+                };
+
+                let angle = dot(w, self.v).atan2(dot(w, self.u));
+
+                Some(Intersect {
+                    distance,
+                    point,
+                    normal,
+                    uv: vec2(
+                        0.5 + angle / (2.0 * std::f64::consts::PI),
+                        1.0 - h / self.height,
+                    ),
+                    color: &self.color,
+                    shape: None,
+                })
+            })
+            .collect()
+    }
+
+    // Finds where the ray's line crosses the base, if it's capped
+    fn cap_crossing(&self, ray: &Ray) -> Option<Intersect> {
+        self.cap
+            .as_ref()
+            .and_then(|cap| cap.crossing(ray))
+            // The cap has its own copy of the material; report the cone's
+            .map(|intersect| Intersect {
+                color: &self.color,
+                ..intersect
+            })
+    }
+}
+
+impl Shape for Cone {
+    /// Intersects the side as a double cone clipped between the apex and the
+    /// base, then checks the cap and keeps whichever is closest.
+    fn intersect(&self, ray: &Ray) -> Option<Intersect> {
+        self.cap_crossing(ray)
+            .into_iter()
+            .chain(self.side_crossings(ray))
+            .filter(|intersect| ray.contains(intersect.distance))
+            .min_by(|first, second| {
+                first
+                    .distance
+                    .partial_cmp(&second.distance)
+                    .unwrap_or(Ordering::Equal)
+            })
+    }
+
+    // Only a capped cone is a closed solid
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        if self.cap.is_none() {
+            return vec![];
+        }
+
+        let mut crossings = self.side_crossings(ray);
+        crossings.extend(self.cap_crossing(ray));
+        pair_intervals(crossings)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use cgmath::{vec3, InnerSpace};
+    use tracer::Shape;
+    use cone::Cone;
+    use ray::Ray;
+    use light::{Material, Rgb};
+
+    // Tests collisions with the side of a cone pointing up the Y axis
+    #[test]
+    fn intersect() {
+        let color = Rgb::new([255, 255, 0]);
+
+        let cone = Cone::new(
+            vec3(0.0, -0.5, 2.0),
+            vec3(0.0, 0.5, 2.0),
+            0.5,
+            Material::new(color.clone(), (1.0, 1.0, 1.0), 0.0, 0.0, 0.0),
+        );
+
+        // Halfway up, the cone is a quarter unit wide
+        let r = Ray::new(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0));
+        let intersect = cone.intersect(&r).expect("Ray should intersect with cone");
+        assert_eq!(&color, intersect.color.diffuse());
+        assert_relative_eq!(1.75, intersect.distance, epsilon = 1e-9);
+        assert_relative_eq!(0.5, intersect.uv.y, epsilon = 1e-9);
+
+        // The normal leans up toward the apex
+        let expected = vec3(0.0, 0.5, -1.0).normalize();
+        assert_relative_eq!(expected, intersect.normal, epsilon = 1e-9);
+
+        // Above the apex, where the mirrored cone would be
+        let r = Ray::new(vec3(0.0, 1.0, 0.0), vec3(0.0, 0.0, 1.0));
+        assert!(cone.intersect(&r).is_none());
+    }
+
+    // Tests that looking up into the base hits the cap only if it exists
+    #[test]
+    fn intersect_capped() {
+        let color = Rgb::new([255, 255, 0]);
+        let material = Material::new(color.clone(), (1.0, 1.0, 1.0), 0.0, 0.0, 0.0);
+
+        let open = Cone::new(vec3(0.0, 0.0, 1.0), vec3(0.0, 0.0, 2.0), 0.5, material.clone());
+        let capped = Cone::capped(vec3(0.0, 0.0, 1.0), vec3(0.0, 0.0, 2.0), 0.5, material);
+
+        // Without a cap the ray hits the inside of the cone near the apex
+        let r = Ray::new(vec3(0.0, 0.1, 0.0), vec3(0.0, 0.0, 1.0));
+        let intersect = open
+            .intersect(&r)
+            .expect("Ray should intersect with the inside of the cone");
+        assert_relative_eq!(1.8, intersect.distance, epsilon = 1e-9);
+
+        let intersect = capped
+            .intersect(&r)
+            .expect("Ray should intersect with the cap");
+        assert_ulps_eq!(1.0, intersect.distance);
+        assert_ulps_eq!(vec3(0.0, 0.0, -1.0), intersect.normal);
+        assert!(::std::ptr::eq(&capped.color, intersect.color));
+    }
+}
@@ -0,0 +1,220 @@
+use std::cmp::Ordering;
+use tracer::{Intersect, Interval, Shape};
+use ray::Ray;
+
+// Boolean operations for combining two solid shapes
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Operation {
+    // Inside either shape
+    Union,
+
+    // Inside both shapes
+    Intersection,
+
+    // Inside the left shape, but not the right
+    Difference,
+}
+
+impl Operation {
+    // Checks whether a point is inside the combined shape, given whether it's
+    // inside each of the two original shapes
+    fn contains(&self, in_left: bool, in_right: bool) -> bool {
+        match *self {
+            Operation::Union => in_left || in_right,
+            Operation::Intersection => in_left && in_right,
+            Operation::Difference => in_left && !in_right,
+        }
+    }
+}
+
+// Constructive solid geometry node that combines two shapes. Either shape can
+// be another CSG node, building up a tree of operations. Only shapes that
+// enclose a volume can be combined; flat shapes have no inside.
+pub struct Csg {
+    pub operation: Operation,
+    left: Box<Shape>,
+    right: Box<Shape>,
+}
+
+impl Csg {
+    pub fn new(operation: Operation, left: Box<Shape>, right: Box<Shape>) -> Csg {
+        Csg {
+            operation,
+            left,
+            right,
+        }
+    }
+
+    /// Creates a shape covering both shapes
+    pub fn union(left: Box<Shape>, right: Box<Shape>) -> Csg {
+        Csg::new(Operation::Union, left, right)
+    }
+
+    /// Creates a shape covering only the overlap of the two shapes
+    pub fn intersection(left: Box<Shape>, right: Box<Shape>) -> Csg {
+        Csg::new(Operation::Intersection, left, right)
+    }
+
+    /// Creates a shape by cutting the right shape out of the left shape
+    pub fn difference(left: Box<Shape>, right: Box<Shape>) -> Csg {
+        Csg::new(Operation::Difference, left, right)
+    }
+}
+
+impl Shape for Csg {
+    fn intersect(&self, ray: &Ray) -> Option<Intersect> {
+        // Intervals are sorted, so the first boundary in front of the ray is
+        // the closest. The origin may already be inside an interval.
+        self.intervals(ray)
+            .into_iter()
+            .flat_map(|interval| vec![interval.enter, interval.exit])
+            .find(|intersect| ray.contains(intersect.distance))
+    }
+
+    /// Walks along the ray through every boundary of both shapes in order,
+    /// tracking whether the ray is inside each one. Boundaries where the
+    /// combined result switches between inside and outside become the new
+    /// intervals.
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        // (boundary, from the left shape, entering the shape)
+        let mut boundaries: Vec<(Intersect, bool, bool)> = vec![];
+        for (shape, is_left) in vec![(&self.left, true), (&self.right, false)] {
+            for interval in shape.intervals(ray) {
+                boundaries.push((interval.enter, is_left, true));
+                boundaries.push((interval.exit, is_left, false));
+            }
+        }
+
+        boundaries.sort_by(|first, second| {
+            first.0
+                .distance
+                .partial_cmp(&second.0.distance)
+                .unwrap_or(Ordering::Equal)
+        });
+
+        let (mut in_left, mut in_right) = (false, false);
+        let mut enter: Option<Intersect> = None;
+        let mut intervals = vec![];
+
+        for (boundary, is_left, entering) in boundaries {
+            let was_inside = self.operation.contains(in_left, in_right);
+
+            if is_left {
+                in_left = entering;
+            } else {
+                in_right = entering;
+            }
+
+            let inside = self.operation.contains(in_left, in_right);
+            if inside == was_inside {
+                continue;
+            }
+
+            // Surfaces of the cut-out shape face into it, so they need to be
+            // flipped to face out of what's left behind
+            let flip = !is_left && self.operation == Operation::Difference;
+            let boundary = Intersect {
+                normal: if flip { -boundary.normal } else { boundary.normal },
+                ..boundary
+            };
+
+            if inside {
+                enter = Some(boundary);
+            } else if let Some(enter) = enter.take() {
+                intervals.push(Interval {
+                    enter,
+                    exit: boundary,
+                });
+            }
+        }
+
+        intervals
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use cgmath::vec3;
+    use tracer::Shape;
+    use csg::Csg;
+    use sphere::Sphere;
+    use ray::Ray;
+    use light::{Material, Rgb};
+
+    // Two unit spheres along the Z axis, overlapping from 3.5 to 4.0
+    fn spheres() -> (Box<Shape>, Box<Shape>) {
+        (
+            Box::new(Sphere::new(
+                vec3(0.0, 0.0, 3.0),
+                1.0,
+                Material::new(Rgb::new([255, 0, 0]), (1.0, 1.0, 1.0), 0.0, 0.0, 0.0),
+            )),
+            Box::new(Sphere::new(
+                vec3(0.0, 0.0, 4.5),
+                1.0,
+                Material::new(Rgb::new([0, 255, 0]), (1.0, 1.0, 1.0), 0.0, 0.0, 0.0),
+            )),
+        )
+    }
+
+    // Tests that a union spans both shapes
+    #[test]
+    fn union() {
+        let (a, b) = spheres();
+        let union = Csg::union(a, b);
+
+        let r = Ray::new(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0));
+        let intervals = union.intervals(&r);
+        assert_eq!(1, intervals.len());
+        assert_ulps_eq!(2.0, intervals[0].enter.distance);
+        assert_ulps_eq!(5.5, intervals[0].exit.distance);
+
+        let intersect = union.intersect(&r).expect("Ray should hit the union");
+        assert_ulps_eq!(2.0, intersect.distance);
+        assert_eq!(&Rgb::new([255, 0, 0]), intersect.color.diffuse());
+    }
+
+    // Tests that an intersection only covers the overlap
+    #[test]
+    fn intersection() {
+        let (a, b) = spheres();
+        let intersection = Csg::intersection(a, b);
+
+        let r = Ray::new(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0));
+        let intervals = intersection.intervals(&r);
+        assert_eq!(1, intervals.len());
+        assert_ulps_eq!(3.5, intervals[0].enter.distance);
+        assert_ulps_eq!(4.0, intervals[0].exit.distance);
+
+        // Off to the side, the spheres don't overlap
+        let r = Ray::new(vec3(0.0, 0.9, 0.0), vec3(0.0, 0.0, 1.0));
+        assert!(intersection.intersect(&r).is_none());
+    }
+
+    // Tests that a difference exposes the inside of the cut, facing outward
+    #[test]
+    fn difference() {
+        let (a, b) = spheres();
+        let difference = Csg::difference(b, a);
+
+        let r = Ray::new(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0));
+        let intersect = difference
+            .intersect(&r)
+            .expect("Ray should hit the inside of the cut");
+        assert_ulps_eq!(4.0, intersect.distance);
+        assert_ulps_eq!(vec3(0.0, 0.0, -1.0), intersect.normal);
+
+        // The cut surface takes the material of the shape that made it
+        assert_eq!(&Rgb::new([255, 0, 0]), intersect.color.diffuse());
+
+        // Starting inside what's left, the ray leaves through the far side
+        let r = Ray::new(vec3(0.0, 0.0, 5.0), vec3(0.0, 0.0, 1.0));
+        let intersect = difference
+            .intersect(&r)
+            .expect("Ray should leave through the far side");
+        assert_ulps_eq!(0.5, intersect.distance);
+        assert_ulps_eq!(vec3(0.0, 0.0, 1.0), intersect.normal);
+    }
+}
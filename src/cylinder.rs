@@ -0,0 +1,236 @@
+extern crate std;
+
+use std::cmp::Ordering;
+use cgmath::{dot, vec2, InnerSpace, Vector3};
+use tracer::{orthonormal_basis, pair_intervals, Intersect, Interval, Shape};
+use disk::Disk;
+use ray::Ray;
+use light::Material;
+
+// Circular tube running from the base to the top. Capped cylinders are closed
+// off at both ends by disks; uncapped cylinders are open tubes.
+pub struct Cylinder {
+    pub base: Vector3<f64>,
+    pub axis: Vector3<f64>,
+    pub height: f64,
+    pub r: f64,
+    pub color: Material,
+    u: Vector3<f64>,
+    v: Vector3<f64>,
+    caps: Option<(Disk, Disk)>,
+}
+
+impl Cylinder {
+    /// Creates an open tube between the two end points
+    pub fn new(base: Vector3<f64>, top: Vector3<f64>, r: f64, color: Material) -> Cylinder {
+        let axis = (top - base).normalize();
+        let (u, v) = orthonormal_basis(axis);
+
+        Cylinder {
+            base,
+            axis,
+            height: (top - base).magnitude(),
+            r,
+            color,
+            u,
+            v,
+            caps: None,
+        }
+    }
+
+    /// Creates a solid cylinder with disks closing both ends
+    pub fn capped(base: Vector3<f64>, top: Vector3<f64>, r: f64, color: Material) -> Cylinder {
+        let mut cylinder = Cylinder::new(base, top, r, color);
+        cylinder.caps = Some((
+            Disk::new(base, -cylinder.axis, r, cylinder.color.clone()),
+            Disk::new(top, cylinder.axis, r, cylinder.color.clone()),
+        ));
+        cylinder
+    }
+
+    // Finds where the ray's line crosses the curved side of the cylinder, even
+    // behind the origin of the ray
+    fn side_crossings(&self, ray: &Ray) -> Vec<Intersect> {
+        // Remove the components along the axis, which reduces the problem to
+        // intersecting a circle in the plane perpendicular to the axis.
+        // a = |D_perp|^2, B = 2 * (D_perp . O_perp), C = |O_perp|^2 - r^2
+        let offset = ray.origin - self.base;
+        let d = ray.direction() - self.axis * dot(ray.direction(), self.axis);
+        let o = offset - self.axis * dot(offset, self.axis);
+
+        let a = dot(d, d);
+
+        // Rays parallel to the axis never touch the side
+        if a < 1e-12 {
+            return vec![];
+        }
+
+        let b = 2.0 * dot(d, o);
+        let c = dot(o, o) - self.r * self.r;
+
+        let discriminant = b * b - 4.0 * a * c;
+        if discriminant < 0.0 {
+            return vec![];
+        }
+
+        let root = discriminant.sqrt();
+        let near = (-b - root) / (2.0 * a);
+        let far = (-b + root) / (2.0 * a);
+
+        // Either crossing may be above or below the tube, so check both
+        [near, far]
+            .iter()
+            .filter_map(|&distance| {
+                let point = ray.extend(distance);
+                let h = dot(point - self.base, self.axis);
+
+                if h < 0.0 || h > self.height {
+                    return None;
+                }
+
+                let radial = point - self.base - self.axis * h;
+                let angle = dot(radial, self.v).atan2(dot(radial, self.u));
+
+                Some(Intersect {
+                    distance,
+                    point,
+                    normal: radial.normalize(),
+                    uv: vec2(
+                        0.5 + angle / (2.0 * std::f64::consts::PI),
+                        h / self.height,
+                    ),
+                    color: &self.color,
+                    shape: None,
+                })
+            })
+            .collect()
+    }
+
+    // Finds where the ray's line crosses the end caps, if there are any
+    fn cap_crossings(&self, ray: &Ray) -> Vec<Intersect> {
+        self.caps
+            .as_ref()
+            .map_or(vec![], |&(ref bottom, ref top)| {
+                vec![bottom.crossing(ray), top.crossing(ray)]
+            })
+            .into_iter()
+            .filter_map(|c| c)
+            // The caps have their own copies of the material; report the cylinder's
+            .map(|intersect| Intersect {
+                color: &self.color,
+                ..intersect
+            })
+            .collect()
+    }
+}
+
+impl Shape for Cylinder {
+    /// Intersects the side as an infinite cylinder clipped to the height, then
+    /// checks the caps and keeps whichever is closest.
+    fn intersect(&self, ray: &Ray) -> Option<Intersect> {
+        self.cap_crossings(ray)
+            .into_iter()
+            .chain(self.side_crossings(ray))
+            .filter(|intersect| ray.contains(intersect.distance))
+            .min_by(|first, second| {
+                first
+                    .distance
+                    .partial_cmp(&second.distance)
+                    .unwrap_or(Ordering::Equal)
+            })
+    }
+
+    // Only a capped cylinder is a closed solid
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        if self.caps.is_none() {
+            return vec![];
+        }
+
+        let mut crossings = self.cap_crossings(ray);
+        crossings.extend(self.side_crossings(ray));
+        pair_intervals(crossings)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use cgmath::vec3;
+    use tracer::Shape;
+    use cylinder::Cylinder;
+    use ray::Ray;
+    use light::{Material, Rgb};
+
+    // Tests collisions with the side of a cylinder standing along the Y axis
+    #[test]
+    fn intersect() {
+        let color = Rgb::new([255, 255, 0]);
+
+        let cylinder = Cylinder::new(
+            vec3(0.0, -0.5, 2.0),
+            vec3(0.0, 0.5, 2.0),
+            0.5,
+            Material::new(color.clone(), (1.0, 1.0, 1.0), 0.0, 0.0, 0.0),
+        );
+
+        let r = Ray::new(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0));
+        let intersect = cylinder
+            .intersect(&r)
+            .expect("Ray should intersect with cylinder");
+        assert_eq!(&color, intersect.color.diffuse());
+        assert_ulps_eq!(1.5, intersect.distance);
+        assert_ulps_eq!(vec3(0.0, 0.0, -1.0), intersect.normal);
+        assert_ulps_eq!(0.5, intersect.uv.y);
+
+        // Above the top of the tube
+        let r = Ray::new(vec3(0.0, 1.0, 0.0), vec3(0.0, 0.0, 1.0));
+        assert!(cylinder.intersect(&r).is_none());
+    }
+
+    // Tests that an open tube can be seen through from the end, but a capped one
+    // can't
+    #[test]
+    fn intersect_capped() {
+        let color = Rgb::new([255, 255, 0]);
+        let material = Material::new(color.clone(), (1.0, 1.0, 1.0), 0.0, 0.0, 0.0);
+
+        let open = Cylinder::new(vec3(0.0, 0.0, 1.0), vec3(0.0, 0.0, 2.0), 0.5, material.clone());
+        let capped = Cylinder::capped(vec3(0.0, 0.0, 1.0), vec3(0.0, 0.0, 2.0), 0.5, material);
+
+        let r = Ray::new(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0));
+        assert!(open.intersect(&r).is_none());
+
+        let intersect = capped
+            .intersect(&r)
+            .expect("Ray should intersect with the cap");
+        assert_ulps_eq!(1.0, intersect.distance);
+        assert_ulps_eq!(vec3(0.0, 0.0, -1.0), intersect.normal);
+        assert!(::std::ptr::eq(&capped.color, intersect.color));
+
+        // Looking in from the open end at an angle hits the inside of the tube
+        let r = Ray::new(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.4, 1.0));
+        let intersect = open
+            .intersect(&r)
+            .expect("Ray should intersect with the inside of the tube");
+        assert_ulps_eq!(0.5, intersect.point.y);
+    }
+
+    // Tests that only a capped cylinder has an inside
+    #[test]
+    fn intervals() {
+        let material = Material::new(Rgb::new([255, 255, 0]), (1.0, 1.0, 1.0), 0.0, 0.0, 0.0);
+
+        let open = Cylinder::new(vec3(0.0, 0.0, 1.0), vec3(0.0, 0.0, 2.0), 0.5, material.clone());
+        let capped = Cylinder::capped(vec3(0.0, 0.0, 1.0), vec3(0.0, 0.0, 2.0), 0.5, material);
+
+        let r = Ray::new(vec3(0.0, 0.25, 0.0), vec3(0.0, 0.0, 1.0));
+        assert!(open.intervals(&r).is_empty());
+
+        let intervals = capped.intervals(&r);
+        assert_eq!(1, intervals.len());
+        assert_ulps_eq!(1.0, intervals[0].enter.distance);
+        assert_ulps_eq!(2.0, intervals[0].exit.distance);
+        assert_ulps_eq!(vec3(0.0, 0.0, 1.0), intervals[0].exit.normal);
+    }
+}
@@ -0,0 +1,58 @@
+use integrator::Integrator;
+use ray::Ray;
+use scene::Scene;
+use light::Rgb;
+use sampler::Sampler;
+
+// Debug integrator that colors each surface by its normal, mapping each axis
+// from [-1, 1] to a color channel. Misses are black.
+pub struct Normals;
+
+impl Integrator for Normals {
+    fn radiance(&self, ray: &Ray, scene: &Scene, _sampler: &mut Sampler) -> Rgb {
+        match scene.intersect(ray) {
+            Some(intersect) => {
+                let n = intersect.normal;
+                let channel = |c: f64| ((c + 1.0) * 0.5 * 255.0).round() as u8;
+                Rgb::new([channel(n.x), channel(n.y), channel(n.z)])
+            }
+            None => Rgb::new([0, 0, 0]),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use cgmath::vec3;
+    use integrator::Integrator;
+    use debug::Normals;
+    use scene::Scene;
+    use sphere::Sphere;
+    use tracer::Background;
+    use ray::Ray;
+    use light::{Material, Rgb};
+    use sampler::Independent;
+
+    // Tests that a normal pointing back at the camera has no blue, and that
+    // misses are black
+    #[test]
+    fn radiance() {
+        let mut scene = Scene::new(Background {
+            color: Rgb::new([255, 255, 255]),
+        });
+        scene.add(Box::new(Sphere::new(
+            vec3(0.0, 0.0, 2.0),
+            0.5,
+            Material::new(Rgb::new([255, 0, 0]), (1.0, 1.0, 1.0), 0.0, 0.0, 0.0),
+        )));
+
+        let sampler = &mut Independent::new(0);
+        let r = Ray::new(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0));
+        assert_eq!(Rgb::new([128, 128, 0]), Normals.radiance(&r, &scene, sampler));
+
+        let r = Ray::new(vec3(0.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0));
+        assert_eq!(Rgb::new([0, 0, 0]), Normals.radiance(&r, &scene, sampler));
+    }
+}
@@ -0,0 +1,212 @@
+extern crate std;
+
+use cgmath::{dot, vec2, InnerSpace, Vector3};
+use tracer::{orthonormal_basis, Intersect, Shape};
+use ray::Ray;
+use light::Material;
+
+// Flat circle facing along its normal. An inner radius above zero cuts a hole
+// out of the middle, turning the disk into an annulus.
+pub struct Disk {
+    pub center: Vector3<f64>,
+    pub normal: Vector3<f64>,
+    pub inner_r: f64,
+    pub r: f64,
+    pub color: Material,
+    u: Vector3<f64>,
+    v: Vector3<f64>,
+}
+
+impl Disk {
+    pub fn new(center: Vector3<f64>, normal: Vector3<f64>, r: f64, color: Material) -> Disk {
+        Disk::annulus(center, normal, 0.0, r, color)
+    }
+
+    /// Creates a ring between the inner and outer radius
+    pub fn annulus(
+        center: Vector3<f64>,
+        normal: Vector3<f64>,
+        inner_r: f64,
+        r: f64,
+        color: Material,
+    ) -> Disk {
+        let normal = normal.normalize();
+        let (u, v) = orthonormal_basis(normal);
+
+        Disk {
+            center,
+            normal,
+            inner_r,
+            r,
+            color,
+            u,
+            v,
+        }
+    }
+
+    /// Same plane intersection as the floor, but the bounds check is the
+    /// distance from the center instead of the corners.
+    /// \omega = ((C - P_o) . P_n) / (P_n . D)
+    ///
+    /// Finds where the ray's line crosses the disk, even if it's behind the
+    /// origin of the ray.
+    pub fn crossing(&self, ray: &Ray) -> Option<Intersect> {
+        let denominator = dot(self.normal, ray.direction());
+
+        // Rays parallel to the disk never cross it
+        if denominator.abs() < 1e-12 {
+            return None;
+        }
+
+        let distance = dot(self.center - ray.origin, self.normal) / denominator;
+        let point = ray.extend(distance);
+        let offset = point - self.center;
+        let radius = offset.magnitude();
+
+        if radius < self.inner_r || radius > self.r {
+            return None;
+        }
+
+        // Angle around the center and distance out from the inner edge
+        let angle = dot(offset, self.v).atan2(dot(offset, self.u));
+        let uv = vec2(
+            0.5 + angle / (2.0 * std::f64::consts::PI),
+            self.ring_position(radius),
+        );
+
+        Some(Intersect {
+            distance,
+            point,
+            normal: self.normal,
+            uv,
+            color: &self.color,
+            shape: None,
+        })
+    }
+
+    // How far out from the inner edge to the outer one a radius is, from 0 to
+    // 1. Rings with no width are all at the inner edge.
+    fn ring_position(&self, radius: f64) -> f64 {
+        let width = self.r - self.inner_r;
+        if width > 0.0 {
+            (radius - self.inner_r) / width
+        } else {
+            0.0
+        }
+    }
+}
+
+impl Shape for Disk {
+    fn intersect(&self, ray: &Ray) -> Option<Intersect> {
+        self.crossing(ray)
+            .filter(|intersect| ray.contains(intersect.distance))
+    }
+
+    fn area(&self) -> f64 {
+        std::f64::consts::PI * (self.r * self.r - self.inner_r * self.inner_r)
+    }
+
+    // Squaring the radius before spreading it out keeps the outer rings, which
+    // are longer, from being sampled as sparsely as the inner ones
+    fn sample(&self, u: f64, v: f64) -> Option<Intersect> {
+        let inner2 = self.inner_r * self.inner_r;
+        let radius = (inner2 + u * (self.r * self.r - inner2)).sqrt();
+        let angle = 2.0 * std::f64::consts::PI * v;
+        let point = self.center + (self.u * angle.cos() + self.v * angle.sin()) * radius;
+
+        Some(Intersect {
+            distance: 0.0,
+            point,
+            normal: self.normal,
+            uv: vec2(v, self.ring_position(radius)),
+            color: &self.color,
+            shape: None,
+        })
+    }
+
+    fn emits(&self) -> bool {
+        !self.color.emission().is_black()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use cgmath::vec3;
+    use tracer::Shape;
+    use disk::Disk;
+    use ray::Ray;
+    use light::{Material, Rgb};
+
+    // Tests collisions with a disk facing the ray
+    #[test]
+    fn intersect() {
+        let color = Rgb::new([255, 255, 0]);
+
+        let disk = Disk::new(
+            vec3(0.0, 0.0, 1.0),
+            vec3(0.0, 0.0, -1.0),
+            0.5,
+            Material::new(color.clone(), (1.0, 1.0, 1.0), 0.0, 0.0, 0.0),
+        );
+
+        let r = Ray::new(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0));
+        let intersect = disk.intersect(&r).expect("Ray should intersect with disk");
+        assert_eq!(&color, intersect.color.diffuse());
+        assert_ulps_eq!(1.0, intersect.distance);
+        assert_ulps_eq!(vec3(0.0, 0.0, -1.0), intersect.normal);
+        assert_ulps_eq!(0.0, intersect.uv.y);
+
+        // Outside the radius
+        let r = Ray::new(vec3(0.6, 0.0, 0.0), vec3(0.0, 0.0, 1.0));
+        assert!(disk.intersect(&r).is_none());
+
+        // Parallel to the disk
+        let r = Ray::new(vec3(0.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0));
+        assert!(disk.intersect(&r).is_none());
+    }
+
+    // Tests that rays pass through the hole in an annulus
+    #[test]
+    fn intersect_annulus() {
+        let color = Rgb::new([255, 255, 0]);
+
+        let annulus = Disk::annulus(
+            vec3(0.0, 0.0, 1.0),
+            vec3(0.0, 0.0, -1.0),
+            0.25,
+            0.5,
+            Material::new(color.clone(), (1.0, 1.0, 1.0), 0.0, 0.0, 0.0),
+        );
+
+        let r = Ray::new(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0));
+        assert!(annulus.intersect(&r).is_none());
+
+        let r = Ray::new(vec3(0.0, 0.375, 0.0), vec3(0.0, 0.0, 1.0));
+        let intersect = annulus
+            .intersect(&r)
+            .expect("Ray should intersect with the ring");
+        assert_ulps_eq!(1.0, intersect.distance);
+        assert_ulps_eq!(0.5, intersect.uv.y);
+    }
+
+    // Tests that a ring with no width still has texture coordinates
+    #[test]
+    fn intersect_zero_width() {
+        let ring = Disk::annulus(
+            vec3(0.0, 0.0, 1.0),
+            vec3(0.0, 0.0, -1.0),
+            0.5,
+            0.5,
+            Material::new(Rgb::new([255, 255, 0]), (1.0, 1.0, 1.0), 0.0, 0.0, 0.0),
+        );
+
+        let r = Ray::new(vec3(0.0, 0.5, 0.0), vec3(0.0, 0.0, 1.0));
+        let intersect = ring.intersect(&r).expect("Ray should hit the edge of the ring");
+        assert_ulps_eq!(0.0, intersect.uv.y);
+
+        let sample = ring.sample(0.5, 0.5).unwrap();
+        assert!(sample.uv.x.is_finite() && sample.uv.y.is_finite());
+    }
+}
@@ -0,0 +1,523 @@
+extern crate std;
+
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use cgmath::{dot, vec3, InnerSpace, Vector3};
+use image::{self, ImageError, ImageResult};
+use image::hdr::HDRDecoder;
+use sampling::{uniform_sphere, uniform_sphere_pdf, Distribution};
+use tracer::Background;
+use color::Color;
+
+const PI: f64 = std::f64::consts::PI;
+
+// Light arriving from infinitely far away, seen by rays that don't hit
+// anything. Integrators that sample lights can also sample it directly.
+pub trait Environment: Send + Sync {
+    // Light arriving along the direction, which points away from the scene
+    fn radiance(&self, direction: Vector3<f64>) -> Color;
+
+    // Seen behind everything by a camera ray, which knows the point on the
+    // image it was fired through if it came from a camera. Most environments
+    // only depend on the direction.
+    fn backdrop(&self, direction: Vector3<f64>, _screen: Option<(f64, f64)>) -> Color {
+        self.radiance(direction)
+    }
+
+    // Picks a direction to sample the environment as a light from two uniform
+    // random numbers in [0, 1), returning it with its probability density.
+    // Environments that don't give off any light return None.
+    fn sample(&self, u: f64, v: f64) -> Option<(Vector3<f64>, f64)> {
+        Some((uniform_sphere(u, v), uniform_sphere_pdf()))
+    }
+
+    // Probability density of sample picking the direction
+    fn pdf(&self, _direction: Vector3<f64>) -> f64 {
+        uniform_sphere_pdf()
+    }
+}
+
+// A flat color lights the scene evenly from every direction
+impl Environment for Background {
+    fn radiance(&self, _direction: Vector3<f64>) -> Color {
+        Color::from_rgb(&self.color)
+    }
+
+    fn sample(&self, u: f64, v: f64) -> Option<(Vector3<f64>, f64)> {
+        if Color::from_rgb(&self.color).is_black() {
+            None
+        } else {
+            Some((uniform_sphere(u, v), uniform_sphere_pdf()))
+        }
+    }
+}
+
+// Blends from one color straight up to another straight down, like a studio
+// backdrop or a simple sky
+pub struct Gradient {
+    pub top: Color,
+    pub bottom: Color,
+}
+
+impl Environment for Gradient {
+    fn radiance(&self, direction: Vector3<f64>) -> Color {
+        let t = (direction.normalize().y + 1.0) / 2.0;
+        self.top * t + self.bottom * (1.0 - t)
+    }
+}
+
+// Image stretched across the camera's view, for placing a scene in front of a
+// photograph. It's laid out in screen space, so it stays put however the
+// camera is posed and fills the image whatever its shape. That only makes
+// sense as the background seen by camera rays; anything that reflects it
+// would show a flat picture from the wrong angle.
+pub struct Backplate {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+
+    // Half the width and height of the view at a distance of 1 in front of
+    // an unposed camera looking along +Z, for rays that don't know where on
+    // the image they came from
+    pub view: f64,
+}
+
+impl Backplate {
+    // Creates a backplate from pixels stored row by row from the top
+    pub fn new(pixels: Vec<Color>, width: usize, height: usize, view: f64) -> Backplate {
+        assert_eq!(width * height, pixels.len());
+        Backplate {
+            width,
+            height,
+            pixels,
+            view,
+        }
+    }
+
+    /// Loads an image file to show behind the scene
+    pub fn open<P: AsRef<Path>>(path: P, view: f64) -> ImageResult<Backplate> {
+        let image = image::open(path)?.to_rgb();
+        let pixels = image
+            .pixels()
+            .map(|pixel| {
+                let channel = |i: usize| pixel.data[i] as f64 / 255.0;
+                Color::new(channel(0), channel(1), channel(2))
+            })
+            .collect();
+
+        Ok(Backplate::new(
+            pixels,
+            image.width() as usize,
+            image.height() as usize,
+            view,
+        ))
+    }
+}
+
+impl Backplate {
+    // Color at a point on the image, with x and y in [0, 1] from the top left.
+    // Points outside of it take the color of its nearest edge.
+    fn pixel(&self, x: f64, y: f64) -> Color {
+        let column = ((x * self.width as f64).max(0.0) as usize).min(self.width - 1);
+        let row = ((y * self.height as f64).max(0.0) as usize).min(self.height - 1);
+        self.pixels[row * self.width + column]
+    }
+}
+
+impl Environment for Backplate {
+    // Follows the direction out to the plane in front of the camera
+    fn radiance(&self, direction: Vector3<f64>) -> Color {
+        let z = direction.z.max(1e-9);
+        let x = (direction.x / z / self.view + 1.0) / 2.0;
+        let y = (1.0 - direction.y / z / self.view) / 2.0;
+        self.pixel(x, y)
+    }
+
+    fn backdrop(&self, direction: Vector3<f64>, screen: Option<(f64, f64)>) -> Color {
+        match screen {
+            Some((x, y)) => self.pixel(x + 0.5, 0.5 - y),
+            None => self.radiance(direction),
+        }
+    }
+}
+
+// How the directions around the scene are laid out on the image
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Projection {
+    // Longitude across and latitude down, twice as wide as it is tall, with
+    // +Z in the middle and +Y along the top edge
+    Equirectangular,
+
+    // Six square faces in a horizontal cross, four faces wide and three tall:
+    //
+    //        +Y
+    //    -X  +Z  +X  -Z
+    //        -Y
+    //
+    // Each face is drawn as seen from the middle of the cube, with +Y up for
+    // the four faces around the middle row.
+    Cube,
+}
+
+// Axis, right and up directions of a cube face, along with the column and row
+// of the cross it's drawn in
+type CubeFace = ([f64; 3], [f64; 3], [f64; 3], usize, usize);
+
+const CUBE_FACES: [CubeFace; 6] = [
+    ([1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0], 2, 1),
+    ([-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0], 0, 1),
+    ([0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0], 1, 0),
+    ([0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0], 1, 2),
+    ([0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0], 1, 1),
+    ([0.0, 0.0, -1.0], [-1.0, 0.0, 0.0], [0.0, 1.0, 0.0], 3, 1),
+];
+
+impl Projection {
+    // Direction through a point on the image, from 0 to 1 across from the left
+    // and down from the top. Cube crosses have empty space around the faces,
+    // which doesn't point anywhere.
+    pub fn direction(&self, u: f64, v: f64) -> Option<Vector3<f64>> {
+        match *self {
+            Projection::Equirectangular => {
+                let phi = (u - 0.5) * 2.0 * PI;
+                let theta = v * PI;
+                Some(vec3(
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                    theta.sin() * phi.cos(),
+                ))
+            }
+            Projection::Cube => {
+                let (x, y) = (u * 4.0, v * 3.0);
+                let (column, row) = (x as usize, y as usize);
+
+                CUBE_FACES
+                    .iter()
+                    .find(|face| face.3 == column && face.4 == row)
+                    .map(|&(axis, right, up, _, _)| {
+                        let s = (x - column as f64) * 2.0 - 1.0;
+                        let t = 1.0 - (y - row as f64) * 2.0;
+                        (Vector3::from(axis) + Vector3::from(right) * s + Vector3::from(up) * t)
+                            .normalize()
+                    })
+            }
+        }
+    }
+
+    // Point on the image that the direction points through, in the same
+    // units as direction()
+    pub fn position(&self, direction: Vector3<f64>) -> (f64, f64) {
+        let d = direction.normalize();
+
+        match *self {
+            Projection::Equirectangular => {
+                let phi = d.x.atan2(d.z);
+                let theta = d.y.clamp(-1.0, 1.0).acos();
+                (0.5 + phi / (2.0 * PI), theta / PI)
+            }
+            Projection::Cube => {
+                let &(axis, right, up, column, row) = CUBE_FACES
+                    .iter()
+                    .max_by(|first, second| {
+                        let first = dot(d, Vector3::from(first.0));
+                        let second = dot(d, Vector3::from(second.0));
+                        first.partial_cmp(&second).unwrap_or(Ordering::Equal)
+                    })
+                    .unwrap();
+
+                let major = dot(d, Vector3::from(axis));
+                let s = dot(d, Vector3::from(right)) / major;
+                let t = dot(d, Vector3::from(up)) / major;
+                (
+                    (column as f64 + (s + 1.0) / 2.0) / 4.0,
+                    (row as f64 + (1.0 - t) / 2.0) / 3.0,
+                )
+            }
+        }
+    }
+}
+
+// High dynamic range image wrapped around the scene, usually a photograph of
+// a real place. Bright spots like the sun or windows light the scene much
+// more than the rest of the image, so they're sampled more often.
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+    projection: Projection,
+
+    // Pixels picked in proportion to the light they send into the scene
+    distribution: Distribution,
+}
+
+impl EnvironmentMap {
+    // Creates a map from linear pixels, stored row by row from the top
+    pub fn new(pixels: Vec<Color>, width: usize, height: usize, projection: Projection) -> EnvironmentMap {
+        assert_eq!(width * height, pixels.len());
+
+        let mut map = EnvironmentMap {
+            width,
+            height,
+            pixels,
+            projection,
+            distribution: Distribution::new(vec![]),
+        };
+
+        // Pixels toward the poles and the corners of cube faces cover less of
+        // the sky, so they send less light into the scene
+        let weights: Vec<f64> = (0..width * height)
+            .map(|index| {
+                let (x, y) = ((index % width) as f64 + 0.5, (index / width) as f64 + 0.5);
+                match map.direction(x, y) {
+                    Some(direction) => map.pixels[index].luminance() * map.solid_angle(direction),
+                    None => 0.0,
+                }
+            })
+            .collect();
+        map.distribution = Distribution::new(weights);
+
+        map
+    }
+
+    /// Loads a Radiance .hdr file, guessing the projection from its shape:
+    /// twice as wide as it is tall for equirectangular, or four faces by
+    /// three for a cube cross
+    pub fn open<P: AsRef<Path>>(path: P) -> ImageResult<EnvironmentMap> {
+        let decoder = HDRDecoder::new(BufReader::new(File::open(path)?))?;
+        let metadata = decoder.metadata();
+        let (width, height) = (metadata.width as usize, metadata.height as usize);
+
+        let projection = if width == 2 * height {
+            Projection::Equirectangular
+        } else if width * 3 == height * 4 {
+            Projection::Cube
+        } else {
+            return Err(ImageError::FormatError(format!(
+                "{}x{} environment map is neither equirectangular (2:1) nor a cube cross (4:3)",
+                width, height
+            )));
+        };
+
+        let pixels = decoder
+            .read_image_hdr()?
+            .into_iter()
+            .map(|pixel: image::Rgb<f32>| {
+                Color::new(pixel.data[0] as f64, pixel.data[1] as f64, pixel.data[2] as f64)
+            })
+            .collect();
+
+        Ok(EnvironmentMap::new(pixels, width, height, projection))
+    }
+
+    // Direction through a position on the image, measured in pixels from the
+    // top left corner
+    fn direction(&self, x: f64, y: f64) -> Option<Vector3<f64>> {
+        self.projection
+            .direction(x / self.width as f64, y / self.height as f64)
+    }
+
+    // Position on the image that the direction points through, in pixels
+    fn position(&self, direction: Vector3<f64>) -> (f64, f64) {
+        let (u, v) = self.projection.position(direction);
+        (u * self.width as f64, v * self.height as f64)
+    }
+
+    // Solid angle covered by a square pixel's worth of the image around the
+    // direction
+    fn solid_angle(&self, direction: Vector3<f64>) -> f64 {
+        let d = direction.normalize();
+
+        match self.projection {
+            Projection::Equirectangular => {
+                let sin_theta = (1.0 - d.y * d.y).max(0.0).sqrt();
+                (2.0 * PI / self.width as f64) * (PI / self.height as f64) * sin_theta
+            }
+            Projection::Cube => {
+                // Faces are 2 units across at a distance of 1, and spread
+                // out by the cube of the distance to their corners
+                let major = d.x.abs().max(d.y.abs()).max(d.z.abs());
+                let pixel = 2.0 / (self.width / 4) as f64;
+                pixel * pixel * major * major * major
+            }
+        }
+    }
+
+    // Index of the pixel containing the position
+    fn pixel(&self, (x, y): (f64, f64)) -> usize {
+        let column = (x.max(0.0) as usize).min(self.width - 1);
+        let row = (y.max(0.0) as usize).min(self.height - 1);
+        row * self.width + column
+    }
+}
+
+impl Environment for EnvironmentMap {
+    fn radiance(&self, direction: Vector3<f64>) -> Color {
+        self.pixels[self.pixel(self.position(direction))]
+    }
+
+    // Picks a pixel, then a point spread evenly across it
+    fn sample(&self, u: f64, v: f64) -> Option<(Vector3<f64>, f64)> {
+        self.distribution.sample(u).and_then(|(index, u)| {
+            let x = (index % self.width) as f64 + u;
+            let y = (index / self.width) as f64 + v;
+
+            self.direction(x, y).map(|direction| {
+                let pdf = self.distribution.probability(index) / self.solid_angle(direction);
+                (direction, pdf)
+            })
+        })
+    }
+
+    fn pdf(&self, direction: Vector3<f64>) -> f64 {
+        let index = self.pixel(self.position(direction));
+        self.distribution.probability(index) / self.solid_angle(direction)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::env;
+    use std::fs::File;
+    use cgmath::{vec3, InnerSpace};
+    use image;
+    use image::hdr::HDREncoder;
+    use environment::{Backplate, Environment, EnvironmentMap, Gradient, Projection};
+    use color::Color;
+
+    // Dark map with a single bright pixel
+    fn map(width: usize, height: usize, bright: usize, projection: Projection) -> EnvironmentMap {
+        let mut pixels = vec![Color::new(0.1, 0.1, 0.1); width * height];
+        pixels[bright] = Color::new(100.0, 50.0, 25.0);
+        EnvironmentMap::new(pixels, width, height, projection)
+    }
+
+    // Tests that a gradient blends between its colors by height
+    #[test]
+    fn gradient() {
+        let gradient = Gradient {
+            top: Color::new(1.0, 1.0, 1.0),
+            bottom: Color::new(0.0, 0.0, 0.5),
+        };
+
+        assert_eq!(Color::new(1.0, 1.0, 1.0), gradient.radiance(vec3(0.0, 2.0, 0.0)));
+        assert_eq!(Color::new(0.0, 0.0, 0.5), gradient.radiance(vec3(0.0, -1.0, 0.0)));
+        assert_eq!(Color::new(0.5, 0.5, 0.75), gradient.radiance(vec3(1.0, 0.0, 1.0)));
+    }
+
+    // Tests that a backplate fills the camera's view, with the top left pixel
+    // in the top left corner
+    #[test]
+    fn backplate() {
+        let pixels = (0..4).map(|i| Color::new(i as f64, 0.0, 0.0)).collect();
+        let backplate = Backplate::new(pixels, 2, 2, 0.5);
+
+        assert_eq!(0.0, backplate.radiance(vec3(-0.2, 0.2, 1.0)).r);
+        assert_eq!(1.0, backplate.radiance(vec3(0.2, 0.2, 1.0)).r);
+        assert_eq!(2.0, backplate.radiance(vec3(-0.2, -0.2, 1.0)).r);
+        assert_eq!(3.0, backplate.radiance(vec3(0.4, -0.4, 1.0)).r);
+
+        // Outside the view, the edges stretch out to fill the rest
+        assert_eq!(1.0, backplate.radiance(vec3(5.0, 0.1, 1.0)).r);
+
+        // Camera rays find it by where they are on the image, whichever way
+        // they point
+        let backward = vec3(0.0, 0.0, -1.0);
+        assert_eq!(0.0, backplate.backdrop(backward, Some((-0.4, 0.4))).r);
+        assert_eq!(1.0, backplate.backdrop(backward, Some((0.4, 0.1))).r);
+        assert_eq!(3.0, backplate.backdrop(backward, Some((0.5, -0.5))).r);
+        assert_eq!(2.0, backplate.backdrop(vec3(-0.2, -0.2, 1.0), None).r);
+    }
+
+    // Tests that directions map to the expected parts of an equirectangular
+    // image and back again
+    #[test]
+    fn equirectangular() {
+        let map = map(8, 4, 8 * 2 + 4, Projection::Equirectangular);
+
+        // +Z is in the middle, just below the bright pixel's top left corner
+        assert_eq!(Color::new(100.0, 50.0, 25.0), map.radiance(vec3(0.1, -0.1, 1.0)));
+        assert_eq!(Color::new(0.1, 0.1, 0.1), map.radiance(vec3(0.0, 1.0, 0.0)));
+
+        let d = vec3(0.3, -0.5, -0.7).normalize();
+        let (x, y) = map.position(d);
+        assert_relative_eq!(d, map.direction(x, y).unwrap(), epsilon = 1e-9);
+    }
+
+    // Tests that each axis lands in the middle of its face of the cross, and
+    // that the corners of the cross don't point anywhere
+    #[test]
+    fn cube() {
+        let map = map(8, 6, 2 * 8 + 7, Projection::Cube);
+
+        let axes = [
+            (vec3(1.0, 0.0, 0.0), (5.0, 3.0)),
+            (vec3(-1.0, 0.0, 0.0), (1.0, 3.0)),
+            (vec3(0.0, 1.0, 0.0), (3.0, 1.0)),
+            (vec3(0.0, -1.0, 0.0), (3.0, 5.0)),
+            (vec3(0.0, 0.0, 1.0), (3.0, 3.0)),
+            (vec3(0.0, 0.0, -1.0), (7.0, 3.0)),
+        ];
+        for &(axis, position) in &axes {
+            let (x, y) = map.position(axis);
+            assert_relative_eq!(position.0, x, epsilon = 1e-9);
+            assert_relative_eq!(position.1, y, epsilon = 1e-9);
+        }
+
+        // Looking back, and up and to the right as seen from inside
+        let d = vec3(-0.2, 0.9, -1.0).normalize();
+        assert_eq!(Color::new(100.0, 50.0, 25.0), map.radiance(d));
+        let (x, y) = map.position(d);
+        assert_relative_eq!(d, map.direction(x, y).unwrap(), epsilon = 1e-9);
+
+        assert!(map.direction(0.5, 0.5).is_none());
+    }
+
+    // Tests that bright pixels are sampled most often, and that sampled
+    // directions report the same density as looking them up
+    #[test]
+    fn sample() {
+        for &(width, height, projection) in
+            &[(8, 4, Projection::Equirectangular), (8, 6, Projection::Cube)]
+        {
+            let map = map(width, height, width * 2 + 4, projection);
+
+            let mut bright = 0;
+            for i in 0..100 {
+                let (u, v) = ((i as f64 + 0.5) / 100.0, (i * 37 % 100) as f64 / 100.0);
+                let (direction, pdf) = map.sample(u, v).expect("Map should be sampled");
+                assert_relative_eq!(1.0, direction.magnitude(), epsilon = 1e-9);
+                assert_relative_eq!(pdf, map.pdf(direction), epsilon = 1e-6);
+
+                if map.radiance(direction).r > 1.0 {
+                    bright += 1;
+                }
+            }
+            assert!(bright > 90, "Only {} samples of the bright pixel", bright);
+        }
+    }
+
+    // Tests loading an .hdr file and guessing its projection
+    #[test]
+    fn open() {
+        let path = env::temp_dir().join("rusttracer_environment_test.hdr");
+        let pixels: Vec<image::Rgb<f32>> = (0..8)
+            .map(|i| image::Rgb([i as f32, 2.0, 0.5]))
+            .collect();
+        HDREncoder::new(File::create(&path).unwrap())
+            .encode(&pixels, 4, 2)
+            .unwrap();
+
+        let map = EnvironmentMap::open(&path).expect("Map should load");
+        assert_eq!(Projection::Equirectangular, map.projection);
+        assert_eq!(Color::new(5.0, 2.0, 0.5), map.pixels[5]);
+
+        HDREncoder::new(File::create(&path).unwrap())
+            .encode(&pixels, 8, 1)
+            .unwrap();
+        assert!(EnvironmentMap::open(&path).is_err());
+    }
+}
@@ -1,9 +1,8 @@
 extern crate std;
 
-use cgmath::{dot, Angle, InnerSpace, Vector3};
+use cgmath::{dot, vec2, Angle, InnerSpace, Vector2, Vector3};
 use tracer::{Intersect, Shape};
 use ray::Ray;
-use std::any::Any;
 use light::Material;
 
 const SUBDIVISIONS_X: f64 = 7.0;
@@ -93,14 +92,24 @@ impl Floor {
         )
     }
 
-    // Calculates the correct color at a specific intersect
-    fn color_at(&self, intersect: &Vector3<f64>) -> &Material {
+    // Calculates the distance of a point along the bottom and left edges
+    fn local_coords(&self, intersect: &Vector3<f64>) -> (f64, f64) {
         let hyp = intersect - self.bottom_left;
         let hyp_length = hyp.magnitude();
         let angle = (self.bottom_right - self.bottom_left).angle(hyp);
 
-        let x = angle.cos() * hyp_length;
-        let y = angle.sin() * hyp_length;
+        (angle.cos() * hyp_length, angle.sin() * hyp_length)
+    }
+
+    // Calculates the texture coordinates at a specific intersect
+    fn uv_at(&self, intersect: &Vector3<f64>) -> Vector2<f64> {
+        let (x, y) = self.local_coords(intersect);
+        vec2(x / self.width, y / self.height)
+    }
+
+    // Calculates the correct color at a specific intersect
+    fn color_at(&self, intersect: &Vector3<f64>) -> &Material {
+        let (x, y) = self.local_coords(intersect);
 
         let x_parity = (x / (self.width / SUBDIVISIONS_X)) as u64 % 2;
         let y_parity = (y / (self.height / SUBDIVISIONS_Y)) as u64 % 2;
@@ -113,18 +122,6 @@ impl Floor {
     }
 }
 
-impl PartialEq for Floor {
-    // Shapes really shouldn't be overlapping. If two different objects have the
-    // same coordinates and dimensions but different materials, we have a bigger
-    // problem.
-    fn eq(&self, other: &Floor) -> bool {
-        ulps_eq!(self.bottom_left, other.bottom_left) && ulps_eq!(self.top_left, other.top_left)
-            && ulps_eq!(self.top_right, other.top_right)
-            && ulps_eq!(self.bottom_right, other.bottom_right)
-            && ulps_eq!(self.normal, other.normal) && ulps_eq!(self.f, other.f)
-    }
-}
-
 
 impl Shape for Floor {
     /// Plane intersection formula comes from CG II slides
@@ -133,7 +130,7 @@ impl Shape for Floor {
     fn intersect(&self, ray: &Ray) -> Option<Intersect> {
         let distance = -(dot(self.normal, ray.origin) + self.f) / dot(self.normal, ray.direction());
 
-        if distance > 0.0 {
+        if ray.contains(distance) {
             let intersect = ray.extend(distance);
             // Make sure the value is inside the shape boundaries
             if intersect.x >= self.bottom_left.x && intersect.x <= self.bottom_right.x
@@ -144,8 +141,9 @@ impl Shape for Floor {
                     distance,
                     point: intersect,
                     normal: self.normal,
+                    uv: self.uv_at(&intersect),
                     color: self.color_at(&intersect),
-                    shape: self,
+                    shape: None,
                 })
             } else {
                 None
@@ -155,15 +153,29 @@ impl Shape for Floor {
         }
     }
 
-    fn eq(&self, other: &Shape) -> bool {
-        other
-            .as_any()
-            .downcast_ref::<Self>()
-            .map_or(false, |x| x == self)
+    fn area(&self) -> f64 {
+        self.width * self.height
+    }
+
+    fn sample(&self, u: f64, v: f64) -> Option<Intersect> {
+        let point = self.bottom_left + (self.bottom_right - self.bottom_left) * u
+            + (self.top_left - self.bottom_left) * v;
+
+        Some(Intersect {
+            distance: 0.0,
+            point,
+            normal: self.normal,
+            uv: vec2(u, v),
+            color: self.color_at(&point),
+            shape: None,
+        })
     }
 
-    fn as_any(&self) -> &Any {
-        self
+    // Either color of the checkerboard can glow
+    fn emits(&self) -> bool {
+        [&self.color1, &self.color2]
+            .iter()
+            .any(|color| !color.emission().is_black())
     }
 }
 
@@ -0,0 +1,491 @@
+extern crate image;
+
+use std::cmp::Ordering;
+use cgmath::{dot, vec2, vec3, InnerSpace, Vector3};
+use tracer::{Intersect, Shape};
+use sampling::Distribution;
+use ray::Ray;
+use std::path::Path;
+use std::u8;
+use light::Material;
+
+// Terrain defined by a grid of heights, like a grayscale elevation map. The
+// grid covers a box with one corner at the origin, spanning the size along X
+// and Z. Heights range from 0 to 1, scaled by the size along Y.
+//
+// Each grid cell is split into two triangles, but the ray only tests cells it
+// actually passes over, so large grids don't slow down every ray.
+pub struct Heightfield {
+    pub origin: Vector3<f64>,
+    pub size: Vector3<f64>,
+    pub color: Material,
+
+    // Number of samples along X and Z
+    columns: usize,
+    rows: usize,
+
+    // Height of each sample above the origin, row by row
+    heights: Vec<f64>,
+
+    // Surface normal at each sample, used to smooth out the triangles
+    normals: Vec<Vector3<f64>>,
+
+    // Size of a single cell
+    cell_x: f64,
+    cell_z: f64,
+
+    // Areas of the triangles, two per cell in the same order as the samples,
+    // for picking random points on the surface
+    areas: Distribution,
+}
+
+// Corners of a cell making up each of its two triangles, counting
+// counterclockwise from the corner nearest the origin
+const TRIANGLES: [[usize; 3]; 2] = [[0, 1, 2], [0, 2, 3]];
+
+impl Heightfield {
+    /// Creates a heightfield from samples between 0 and 1, stored row by row.
+    /// There must be at least two rows and two columns.
+    pub fn new(
+        heights: Vec<f64>,
+        columns: usize,
+        rows: usize,
+        origin: Vector3<f64>,
+        size: Vector3<f64>,
+        color: Material,
+    ) -> Heightfield {
+        assert!(columns >= 2 && rows >= 2, "heightfield needs at least 2x2 samples");
+        assert_eq!(columns * rows, heights.len());
+
+        let cell_x = size.x / (columns - 1) as f64;
+        let cell_z = size.z / (rows - 1) as f64;
+        let heights: Vec<f64> = heights.into_iter().map(|h| h * size.y).collect();
+
+        let mut heightfield = Heightfield {
+            origin,
+            size,
+            color,
+            columns,
+            rows,
+            heights,
+            normals: vec![],
+            cell_x,
+            cell_z,
+            areas: Distribution::new(vec![]),
+        };
+
+        let normals = (0..rows)
+            .flat_map(|z| (0..columns).map(move |x| (x, z)))
+            .map(|(x, z)| heightfield.sample_normal(x, z))
+            .collect();
+        heightfield.normals = normals;
+
+        let areas = Distribution::new((0..(rows - 1) * (columns - 1) * 2).map(|index| {
+            let [a, b, c] = heightfield.triangle(index);
+            let (a, b, c) = (
+                heightfield.vertex(a.0, a.1),
+                heightfield.vertex(b.0, b.1),
+                heightfield.vertex(c.0, c.1),
+            );
+            (b - a).cross(c - a).magnitude() / 2.0
+        }));
+        heightfield.areas = areas;
+
+        heightfield
+    }
+
+    /// Creates a heightfield from a grayscale image, where black is the bottom
+    /// of the box and white is the top. Each pixel becomes one sample, with
+    /// the top row of the image along the origin.
+    pub fn from_image(
+        image: &image::GrayImage,
+        origin: Vector3<f64>,
+        size: Vector3<f64>,
+        color: Material,
+    ) -> Heightfield {
+        let heights = image
+            .pixels()
+            .map(|pixel| pixel.data[0] as f64 / u8::MAX as f64)
+            .collect();
+
+        Heightfield::new(
+            heights,
+            image.width() as usize,
+            image.height() as usize,
+            origin,
+            size,
+            color,
+        )
+    }
+
+    /// Loads an image file and creates a heightfield from its brightness
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        origin: Vector3<f64>,
+        size: Vector3<f64>,
+        color: Material,
+    ) -> image::ImageResult<Heightfield> {
+        let image = image::open(path)?.to_luma();
+        Ok(Heightfield::from_image(&image, origin, size, color))
+    }
+
+    // Position of a sample in world space
+    fn vertex(&self, x: usize, z: usize) -> Vector3<f64> {
+        self.origin
+            + vec3(
+                x as f64 * self.cell_x,
+                self.heights[z * self.columns + x],
+                z as f64 * self.cell_z,
+            )
+    }
+
+    // Grid positions of the corners of one of the triangles covering a cell
+    fn corners(&self, x: usize, z: usize, triangle: usize) -> [(usize, usize); 3] {
+        let corners = [(x, z), (x + 1, z), (x + 1, z + 1), (x, z + 1)];
+        let triangle = TRIANGLES[triangle];
+        [corners[triangle[0]], corners[triangle[1]], corners[triangle[2]]]
+    }
+
+    // Grid positions of the corners of a triangle, numbered two per cell
+    fn triangle(&self, index: usize) -> [(usize, usize); 3] {
+        let cell = index / 2;
+        self.corners(cell % (self.columns - 1), cell / (self.columns - 1), index % 2)
+    }
+
+    // Smooth normal inside a triangle, from the barycentric weights of its
+    // second and third corners
+    fn normal_at(&self, corners: [(usize, usize); 3], u: f64, v: f64) -> Vector3<f64> {
+        let normal = |(x, z): (usize, usize)| self.normals[z * self.columns + x];
+        (normal(corners[0]) * (1.0 - u - v) + normal(corners[1]) * u + normal(corners[2]) * v)
+            .normalize()
+    }
+
+    // Estimates the normal at a sample from the slope to its neighbors, using
+    // central differences except along the edges
+    fn sample_normal(&self, x: usize, z: usize) -> Vector3<f64> {
+        let height = |x: usize, z: usize| self.heights[z * self.columns + x];
+
+        let (left, right) = (x.saturating_sub(1), (x + 1).min(self.columns - 1));
+        let (near, far) = (z.saturating_sub(1), (z + 1).min(self.rows - 1));
+
+        let slope_x = (height(right, z) - height(left, z)) / ((right - left) as f64 * self.cell_x);
+        let slope_z = (height(x, far) - height(x, near)) / ((far - near) as f64 * self.cell_z);
+
+        vec3(-slope_x, 1.0, -slope_z).normalize()
+    }
+
+    // Finds where the ray enters and leaves the bounding box, using the slab
+    // method
+    fn bounds(&self, ray: &Ray) -> Option<(f64, f64)> {
+        let mut enter = ray.t_min;
+        let mut exit = ray.t_max;
+
+        for axis in 0..3 {
+            let o = ray.origin[axis] - self.origin[axis];
+            let d = ray.direction()[axis];
+            let size = self.size[axis];
+
+            if d.abs() < 1e-12 {
+                // Parallel to this pair of faces, so it has to start between them
+                if o < 0.0 || o > size {
+                    return None;
+                }
+            } else {
+                let (t1, t2) = (-o / d, (size - o) / d);
+                enter = f64::max(enter, f64::min(t1, t2));
+                exit = f64::min(exit, f64::max(t1, t2));
+            }
+        }
+
+        if enter <= exit {
+            Some((enter, exit))
+        } else {
+            None
+        }
+    }
+
+    fn intersect_at(&self, point: Vector3<f64>, distance: f64, normal: Vector3<f64>) -> Intersect {
+        let local = point - self.origin;
+
+        Intersect {
+            distance,
+            point,
+            normal,
+            uv: vec2(local.x / self.size.x, local.z / self.size.z),
+            color: &self.color,
+            shape: None,
+        }
+    }
+
+    // Tests the two triangles covering a cell, returning the closest hit
+    fn intersect_cell(&self, ray: &Ray, x: usize, z: usize) -> Option<Intersect> {
+        (0..TRIANGLES.len())
+            .filter_map(|triangle| {
+                let corners = self.corners(x, z, triangle);
+                let [a, b, c] = corners;
+
+                intersect_triangle(
+                    ray,
+                    self.vertex(a.0, a.1),
+                    self.vertex(b.0, b.1),
+                    self.vertex(c.0, c.1),
+                ).map(|(distance, u, v)| {
+                    let point = ray.extend(distance);
+                    self.intersect_at(point, distance, self.normal_at(corners, u, v))
+                })
+            })
+            .min_by(|first, second| {
+                first
+                    .distance
+                    .partial_cmp(&second.distance)
+                    .unwrap_or(Ordering::Equal)
+            })
+    }
+}
+
+// Moller-Trumbore ray/triangle intersection. Returns the distance along the
+// ray and the barycentric weights of the second and third corners.
+fn intersect_triangle(
+    ray: &Ray,
+    a: Vector3<f64>,
+    b: Vector3<f64>,
+    c: Vector3<f64>,
+) -> Option<(f64, f64, f64)> {
+    let edge1 = b - a;
+    let edge2 = c - a;
+
+    let p = ray.direction().cross(edge2);
+    let determinant = dot(edge1, p);
+
+    // Ray is parallel to the triangle
+    if determinant.abs() < 1e-12 {
+        return None;
+    }
+
+    let inverse = 1.0 / determinant;
+    let s = ray.origin - a;
+    let u = dot(s, p) * inverse;
+    if u < 0.0 || u > 1.0 {
+        return None;
+    }
+
+    let q = s.cross(edge1);
+    let v = dot(ray.direction(), q) * inverse;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let distance = dot(edge2, q) * inverse;
+    if ray.contains(distance) {
+        Some((distance, u, v))
+    } else {
+        None
+    }
+}
+
+impl Shape for Heightfield {
+    /// Walks across the grid cells under the ray in order, from where it enters
+    /// the bounding box to where it leaves (Amanatides & Woo). The first cell
+    /// with a hit has the closest one.
+    fn intersect(&self, ray: &Ray) -> Option<Intersect> {
+        let (enter, exit) = match self.bounds(ray) {
+            Some(bounds) => bounds,
+            None => return None,
+        };
+
+        let start = ray.extend(enter) - self.origin;
+        let d = ray.direction();
+
+        let cell = |position: f64, size: f64, count: usize| {
+            ((position / size).floor().max(0.0) as usize).min(count - 2)
+        };
+        let mut x = cell(start.x, self.cell_x, self.columns);
+        let mut z = cell(start.z, self.cell_z, self.rows);
+
+        // Distance along the ray to the next cell boundary on each axis, and
+        // between boundaries after that
+        let setup = |position: f64, direction: f64, index: usize, size: f64| {
+            if direction > 0.0 {
+                (enter + ((index + 1) as f64 * size - position) / direction, size / direction)
+            } else if direction < 0.0 {
+                (enter + (index as f64 * size - position) / direction, -size / direction)
+            } else {
+                (::std::f64::INFINITY, ::std::f64::INFINITY)
+            }
+        };
+        let (mut next_x, delta_x) = setup(start.x, d.x, x, self.cell_x);
+        let (mut next_z, delta_z) = setup(start.z, d.z, z, self.cell_z);
+
+        loop {
+            if let Some(intersect) = self.intersect_cell(ray, x, z) {
+                return Some(intersect);
+            }
+
+            // Step into whichever neighbor the ray reaches first
+            let entered = if next_x < next_z {
+                if (d.x > 0.0 && x + 2 >= self.columns) || (d.x < 0.0 && x == 0) {
+                    return None;
+                }
+                x = if d.x > 0.0 { x + 1 } else { x - 1 };
+                let entered = next_x;
+                next_x += delta_x;
+                entered
+            } else {
+                if (d.z > 0.0 && z + 2 >= self.rows) || (d.z < 0.0 && z == 0) || d.z == 0.0 {
+                    return None;
+                }
+                z = if d.z > 0.0 { z + 1 } else { z - 1 };
+                let entered = next_z;
+                next_z += delta_z;
+                entered
+            };
+
+            if entered > exit {
+                return None;
+            }
+        }
+    }
+
+    fn area(&self) -> f64 {
+        self.areas.total()
+    }
+
+    // Picks a triangle in proportion to its area, then reuses the part of u
+    // that picked it to place the point inside the triangle
+    fn sample(&self, u: f64, v: f64) -> Option<Intersect> {
+        let (index, u) = self.areas.sample(u)?;
+
+        // Folding the square of u over the triangle spreads points evenly
+        let root = u.sqrt();
+        let (b, c) = (root * (1.0 - v), root * v);
+
+        let corners = self.triangle(index);
+        let vertex = |(x, z): (usize, usize)| self.vertex(x, z);
+        let point = vertex(corners[0]) * (1.0 - b - c) + vertex(corners[1]) * b + vertex(corners[2]) * c;
+
+        Some(self.intersect_at(point, 0.0, self.normal_at(corners, b, c)))
+    }
+
+    fn emits(&self) -> bool {
+        !self.color.emission().is_black()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use cgmath::{vec3, InnerSpace};
+    use image;
+    use tracer::Shape;
+    use heightfield::Heightfield;
+    use ray::Ray;
+    use light::{Material, Rgb};
+
+    fn material() -> Material {
+        Material::new(Rgb::new([255, 255, 0]), (1.0, 1.0, 1.0), 0.0, 0.0, 0.0)
+    }
+
+    // Tests hitting flat terrain from above
+    #[test]
+    fn intersect_flat() {
+        let field = Heightfield::new(
+            vec![0.5; 9],
+            3,
+            3,
+            vec3(-1.0, 0.0, 1.0),
+            vec3(2.0, 1.0, 2.0),
+            material(),
+        );
+
+        let r = Ray::new(vec3(0.25, 2.0, 2.5), vec3(0.0, -1.0, 0.0));
+        let intersect = field
+            .intersect(&r)
+            .expect("Ray should intersect with the terrain");
+        assert_relative_eq!(1.5, intersect.distance, epsilon = 1e-9);
+        assert_relative_eq!(vec3(0.0, 1.0, 0.0), intersect.normal, epsilon = 1e-9);
+        assert_relative_eq!(0.625, intersect.uv.x, epsilon = 1e-9);
+
+        // Off the edge of the grid
+        let r = Ray::new(vec3(1.5, 2.0, 2.0), vec3(0.0, -1.0, 0.0));
+        assert!(field.intersect(&r).is_none());
+    }
+
+    // Tests that a steady slope has the same smooth normal everywhere
+    #[test]
+    fn intersect_slope() {
+        let heights = (0..4)
+            .flat_map(|_| (0..5).map(|x| x as f64 / 4.0))
+            .collect();
+        let field = Heightfield::new(
+            heights,
+            5,
+            4,
+            vec3(0.0, 0.0, 0.0),
+            vec3(2.0, 1.0, 3.0),
+            material(),
+        );
+
+        let r = Ray::new(vec3(1.3, 5.0, 1.7), vec3(0.0, -1.0, 0.0));
+        let intersect = field
+            .intersect(&r)
+            .expect("Ray should intersect with the slope");
+        assert_relative_eq!(0.65, intersect.point.y, epsilon = 1e-9);
+        assert_relative_eq!(vec3(-0.5, 1.0, 0.0).normalize(), intersect.normal, epsilon = 1e-9);
+    }
+
+    // Tests walking across the grid to a peak loaded from an image
+    #[test]
+    fn intersect_image() {
+        // Flat, except for the last column
+        let image = image::GrayImage::from_fn(8, 2, |x, _| {
+            image::Luma([if x == 7 { 255 } else { 0 }])
+        });
+        let field = Heightfield::from_image(
+            &image,
+            vec3(0.0, 0.0, 0.0),
+            vec3(7.0, 1.0, 1.0),
+            material(),
+        );
+
+        // Skim just above the floor toward the peak, which starts rising at 6
+        let r = Ray::new(vec3(-1.0, 0.5, 0.5), vec3(1.0, 0.0, 0.0));
+        let intersect = field
+            .intersect(&r)
+            .expect("Ray should intersect with the peak");
+        assert_relative_eq!(7.5, intersect.distance, epsilon = 1e-9);
+
+        // Going the other way there's nothing to hit
+        let r = Ray::new(vec3(6.0, 0.5, 0.5), vec3(-1.0, 0.0, 0.0));
+        assert!(field.intersect(&r).is_none());
+    }
+
+    // Tests that sampled points land on the slope, covering its whole area
+    #[test]
+    fn sample() {
+        let heights = (0..3)
+            .flat_map(|_| (0..3).map(|x| x as f64 / 2.0))
+            .collect();
+        let field = Heightfield::new(
+            heights,
+            3,
+            3,
+            vec3(0.0, 0.0, 0.0),
+            vec3(2.0, 2.0, 2.0),
+            material(),
+        );
+
+        // A 45 degree slope stretches the 2x2 square by sqrt(2)
+        assert_relative_eq!(4.0 * 2.0f64.sqrt(), field.area(), epsilon = 1e-9);
+
+        for &(u, v) in &[(0.0, 0.0), (0.3, 0.8), (0.55, 0.1), (0.999, 0.5)] {
+            let sample = field.sample(u, v).expect("Heightfield should be sampled");
+            assert_relative_eq!(sample.point.x, sample.point.y, epsilon = 1e-9);
+            assert_relative_eq!(vec3(-1.0, 1.0, 0.0).normalize(), sample.normal, epsilon = 1e-9);
+        }
+
+        // Low values of u land in the first cell and high ones in the last
+        assert!(field.sample(0.01, 0.5).unwrap().point.z < 1.0);
+        assert!(field.sample(0.99, 0.5).unwrap().point.z > 1.0);
+    }
+}
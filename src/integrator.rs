@@ -0,0 +1,66 @@
+use ray::Ray;
+use scene::Scene;
+use light::Rgb;
+use whitted::Whitted;
+use path::PathTracer;
+use debug::Normals;
+use ao::AmbientOcclusion;
+use sampler::Sampler;
+
+// Light transport algorithm that decides what color a ray sees. Each render
+// uses one integrator for every pixel, so they have to be shareable across the
+// worker threads.
+pub trait Integrator: Send + Sync {
+    // Runs once before rendering starts, for integrators that need to look
+    // over the whole scene first
+    fn prepare(&mut self, _scene: &Scene) {}
+
+    // Fires the ray into the scene and returns the light coming back along it,
+    // drawing any random numbers it needs from the pixel's sampler
+    fn radiance(&self, ray: &Ray, scene: &Scene, sampler: &mut Sampler) -> Rgb;
+
+    // Sampler samples each ray takes up. Integrators that average several
+    // paths move on to the next sample for each one, so the renderer leaves
+    // room for them.
+    fn samples(&self) -> u32 {
+        1
+    }
+}
+
+const CAUSTIC_PHOTONS: usize = 100000;
+
+// Options shared between the integrators that use them, set from the command
+// line
+pub struct Settings {
+    pub ambient_occlusion: AmbientOcclusion,
+
+    // Photons fired at mirror and glass surfaces to find caustics. Zero turns
+    // caustics off.
+    pub caustic_photons: usize,
+}
+
+impl Settings {
+    pub fn new() -> Settings {
+        Settings {
+            ambient_occlusion: AmbientOcclusion::new(),
+            caustic_photons: CAUSTIC_PHOTONS,
+        }
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings::new()
+    }
+}
+
+// Looks up an integrator by the name used to select it on the command line
+pub fn from_name(name: &str, settings: &Settings) -> Option<Box<Integrator>> {
+    match name {
+        "whitted" => Some(Box::new(Whitted::new(settings))),
+        "ao" => Some(Box::new(settings.ambient_occlusion.clone())),
+        "path" => Some(Box::new(PathTracer::new())),
+        "normals" => Some(Box::new(Normals)),
+        _ => None,
+    }
+}
@@ -0,0 +1,231 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use cgmath::{InnerSpace, Vector3};
+
+// Anything with a position that can be stored in a kd-tree
+pub trait Positioned {
+    fn position(&self) -> Vector3<f64>;
+}
+
+// Balanced kd-tree for finding the items near a point. The tree is stored
+// implicitly: the item in the middle of any range splits the rest of the range
+// in half along that node's axis, so no links between nodes are needed.
+pub struct KdTree<T: Positioned> {
+    items: Vec<T>,
+
+    // Split axis for the node stored at the same index in items
+    axes: Vec<usize>,
+}
+
+impl<T: Positioned> KdTree<T> {
+    pub fn new(mut items: Vec<T>) -> KdTree<T> {
+        let mut axes = vec![0; items.len()];
+        build(&mut items, &mut axes);
+        KdTree { items, axes }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Finds up to count of the items closest to the point, but no farther
+    // than the max distance. Returns them with their squared distances,
+    // sorted nearest first.
+    pub fn nearest(&self, point: Vector3<f64>, count: usize, max_distance: f64) -> Vec<(f64, &T)> {
+        let mut found = Found {
+            count,
+            max_distance2: max_distance * max_distance,
+            items: BinaryHeap::new(),
+        };
+        if count > 0 {
+            self.search(0, self.items.len(), point, &mut found);
+        }
+
+        found
+            .items
+            .into_sorted_vec()
+            .into_iter()
+            .map(|neighbor| (neighbor.distance2, neighbor.item))
+            .collect()
+    }
+
+    // Gathers the items within the range of the tree that are close enough to
+    // the point to be among the nearest found so far
+    fn search<'a>(&'a self, start: usize, end: usize, point: Vector3<f64>, found: &mut Found<'a, T>) {
+        if start >= end {
+            return;
+        }
+
+        let middle = start + (end - start) / 2;
+        let item = &self.items[middle];
+        let axis = self.axes[middle];
+
+        found.add((item.position() - point).magnitude2(), item);
+
+        // Only cross to the far side of the split if the sphere around the
+        // point reaches over it, which shrinks as closer items are found
+        let offset = point[axis] - item.position()[axis];
+        let (near, far) = if offset < 0.0 {
+            ((start, middle), (middle + 1, end))
+        } else {
+            ((middle + 1, end), (start, middle))
+        };
+
+        self.search(near.0, near.1, point, found);
+        if offset * offset <= found.max_distance2 {
+            self.search(far.0, far.1, point, found);
+        }
+    }
+}
+
+// Nearest items found so far in a search, kept in a heap with the farthest on
+// top. Once there are enough of them, only items closer than the farthest can
+// get in, so the search narrows down to the distance of the farthest.
+struct Found<'a, T: 'a> {
+    count: usize,
+    max_distance2: f64,
+    items: BinaryHeap<Neighbor<'a, T>>,
+}
+
+impl<'a, T> Found<'a, T> {
+    fn add(&mut self, distance2: f64, item: &'a T) {
+        if distance2 > self.max_distance2 {
+            return;
+        }
+
+        self.items.push(Neighbor { distance2, item });
+        if self.items.len() > self.count {
+            self.items.pop();
+        }
+        if self.items.len() == self.count {
+            self.max_distance2 = self.items.peek().map_or(self.max_distance2, |far| far.distance2);
+        }
+    }
+}
+
+// Item found by a search, ordered by its squared distance from the point
+struct Neighbor<'a, T: 'a> {
+    distance2: f64,
+    item: &'a T,
+}
+
+impl<'a, T> Ord for Neighbor<'a, T> {
+    fn cmp(&self, other: &Neighbor<'a, T>) -> Ordering {
+        self.distance2
+            .partial_cmp(&other.distance2)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+impl<'a, T> PartialOrd for Neighbor<'a, T> {
+    fn partial_cmp(&self, other: &Neighbor<'a, T>) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a, T> PartialEq for Neighbor<'a, T> {
+    fn eq(&self, other: &Neighbor<'a, T>) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<'a, T> Eq for Neighbor<'a, T> {}
+
+// Arranges the items so the middle one splits the rest along the axis where
+// they're most spread out, then does the same for each half
+fn build<T: Positioned>(items: &mut [T], axes: &mut [usize]) {
+    if items.is_empty() {
+        return;
+    }
+
+    let first = items[0].position();
+    let (min, max) = items.iter().fold((first, first), |(min, max), item| {
+        let p = item.position();
+        (
+            Vector3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z)),
+            Vector3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z)),
+        )
+    });
+
+    let extent = max - min;
+    let axis = if extent.x >= extent.y && extent.x >= extent.z {
+        0
+    } else if extent.y >= extent.z {
+        1
+    } else {
+        2
+    };
+
+    items.sort_by(|first, second| {
+        first.position()[axis]
+            .partial_cmp(&second.position()[axis])
+            .unwrap_or(Ordering::Equal)
+    });
+
+    let middle = items.len() / 2;
+    axes[middle] = axis;
+
+    let (left_items, rest) = items.split_at_mut(middle);
+    let (left_axes, rest_axes) = axes.split_at_mut(middle);
+    build(left_items, left_axes);
+    build(&mut rest[1..], &mut rest_axes[1..]);
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use cgmath::{vec3, InnerSpace, Vector3};
+    use kdtree::{KdTree, Positioned};
+
+    impl Positioned for Vector3<f64> {
+        fn position(&self) -> Vector3<f64> {
+            *self
+        }
+    }
+
+    // Tests that searching the tree finds the same points as checking all of
+    // them
+    #[test]
+    fn nearest() {
+        let mut points = vec![];
+        for x in 0..10 {
+            for y in 0..10 {
+                for z in 0..10 {
+                    // Scramble the points a bit so they aren't all on a grid
+                    let jitter = ((x * 7 + y * 13 + z * 29) % 10) as f64 * 0.03;
+                    points.push(vec3(x as f64 + jitter, y as f64 - jitter, z as f64));
+                }
+            }
+        }
+
+        assert!(KdTree::new(Vec::<Vector3<f64>>::new()).is_empty());
+
+        let tree = KdTree::new(points.clone());
+        assert_eq!(1000, tree.len());
+        assert!(!tree.is_empty());
+
+        let point = vec3(4.3, 5.1, 6.7);
+        let found = tree.nearest(point, 8, 1.5);
+        assert_eq!(8, found.len());
+
+        let mut expected: Vec<f64> = points
+            .iter()
+            .map(|p| (p - point).magnitude2())
+            .filter(|&d| d <= 1.5 * 1.5)
+            .collect();
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        for (&(distance2, p), &expected) in found.iter().zip(expected.iter()) {
+            assert_ulps_eq!(expected, distance2);
+            assert_ulps_eq!(expected, (p - point).magnitude2());
+        }
+
+        // Nothing is that close
+        assert!(tree.nearest(vec3(-5.0, 0.0, 0.0), 8, 1.0).is_empty());
+    }
+}
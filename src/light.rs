@@ -1,9 +1,11 @@
 extern crate image;
 
 use cgmath::{dot, InnerSpace, Vector3};
-use tracer::{Intersect, Shape};
+use tracer::Intersect;
 use ray::Ray;
-use tracer::{shape_intersect, transmission_ray};
+use scene::Scene;
+use color::Color;
+use animation::Interpolate;
 use std::{cmp, u8};
 use image::Pixel;
 use std::ops::{Add, Mul};
@@ -131,10 +133,43 @@ impl Mul<u8> for Rgb {
 }
 
 
-// Represents a single point light that's placed within the scene
+// Represents a single light shining on the scene. Point lights are placed
+// within the scene, while directional lights like the sun are so far away that
+// their light arrives from the same direction everywhere.
+#[derive(Clone)]
 pub struct Light {
+    // Where a point light is, or the direction toward a directional light
     pub position: Vector3<f64>,
     pub color: Rgb,
+    pub directional: bool,
+}
+
+impl Light {
+    pub fn point(position: Vector3<f64>, color: Rgb) -> Light {
+        Light {
+            position,
+            color,
+            directional: false,
+        }
+    }
+
+    pub fn directional(direction: Vector3<f64>, color: Rgb) -> Light {
+        Light {
+            position: direction.normalize(),
+            color,
+            directional: true,
+        }
+    }
+
+    // Direction from the point toward the light, and how far away it is
+    pub fn toward(&self, point: Vector3<f64>) -> (Vector3<f64>, f64) {
+        if self.directional {
+            (self.position, ::std::f64::INFINITY)
+        } else {
+            let offset = self.position - point;
+            (offset.normalize(), offset.magnitude())
+        }
+    }
 }
 
 // Color of a shape at a specific point. Includes the components needed for
@@ -159,6 +194,9 @@ pub struct Material {
 
     // Refraction index of the material
     refraction_index: f64,
+
+    // Light given off by the surface, on both sides
+    emission: Color,
 }
 
 impl Material {
@@ -180,6 +218,16 @@ impl Material {
             reflection,
             transmission,
             refraction_index,
+            emission: Color::black(),
+        }
+    }
+
+    // Makes the material glow with the color, scaled by the strength. Strength
+    // can go above 1 for surfaces brighter than white, like lamps.
+    pub fn emissive(self, color: Rgb, strength: f64) -> Material {
+        Material {
+            emission: Color::from_rgb(&color) * strength,
+            ..self
         }
     }
 
@@ -211,35 +259,92 @@ impl Material {
         self.refraction_index
     }
 
+    pub fn emission(&self) -> Color {
+        self.emission
+    }
+
     pub fn phong_constants(&self) -> (f64, f64, f64) {
         (self.k_a, self.k_d, self.k_s)
     }
 }
 
+// Blends 8-bit colors in linear space, so they can be keyframed
+fn interpolate_rgb(from: &Rgb, to: &Rgb, amount: f64) -> Rgb {
+    Color::from_rgb(from)
+        .interpolate(&Color::from_rgb(to), amount)
+        .to_rgb()
+}
+
+// Lights keep whether they're directional from the earlier keyframe
+impl Interpolate for Light {
+    fn interpolate(&self, other: &Light, amount: f64) -> Light {
+        let mut position = self.position.interpolate(&other.position, amount);
+
+        // Opposite directions cancel out halfway between them, which leaves
+        // no direction to normalize, so the light keeps the earlier one there
+        if self.directional {
+            position = if position.magnitude2() > 1e-12 {
+                position.normalize()
+            } else {
+                self.position
+            };
+        }
+
+        Light {
+            position,
+            color: interpolate_rgb(&self.color, &other.color, amount),
+            directional: self.directional,
+        }
+    }
+}
+
+// Every parameter blends on its own, so a material can fade from one look to
+// another, like from glass to a mirror
+impl Interpolate for Material {
+    fn interpolate(&self, other: &Material, amount: f64) -> Material {
+        let blend = |from: f64, to: f64| from.interpolate(&to, amount);
+        Material {
+            ambient: interpolate_rgb(&self.ambient, &other.ambient, amount),
+            diffuse: interpolate_rgb(&self.diffuse, &other.diffuse, amount),
+            specular: interpolate_rgb(&self.specular, &other.specular, amount),
+            k_a: blend(self.k_a, other.k_a),
+            k_d: blend(self.k_d, other.k_d),
+            k_s: blend(self.k_s, other.k_s),
+            reflection: blend(self.reflection, other.reflection),
+            transmission: blend(self.transmission, other.transmission),
+            refraction_index: blend(self.refraction_index, other.refraction_index),
+            emission: self.emission.interpolate(&other.emission, amount),
+        }
+    }
+}
+
 
-// Performs phong shading in a scene
+// Performs phong shading in a scene. Ambient visibility is the fraction of the
+// ambient light that isn't occluded at the intersect.
 pub fn phong(
     intersect: &Intersect,
-    shapes: &Vec<Box<Shape>>,
-    lights: &Vec<Light>,
+    time: f64,
+    scene: &Scene,
     v: Vector3<f64>,
+    ambient_visibility: f64,
 ) -> Rgb {
     let n = intersect.normal;
 
     let (k_a, k_d, k_s) = intersect.color.phong_constants();
 
-    // Start with the base ambient lighting
-    let ambient = intersect.color.ambient() * AMBIENT_FACTOR * k_a;
+    // Start with the base ambient lighting. The material's ambient color is
+    // already scaled down by the ambient factor.
+    let ambient = intersect.color.ambient() * (k_a * ambient_visibility);
 
-    lights.iter().fold(ambient, |result, ref light| {
+    scene.lights.iter().fold(ambient, |result, ref light| {
         // Shadow ray
-        let s = (light.position - intersect.point).normalize();
+        let (s, _) = light.toward(intersect.point);
 
         // Reflected vector
         let r = (s - 2.0 * (dot(s, n) / n.magnitude().powi(2)) * n).normalize();
 
         // Calculate the color including shadow transmission
-        let light_color = trace_shadow(intersect.point, intersect.shape, shapes, &light, 1);
+        let light_color = trace_shadow(intersect.point, n, time, scene, &light);
 
         // Calculate diffuse light component
         let diffuse_dot = dot(s, n);
@@ -268,50 +373,63 @@ pub fn phong(
     })
 }
 
-// Calculates the amount to dim based on transmitted shadows
-fn trace_shadow(
+// Calculates the light that reaches a point past anything in the way. Shapes
+// that let light through tint it and dim it by how much they transmit, one
+// after another along the shadow ray, while anything opaque blocks it.
+pub fn trace_shadow(
     point: Vector3<f64>,
-    shape: &Shape,
-    shapes: &Vec<Box<Shape>>,
+    normal: Vector3<f64>,
+    time: f64,
+    scene: &Scene,
     light: &Light,
-    depth: u8,
 ) -> Rgb {
-    let s = (light.position - point).normalize();
-
-    match shape_intersect(&Ray::new(point, s), shapes, Some(shape)) {
-        // Nothing blocking, use full value
-        None => &light.color * 1.0,
-
-        // If a shape is in the way, check transmission before determining shadow
-        Some(blocking) => {
-            let k_t = blocking.color.transmission();
-            let (_, k_d, _) = blocking.color.phong_constants();
-
-            // Transmission color should only reduce the light color by the
-            // diffuse phong constant for the shape.
-            let color = Rgb {
-                color: blocking
-                    .color
-                    .diffuse()
-                    .color
-                    .map(|channel| u8::MAX - ((u8::MAX - channel) as f64 * k_d) as u8),
-            };
-
-            if k_t > 0.0 && depth < MAX_SHADOW_DEPTH {
-                let entry_v = (light.position - blocking.point).normalize();
-                let transmission = transmission_ray(entry_v, &blocking);
+    let (s, _) = light.toward(point);
+
+    // Only shapes between the point and the light can cast a shadow
+    let mut ray = Ray::from_surface(point, normal, s).at_time(time);
+    ray.t_max = light.toward(ray.origin).1;
+
+    // Most shadow rays are either clear or blocked by something opaque, so
+    // check for any blocker before searching for the closest one
+    let mut color = &light.color * 1.0;
+    for _ in 0..MAX_SHADOW_DEPTH {
+        if !scene.occluded(&ray) {
+            return color;
+        }
 
-                // We were transmitting through the shape, so there should
-                // definitely be an exit point
-                let exit = blocking.shape.intersect(&transmission).unwrap();
+        let blocking = match scene.intersect(&ray) {
+            Some(blocking) => blocking,
+            None => return color,
+        };
 
-                color * trace_shadow(exit.point, blocking.shape, shapes, light, depth + 1) * k_t
-            } else if k_t > 0.0 {
-                color * k_t
-            } else {
-                blocking.color.diffuse() * 0.0
-            }
+        let k_t = blocking.color.transmission();
+        if k_t <= 0.0 {
+            return Rgb::new([0, 0, 0]);
         }
+
+        // Transmission color should only reduce the light color by the
+        // diffuse phong constant for the shape.
+        let (_, k_d, _) = blocking.color.phong_constants();
+        let tint = Rgb {
+            color: blocking
+                .color
+                .diffuse()
+                .color
+                .map(|channel| u8::MAX - ((u8::MAX - channel) as f64 * k_d) as u8),
+        };
+        color = tint * color * k_t;
+
+        // Carry on toward the light from the other side of the surface
+        ray = Ray::from_surface(blocking.point, blocking.normal, s).at_time(time);
+        ray.t_max = light.toward(ray.origin).1;
+    }
+
+    // Anything still in the way after that many surfaces is treated as
+    // blocking the light
+    if scene.occluded(&ray) {
+        Rgb::new([0, 0, 0])
+    } else {
+        color
     }
 }
 
@@ -319,6 +437,9 @@ fn trace_shadow(
 mod tests {
 
     use super::*;
+    use cgmath::vec3;
+    use floor::Floor;
+    use tracer::Background;
 
     // Tests multiplying the same color struct
     #[test]
@@ -394,4 +515,129 @@ mod tests {
         assert_eq!(Rgb::new([255, 40, 4]), (&color + &color) * 2);
         assert_eq!(Rgb::new([255, 40, 4]), (&color + &color) * 2.0);
     }
+
+    // Tests that shapes only cast shadows when they're between the point and
+    // the light
+    #[test]
+    fn shadow_bounded_by_light() {
+        let material = Material::new(Rgb::new([255, 0, 0]), (1.0, 1.0, 1.0), 0.0, 0.0, 0.0);
+        let mut scene = Scene::new(Background {
+            color: Rgb::new([0, 0, 0]),
+        });
+        scene.add(Box::new(Floor::new(
+            vec3(-1.0, -1.0, 1.0),
+            vec3(-1.0, 1.0, 1.0),
+            vec3(1.0, -1.0, 1.0),
+            vec3(1.0, 1.0, 1.0),
+            material.clone(),
+            material,
+        )));
+
+        let point = vec3(0.0, 0.0, 0.0);
+        let normal = vec3(0.0, 0.0, 1.0);
+        let color = Rgb::new([255, 255, 255]);
+
+        // Light in front of the floor isn't blocked by it
+        let light = Light::point(vec3(0.0, 0.0, 0.5), color.clone());
+        assert_eq!(color, trace_shadow(point, normal, 0.0, &scene, &light));
+
+        // Light behind the floor is
+        let light = Light::point(vec3(0.0, 0.0, 2.0), color.clone());
+        assert_eq!(Rgb::new([0, 0, 0]), trace_shadow(point, normal, 0.0, &scene, &light));
+
+        // Directional lights are behind everything in their direction
+        let light = Light::directional(vec3(0.0, 0.0, 1.0), color.clone());
+        assert_eq!(Rgb::new([0, 0, 0]), trace_shadow(point, normal, 0.0, &scene, &light));
+
+        let light = Light::directional(vec3(0.0, 0.0, -1.0), color.clone());
+        assert_eq!(color, trace_shadow(point, -normal, 0.0, &scene, &light));
+    }
+
+    // Square facing the origin at a distance along +Z
+    fn pane(z: f64, material: Material) -> Box<Floor> {
+        Box::new(Floor::new(
+            vec3(-1.0, -1.0, z),
+            vec3(-1.0, 1.0, z),
+            vec3(1.0, -1.0, z),
+            vec3(1.0, 1.0, z),
+            material.clone(),
+            material,
+        ))
+    }
+
+    // Tests that light through several panes of glass is dimmed by each one,
+    // and blocked by anything opaque behind them
+    #[test]
+    fn shadow_transmission() {
+        let glass = Material::new(Rgb::new([255, 255, 255]), (0.0, 0.0, 0.0), 0.0, 0.5, 1.0);
+        let opaque = Material::new(Rgb::new([255, 0, 0]), (1.0, 1.0, 1.0), 0.0, 0.0, 0.0);
+        let mut scene = Scene::new(Background {
+            color: Rgb::new([0, 0, 0]),
+        });
+
+        let point = vec3(0.0, 0.0, 0.0);
+        let normal = vec3(0.0, 0.0, 1.0);
+        let light = Light::point(vec3(0.0, 0.0, 4.0), Rgb::new([255, 255, 255]));
+
+        scene.add(pane(1.0, glass.clone()));
+        assert_eq!(Rgb::new([127, 127, 127]), trace_shadow(point, normal, 0.0, &scene, &light));
+
+        scene.add(pane(2.0, glass));
+        assert_eq!(Rgb::new([63, 63, 63]), trace_shadow(point, normal, 0.0, &scene, &light));
+
+        scene.add(pane(3.0, opaque.clone()));
+        assert_eq!(Rgb::new([0, 0, 0]), trace_shadow(point, normal, 0.0, &scene, &light));
+    }
+
+    // Tests that an opaque wall still blocks the light behind more panes of
+    // glass than are followed
+    #[test]
+    fn shadow_transmission_depth() {
+        let glass = Material::new(Rgb::new([255, 255, 255]), (0.0, 0.0, 0.0), 0.0, 1.0, 1.0);
+        let opaque = Material::new(Rgb::new([255, 0, 0]), (1.0, 1.0, 1.0), 0.0, 0.0, 0.0);
+        let mut scene = Scene::new(Background {
+            color: Rgb::new([0, 0, 0]),
+        });
+
+        let point = vec3(0.0, 0.0, 0.0);
+        let normal = vec3(0.0, 0.0, 1.0);
+        let light = Light::point(vec3(0.0, 0.0, 10.0), Rgb::new([255, 255, 255]));
+
+        for i in 0..MAX_SHADOW_DEPTH {
+            scene.add(pane(1.0 + i as f64, glass.clone()));
+        }
+        assert_eq!(light.color, trace_shadow(point, normal, 0.0, &scene, &light));
+
+        scene.add(pane(9.0, opaque));
+        assert_eq!(Rgb::new([0, 0, 0]), trace_shadow(point, normal, 0.0, &scene, &light));
+    }
+
+    // Tests that materials and lights blend every parameter between
+    // keyframes
+    #[test]
+    fn interpolate() {
+        let glass = Material::new(Rgb::new([255, 255, 255]), (0.0, 0.0, 0.0), 0.0, 1.0, 1.5);
+        let mirror = Material::new(Rgb::new([0, 0, 255]), (0.0, 1.0, 0.0), 1.0, 0.0, 1.0);
+
+        let halfway = glass.interpolate(&mirror, 0.5);
+        assert_ulps_eq!(0.5, halfway.reflection());
+        assert_ulps_eq!(0.5, halfway.transmission());
+        assert_ulps_eq!(1.25, halfway.refraction_index());
+        assert_eq!((0.0, 0.5, 0.0), halfway.phong_constants());
+        assert_eq!(&Rgb::new([128, 128, 255]), halfway.diffuse());
+        assert_eq!(mirror, glass.interpolate(&mirror, 1.0));
+
+        let from = Light::point(vec3(0.0, 0.0, 0.0), Rgb::new([255, 0, 0]));
+        let to = Light::point(vec3(2.0, 4.0, 0.0), Rgb::new([0, 0, 0]));
+        let light = from.interpolate(&to, 0.25);
+        assert_relative_eq!(vec3(0.5, 1.0, 0.0), light.position);
+        assert_eq!(Rgb::new([191, 0, 0]), light.color);
+
+        // Directional lights stay pointing somewhere, even between opposite
+        // directions
+        let from = Light::directional(vec3(0.0, 1.0, 0.0), Rgb::new([255, 255, 255]));
+        let to = Light::directional(vec3(0.0, -1.0, 0.0), Rgb::new([255, 255, 255]));
+        assert_relative_eq!(vec3(0.0, 1.0, 0.0), from.interpolate(&to, 0.5).position);
+        assert_relative_eq!(vec3(0.0, -1.0, 0.0), from.interpolate(&to, 0.75).position);
+    }
 }
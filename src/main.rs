@@ -1,4 +1,4 @@
-#[macro_use]
+#[cfg_attr(test, macro_use)]
 extern crate cgmath;
 extern crate chan;
 extern crate image;
@@ -8,45 +8,578 @@ extern crate time;
 mod tracer;
 mod sphere;
 mod floor;
+mod disk;
+mod cylinder;
+mod cone;
+mod torus;
+mod polynomial;
+mod csg;
+mod sdf;
+mod heightfield;
 mod ray;
+mod color;
+mod sampling;
+mod integrator;
+mod whitted;
+mod path;
+mod ao;
+mod kdtree;
+mod photon;
+mod debug;
+mod scene;
+mod environment;
+mod sky;
 mod light;
-
-use std::sync::Arc;
+mod camera;
+mod motion;
+mod animation;
+mod adaptive;
+mod stereo;
+mod sampler;
+mod progressive;
+mod navigation;
+
+use std::env;
+use std::path::Path;
+use std::sync::{mpsc, Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
-use std::sync::mpsc;
 use image::ConvertBuffer;
-use cgmath::vec3;
-use tracer::{Background, Shape};
+use image::FilterType;
+use cgmath::{vec3, Vector3};
+use piston_window::{Button, Key, MouseButton, MouseCursorEvent, MouseScrollEvent, PressEvent,
+                    ReleaseEvent, RenderEvent, UpdateEvent};
+use tracer::Background;
+use environment::{Backplate, EnvironmentMap, Gradient, Projection};
+use color::Color;
+use sky::Sky;
+use integrator::{Integrator, Settings};
 use sphere::Sphere;
 use floor::Floor;
-use ray::Ray;
+use camera::{Camera, Fisheye, Orthographic, Panorama, Perspective, Posed, Shutter};
+use stereo::{Layout, Stereo};
+use motion::{Moving, Transform};
+use animation::{frame_path, Interpolation, Track};
+use adaptive::{Adaptive, Corners};
+use progressive::{Film, Pixel, TILE_SIZE};
+use navigation::{Navigation, FLY_SPEED, LOOK_SPEED};
+use scene::{Scene, ShapeId};
 use light::{Light, Material, Rgb};
 
-const IMAGE_PLANE: f64 = 0.5;
+// Rays averaged through each pixel when the camera has an aperture or
+// something moves, unless set with --lens-samples
+const LENS_SAMPLES: u32 = 16;
+
+// Size of the rendered image, unless set with --width and --height
+const IMAGE_SIZE: u32 = 640;
+
+// Threads tracing pixels at the same time
+const WORKERS: usize = 4;
+
+// Where the object ID image is saved when requested with --object-ids
+const OBJECT_ID_PATH: &str = "object_ids.png";
+
+// Where the image of how many camera rays each pixel took is saved when
+// requested with --sample-counts
+const SAMPLE_COUNT_PATH: &str = "sample_counts.png";
+
+// Middle of the green sphere, which the animated camera circles around, as
+// does the camera in the window until it's moved
+const PIVOT: [f64; 3] = [0.0, 0.0, 1.5];
+
+// Times smaller the preview is in each direction, shown while the full image
+// is traced after the camera moves in the window
+const PREVIEW_SCALE: u32 = 4;
+
+// Pixels the mouse can move while a button is held and still count as a click
+const CLICK_DISTANCE: f64 = 3.0;
+
+
+// Keyframes for the animation rendered with --frames. The camera circles once
+// around the green sphere like a turntable, while the sphere hops up and turns
+// from glass to a mirror and back, and the light swings across the scene.
+struct Animation {
+    camera: Track<Transform>,
+    light: Track<Light>,
+    sphere: Track<Transform>,
+    material: Track<Material>,
+}
+
+impl Animation {
+    fn new(first: u32, last: u32) -> Animation {
+        let first = first as f64;
+        let length = (last as f64 - first).max(1.0);
+        let frame = |fraction: f64| first + length * fraction;
+
+        // Quarter turns, so every rotation goes the right way around
+        let pivot = Vector3::from(PIVOT);
+        let camera = (0..5).fold(Track::new(), |track, quarter| {
+            let turn = Transform {
+                translation: pivot,
+                ..Transform::rotation(vec3(0.0, 1.0, 0.0), 90.0 * quarter as f64)
+            };
+            track.key(frame(quarter as f64 / 4.0), turn, Interpolation::Linear)
+        });
+
+        let white = Rgb::new([255, 255, 255]);
+        let light = Track::new()
+            .key(frame(0.0), Light::point(vec3(2.0, 3.0, -4.0), white.clone()), Interpolation::ease())
+            .key(frame(1.0), Light::point(vec3(-2.0, 3.0, -4.0), white), Interpolation::ease());
+
+        let sphere = Track::new()
+            .key(frame(0.0), Transform::identity(), Interpolation::ease())
+            .key(frame(0.5), Transform::translation(vec3(0.0, 0.5, 0.0)), Interpolation::ease())
+            .key(frame(1.0), Transform::identity(), Interpolation::ease());
+
+        let glass = green_glass();
+        let mirror = Material::new(Rgb::new([0, 255, 0]), (0.075, 0.5, 0.2), 0.9, 0.0, 1.0);
+        let material = Track::new()
+            .key(frame(0.0), glass.clone(), Interpolation::ease())
+            .key(frame(0.5), mirror, Interpolation::ease())
+            .key(frame(1.0), glass, Interpolation::ease());
+
+        Animation {
+            camera,
+            light,
+            sphere,
+            material,
+        }
+    }
+}
 
 
 fn main() {
-    let background = Arc::new(Background {
+    let mut settings = Settings::new();
+    if let Some(samples) = arg_value("--ao-samples").and_then(|s| s.parse().ok()) {
+        settings.ambient_occlusion.samples = samples;
+    }
+    if let Some(distance) = arg_value("--ao-distance").and_then(|d| d.parse().ok()) {
+        settings.ambient_occlusion.max_distance = distance;
+    }
+    if let Some(photons) = arg_value("--caustic-photons").and_then(|p| p.parse().ok()) {
+        settings.caustic_photons = photons;
+    }
+
+    // Integrators hold on to what they've prepared for a scene, so every frame
+    // gets a new one
+    let integrator_name = arg_value("--integrator").unwrap_or_else(|| "whitted".to_string());
+    if integrator::from_name(&integrator_name, &settings).is_none() {
+        println!("Unknown integrator: {}", integrator_name);
+        return;
+    }
+    let new_integrator = || integrator::from_name(&integrator_name, &settings).unwrap();
+
+    // Where the random numbers for each pixel come from. The same seed always
+    // gives the same image.
+    let sampler_name = arg_value("--sampler").unwrap_or_else(|| "independent".to_string());
+    if sampler::from_name(&sampler_name, 1, 0).is_none() {
+        println!("Unknown sampler: {}", sampler_name);
+        return;
+    }
+    let seed = arg_value("--seed").and_then(|s| s.parse().ok()).unwrap_or(0);
+
+    let width = arg_value("--width").and_then(|w| w.parse().ok()).unwrap_or(IMAGE_SIZE).max(1);
+    let height = arg_value("--height").and_then(|h| h.parse().ok()).unwrap_or(IMAGE_SIZE).max(1);
+
+    let mut shutter = Shutter::new();
+    if let Some(open) = arg_value("--shutter-open").and_then(|o| o.parse().ok()) {
+        shutter.open = open;
+    }
+    if let Some(close) = arg_value("--shutter-close").and_then(|c| c.parse().ok()) {
+        shutter.close = close;
+    }
+
+    // How far the green sphere moves over the frame
+    let motion = match arg_value("--motion") {
+        Some(offset) => {
+            let offset: Vec<f64> = offset.split(',').filter_map(|o| o.parse().ok()).collect();
+            if offset.len() != 3 {
+                println!("Motion needs an offset, like 0.5,0,0");
+                return;
+            }
+            Some(vec3(offset[0], offset[1], offset[2]))
+        }
+        None => None,
+    };
+
+    // Render both eyes into one image, twice as wide or twice as tall. Separate
+    // files are split from a side by side render when it's saved.
+    let stereo = arg_value("--stereo");
+    let layout = match stereo.as_ref().map(|s| s.as_str()) {
+        None => None,
+        Some("side-by-side") | Some("separate") => Some(Layout::SideBySide),
+        Some("over-under") => Some(Layout::OverUnder),
+        Some(name) => {
+            println!("Unknown stereo layout: {}", name);
+            return;
+        }
+    };
+    let separate = stereo.as_ref().map_or(false, |s| s == "separate");
+
+    // Range of frames to render as an image sequence, like 1-48
+    let frames = match arg_value("--frames") {
+        Some(range) => {
+            let bounds: Vec<u32> = range.split('-').filter_map(|f| f.parse().ok()).collect();
+            match bounds.len() {
+                1 => Some((bounds[0], bounds[0])),
+                2 if bounds[0] <= bounds[1] => Some((bounds[0], bounds[1])),
+                _ => {
+                    println!("Frames need a range, like 1-48");
+                    return;
+                }
+            }
+        }
+        None => None,
+    };
+
+    if separate && frames.is_none() && arg_value("--output").is_none() {
+        println!("Separate stereo images need an --output path");
+        return;
+    }
+
+    // Stereo cameras render both eyes side by side or one over the other, so
+    // each eye keeps the size that was asked for
+    let (eye_width, eye_height) = (width, height);
+    let (camera, width, height) = match build_camera(eye_width, eye_height, layout, None) {
+        Ok(camera) => camera,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    let aperture = arg_value("--aperture").and_then(|a| a.parse::<f64>().ok()).unwrap_or(0.0);
+    let moving = motion.is_some() || frames.is_some();
+    let default_samples = if aperture > 0.0 || moving { LENS_SAMPLES } else { 1 };
+    let lens_samples = arg_value("--lens-samples")
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(default_samples)
+        .max(1);
+
+    // Only take extra samples where pixels have edges or detail
+    let adaptive = if env::args().any(|arg| arg == "--adaptive") {
+        let mut adaptive = Adaptive::new();
+        if let Some(threshold) = arg_value("--adaptive").and_then(|t| t.parse().ok()) {
+            adaptive.threshold = threshold;
+        }
+        if let Some(depth) = arg_value("--adaptive-depth").and_then(|d| d.parse().ok()) {
+            adaptive.max_depth = depth;
+        }
+        Some(adaptive)
+    } else {
+        None
+    };
+    let sampling = Sampling {
+        lens_samples,
+        shutter,
+        adaptive,
+        sampler: sampler_name,
+        seed,
+        passes: arg_value("--passes").and_then(|p| p.parse().ok()).unwrap_or(1).max(1),
+
+        // Only the window picks shapes or saves them with --object-ids
+        ids: frames.is_none(),
+    };
+
+    // Write out each frame of the animation, in place of the window
+    if let Some((first, last)) = frames {
+        let animation = Animation::new(first, last);
+        let directory = arg_value("--output").unwrap_or_else(|| ".".to_string());
+
+        for frame in first..last + 1 {
+            let start = time::precise_time_ns();
+
+            let time = frame as f64;
+            let scene = build_scene(Some((&animation, time)), motion);
+            let camera = build_camera(eye_width, eye_height, layout, animation.camera.at(time));
+            let (scene, (camera, _, _)) = match (scene, camera) {
+                (Ok(scene), Ok(camera)) => (scene, camera),
+                (Err(e), _) | (_, Err(e)) => {
+                    println!("{}", e);
+                    return;
+                }
+            };
+
+            let mut integrator = new_integrator();
+            integrator.prepare(&scene);
+
+            let film = Arc::new(Mutex::new(Film::new(width, height)));
+            let stop = Arc::new(AtomicBool::new(false));
+            let (scene, integrator, camera) = (Arc::new(scene), Arc::new(integrator), Arc::new(camera));
+            render(&scene, &integrator, &camera, &film, &sampling, &stop);
+
+            let mut image = film.lock().unwrap().image();
+
+            let path = Path::new(&directory).join(frame_path(frame));
+            save(&mut image, &path.to_string_lossy(), separate);
+            println!("Time to render frame {}: {} ms", frame, (time::precise_time_ns() - start) / 1000000);
+        }
+        return;
+    }
+
+    let scene = match build_scene(None, motion) {
+        Ok(scene) => scene,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    // Measure render speed
+    let mut start = time::precise_time_ns();
+
+    let (views_tx, views_rx) = mpsc::channel();
+    let rendering = render_views(scene, new_integrator(), sampling.clone(), views_rx);
+
+    let view = View::new(camera, width, height);
+    let (mut preview, mut film, mut stop) = (
+        Arc::clone(&view.preview),
+        Arc::clone(&view.film),
+        Arc::clone(&view.stop),
+    );
+    views_tx.send(view).unwrap();
+
+    // Set up the window for rendering, which shows the film as it fills in.
+    // Without one, the render can still be saved once it's done.
+    let window = piston_window::WindowSettings::new("RustTracer", [width, height])
+        .exit_on_esc(true)
+        .build();
+    let mut window: piston_window::PistonWindow = match window {
+        Ok(window) => window,
+        Err(e) => {
+            println!("Failed to open window: {}", e);
+            drop(views_tx);
+            rendering.join().unwrap();
+            println!("Time to compute pixels: {} ms", (time::precise_time_ns() - start) / 1000000);
+            save_finished(&film.lock().unwrap(), separate);
+            return;
+        }
+    };
+
+    let mut texture = piston_window::Texture::from_image(
+        &mut window.factory,
+        &film.lock().unwrap().image().convert(),
+        &piston_window::TextureSettings::new(),
+    ).unwrap();
+
+    println!("Number of threads: {}", WORKERS);
+    println!(
+        "Drag to orbit, right drag to pan, scroll to zoom, W A S D Q E to fly, \
+         arrow keys to look around, R to reset the camera and click to pick"
+    );
+
+    let home = Navigation::new(Vector3::from(PIVOT), Perspective::new().position);
+    let mut navigation = home;
+    let mut rendered = home;
+
+    // Event loop
+    let mut cursor = [0.0, 0.0];
+    let mut dragging: Option<(MouseButton, f64)> = None;
+    let mut held: Vec<Key> = vec![];
+    let mut shown = None;
+    let mut finished = false;
+    while let Some(e) = window.next() {
+        // Dragging only moves the camera once the mouse has gone far enough
+        // that it couldn't have been a click
+        if let Some([x, y]) = e.mouse_cursor_args() {
+            let (dx, dy) = (x - cursor[0], y - cursor[1]);
+            cursor = [x, y];
+
+            if let Some((button, distance)) = dragging {
+                let distance = distance + dx.abs() + dy.abs();
+                dragging = Some((button, distance));
+                if distance >= CLICK_DISTANCE {
+                    match button {
+                        MouseButton::Left => navigation.orbit(dx, dy),
+                        _ => navigation.pan(dx, dy),
+                    }
+                }
+            }
+        }
+        e.mouse_scroll(|_, notches| navigation.zoom(notches));
+
+        match e.press_args() {
+            Some(Button::Mouse(button)) => dragging = Some((button, 0.0)),
+            Some(Button::Keyboard(Key::R)) => navigation = home,
+            Some(Button::Keyboard(key)) => {
+                if !held.contains(&key) {
+                    held.push(key);
+                }
+            }
+            _ => {}
+        }
+
+        match e.release_args() {
+            // Clicking on the image reports which shape is under the cursor
+            Some(Button::Mouse(MouseButton::Left)) => {
+                let (x, y) = (cursor[0] as u32, cursor[1] as u32);
+                let click = dragging.map_or(false, |(_, distance)| distance < CLICK_DISTANCE);
+                if click && x < width && y < height {
+                    match film.lock().unwrap().ids()[(y * width + x) as usize] {
+                        Some(ShapeId(id)) => println!("Picked shape {} at ({}, {})", id, x, y),
+                        None => println!("Picked background at ({}, {})", x, y),
+                    }
+                }
+                dragging = None;
+            }
+            Some(Button::Mouse(_)) => dragging = None,
+            Some(Button::Keyboard(key)) => held.retain(|&k| k != key),
+            _ => {}
+        }
+
+        // Keys fly and turn the camera for as long as they're held
+        if let Some(args) = e.update_args() {
+            let axis = |positive: Key, negative: Key| {
+                held.contains(&positive) as i32 as f64 - held.contains(&negative) as i32 as f64
+            };
+
+            let step = FLY_SPEED * args.dt;
+            let (right, up) = (axis(Key::D, Key::A), axis(Key::E, Key::Q));
+            let ahead = axis(Key::W, Key::S);
+            if right != 0.0 || up != 0.0 || ahead != 0.0 {
+                navigation.fly(right * step, up * step, ahead * step);
+            }
+
+            let turn = LOOK_SPEED * args.dt;
+            let (right, up) = (axis(Key::Right, Key::Left), axis(Key::Up, Key::Down));
+            if right != 0.0 || up != 0.0 {
+                navigation.look(right * turn, up * turn);
+            }
+        }
+
+        if e.render_args().is_some() {
+            // Start over from a preview whenever the camera has moved, at most
+            // once a frame. The camera already built once with the same
+            // settings, so it can't fail.
+            if navigation != rendered {
+                rendered = navigation;
+                stop.store(true, Ordering::Relaxed);
+
+                let pose = Some(navigation.transform());
+                let (camera, _, _) = build_camera(eye_width, eye_height, layout, pose).unwrap();
+                let view = View::new(camera, width, height);
+                preview = Arc::clone(&view.preview);
+                film = Arc::clone(&view.film);
+                stop = Arc::clone(&view.stop);
+                views_tx.send(view).unwrap();
+
+                start = time::precise_time_ns();
+                shown = None;
+                finished = false;
+            }
+
+            // Copy whatever tiles have finished since the last frame, with the
+            // preview showing through where the full image hasn't reached yet.
+            // The films only hold whole tiles, so the image never shows half
+            // of one.
+            let film = film.lock().unwrap();
+            let preview = preview.lock().unwrap();
+            let changes = Some((preview.changes(), film.changes()));
+            if changes != shown {
+                shown = changes;
+                let under =
+                    image::imageops::resize(&preview.image(), width, height, FilterType::Nearest);
+                texture.update(&mut window.encoder, &film.image_over(&under).convert()).unwrap();
+            }
+
+            if !finished && film.passes() == sampling.passes {
+                finished = true;
+                println!("Time to compute pixels: {} ms", (time::precise_time_ns() - start) / 1000000);
+                save_finished(&film, separate);
+            }
+        }
+
+        window.draw_2d(&e, |c, g| {
+            // Clear the screen
+            piston_window::clear([0.0; 4], g);
+
+            // Render the traced image to the window
+            piston_window::image(&texture, c.transform, g);
+        });
+    }
+}
+
+// Saves what was asked for on the command line once every pass is done: the
+// render itself, an image of the shape seen through each pixel, and one of how
+// many camera rays each pixel took
+fn save_finished(film: &Film, separate: bool) {
+    // Keep the render, which is how panoramas get to other tools
+    if let Some(path) = arg_value("--output") {
+        save(&mut film.image(), &path, separate);
+    }
+
+    let (width, height) = (film.width, film.height);
+
+    // Color each pixel by the shape it shows, leaving the background black
+    if env::args().any(|arg| arg == "--object-ids") {
+        let ids = film.ids();
+        let object_ids = image::RgbImage::from_fn(width, height, |x, y| {
+            ids[(y * width + x) as usize].map_or(image::Rgb([0, 0, 0]), |id| id.color().color)
+        });
+
+        match object_ids.save(OBJECT_ID_PATH) {
+            Ok(_) => println!("Saved object IDs to {}", OBJECT_ID_PATH),
+            Err(e) => println!("Failed to save object IDs: {}", e),
+        }
+    }
+
+    // Shade each pixel by how many camera rays it took, from black for the
+    // fewest to white for the most
+    if env::args().any(|arg| arg == "--sample-counts") {
+        let samples = film.rays();
+        let (fewest, most) = (
+            *samples.iter().min().unwrap_or(&0),
+            *samples.iter().max().unwrap_or(&0),
+        );
+        let range = (most - fewest).max(1) as f64;
+
+        let sample_counts = image::GrayImage::from_fn(width, height, |x, y| {
+            let count = samples[(y * width + x) as usize];
+            image::Luma([((count - fewest) as f64 / range * 255.0).round() as u8])
+        });
+
+        match sample_counts.save(SAMPLE_COUNT_PATH) {
+            Ok(_) => println!("Saved sample counts to {} ({} to {} rays per pixel)", SAMPLE_COUNT_PATH, fewest, most),
+            Err(e) => println!("Failed to save sample counts: {}", e),
+        }
+    }
+}
+
+// Material of the green glass sphere in the middle of the scene
+fn green_glass() -> Material {
+    Material::new(
+        Rgb::new([0, 255, 0]),
+        (0.075, 0.5, 0.2),
+        0.01,
+        0.85,
+        0.95,
+    )
+}
+
+// Builds the scene, posed for a frame of the animation if there is one
+fn build_scene(animation: Option<(&Animation, f64)>, motion: Option<Vector3<f64>>) -> Result<Scene, String> {
+    let mut scene = Scene::new(Background {
         color: Rgb::new([0, 175, 215]),
     });
 
+    // Surround the scene with a photograph instead of a flat color
+    if let Some(path) = arg_value("--environment") {
+        match EnvironmentMap::open(&path) {
+            Ok(environment) => scene.background = Box::new(environment),
+            Err(e) => return Err(format!("Failed to load environment map {}: {}", path, e)),
+        }
+    }
+
     let sphere1 = Sphere::new(
         vec3(-0.87, -0.5, 2.25),
         0.45,
         Material::new(Rgb::new([179, 179, 179]), (0.15, 0.25, 1.0), 0.75, 0.0, 0.0),
     );
 
-    let sphere2 = Sphere::new(
-        vec3(0.0, 0.0, 1.5),
-        0.5,
-        Material::new(
-            Rgb::new([0, 255, 0]),
-            (0.075, 0.5, 0.2),
-            0.01,
-            0.85,
-            0.95,
-        ),
-    );
+    let material = animation
+        .and_then(|(animation, frame)| animation.material.at(frame))
+        .unwrap_or_else(green_glass);
+    let sphere2 = Sphere::new(vec3(0.0, 0.0, 1.5), 0.5, material);
 
     let floor = Floor::new(
         vec3(-2.0, -2.0, 0.0),
@@ -59,121 +592,398 @@ fn main() {
     let floor = floor.rotate_x(65.0);
     let floor = floor.translate(vec3(-1.0, -1.25, 2.0));
 
-    let shapes: Arc<Vec<Box<Shape>>> =
-        Arc::new(vec![Box::new(sphere1), Box::new(sphere2), Box::new(floor)]);
+    // The green sphere moves from where it is at the start of the frame to
+    // where it is at the start of the next, plus any extra motion
+    let (start, mut end) = match animation {
+        Some((animation, frame)) => (
+            animation.sphere.at(frame).unwrap(),
+            animation.sphere.at(frame + 1.0).unwrap(),
+        ),
+        None => (Transform::identity(), Transform::identity()),
+    };
+    if let Some(offset) = motion {
+        end.translation += offset;
+    }
 
-    let light1 = Light {
-        position: vec3(2.0, 3.0, -4.0),
-        color: Rgb::new([255, 255, 255]),
+    scene.add(Box::new(sphere1));
+    if start == end {
+        scene.add(Box::new(sphere2));
+    } else {
+        scene.add(Box::new(Moving::new(Box::new(sphere2), start, end)));
+    }
+    scene.add(Box::new(floor));
+
+    let light = animation
+        .and_then(|(animation, frame)| animation.light.at(frame))
+        .unwrap_or_else(|| Light::point(vec3(2.0, 3.0, -4.0), Rgb::new([255, 255, 255])));
+    scene.lights.push(light);
+
+    // Show a backdrop to the camera without changing what the scene reflects
+    if let Some(path) = arg_value("--backplate") {
+        match Backplate::open(&path, Perspective::new().view()) {
+            Ok(backplate) => scene.camera_background = Some(Box::new(backplate)),
+            Err(e) => return Err(format!("Failed to load backplate {}: {}", path, e)),
+        }
+    }
+    if let Some(colors) = arg_value("--gradient") {
+        let channels: Vec<u8> = colors.split(',').filter_map(|c| c.parse().ok()).collect();
+        if channels.len() != 6 {
+            return Err("Gradient needs top and bottom colors, like 255,255,255,96,96,96".to_string());
+        }
+
+        let color = |c: &[u8]| Color::from_rgb(&Rgb::new([c[0], c[1], c[2]]));
+        scene.camera_background = Some(Box::new(Gradient {
+            top: color(&channels[0..3]),
+            bottom: color(&channels[3..6]),
+        }));
+    }
+
+    // Light the scene outdoors, with the sun in place of the point light
+    if env::args().any(|arg| arg == "--sky") {
+        let elevation = arg_value("--sun-elevation").and_then(|e| e.parse().ok()).unwrap_or(45.0);
+        let azimuth = arg_value("--sun-azimuth").and_then(|a| a.parse().ok()).unwrap_or(150.0);
+        let turbidity = arg_value("--turbidity").and_then(|t| t.parse().ok()).unwrap_or(3.0);
+
+        let sky = Sky::new(elevation, azimuth, turbidity);
+        scene.lights = vec![sky.sun()];
+        scene.background = Box::new(sky);
+    }
+
+    Ok(scene)
+}
+
+// Builds the camera chosen with --camera for an image of the given size,
+// moved into place if it's posed. Returns the camera along with the size of
+// the whole image, which holds both eyes for stereo.
+fn build_camera(
+    width: u32,
+    height: u32,
+    layout: Option<Layout>,
+    pose: Option<Transform>,
+) -> Result<(Box<Camera>, u32, u32), String> {
+    let aspect = width as f64 / height as f64;
+
+    let mut perspective = Perspective::new();
+    perspective.aspect = aspect;
+    if let Some(aperture) = arg_value("--aperture").and_then(|a| a.parse().ok()) {
+        perspective.aperture = aperture;
+    }
+    if let Some(distance) = arg_value("--focus-distance").and_then(|d| d.parse().ok()) {
+        perspective.focus_distance = distance;
+    }
+    if let Some(blades) = arg_value("--aperture-blades").and_then(|b| b.parse().ok()) {
+        perspective.blades = blades;
+    }
+
+    // Posed cameras start out relative to the pivot they turn around
+    let position = match pose {
+        Some(_) => perspective.position - Vector3::from(PIVOT),
+        None => perspective.position,
+    };
+    perspective.position = position;
+
+    let camera: Box<Camera> = match arg_value("--camera").as_ref().map(|c| c.as_str()) {
+        None | Some("perspective") => Box::new(perspective),
+        Some("orthographic") => {
+            let size = arg_value("--view-size").and_then(|s| s.parse().ok()).unwrap_or(4.0);
+            Box::new(Orthographic {
+                position,
+                width: size * aspect,
+                height: size,
+            })
+        }
+        Some("fisheye") => {
+            let fov: f64 = arg_value("--fov").and_then(|f| f.parse().ok()).unwrap_or(180.0);
+            Box::new(Fisheye {
+                position,
+                field_of_view: fov.to_radians(),
+                aspect,
+            })
+        }
+        Some("equirectangular") => Box::new(Panorama {
+            position,
+            projection: Projection::Equirectangular,
+        }),
+        Some("cube") => Box::new(Panorama {
+            position,
+            projection: Projection::Cube,
+        }),
+        Some(name) => return Err(format!("Unknown camera: {}", name)),
     };
 
-    let lights: Arc<Vec<Light>> = Arc::new(vec![light1]);
+    let camera: Box<Camera> = match pose {
+        Some(transform) => Box::new(Posed { camera, transform }),
+        None => camera,
+    };
 
-    // Create the raw image buffer
-    let mut image = image::RgbImage::from_pixel(640, 640, image::Rgb([255, 0, 0]));
+    match layout {
+        Some(layout) => {
+            let mut stereo = Stereo::new(camera, layout);
+            if let Some(separation) = arg_value("--eye-separation").and_then(|s| s.parse().ok()) {
+                stereo.separation = separation;
+            }
+            if let Some(convergence) = arg_value("--convergence").and_then(|c| c.parse().ok()) {
+                stereo.convergence = convergence;
+            }
 
-    let height = image.height();
+            match layout {
+                Layout::SideBySide => Ok((Box::new(stereo), width * 2, height)),
+                Layout::OverUnder => Ok((Box::new(stereo), width, height * 2)),
+            }
+        }
+        None => Ok((camera, width, height)),
+    }
+}
 
-    let dx = 1.0 / image.width() as f64;
-    let dy = 1.0 / image.height() as f64;
+// How the camera rays through each pixel are picked
+#[derive(Clone)]
+struct Sampling {
+    // Rays through each point on the image, from across the lens and over
+    // the time the shutter is open
+    lens_samples: u32,
+    shutter: Shutter,
 
-    // Set up computation channel
-    let (compute_tx, compute_rx) = chan::async();
+    // Points on the image each pixel is split into, if it's sampled more
+    // where it has edges. Otherwise each pixel is a single point.
+    adaptive: Option<Adaptive>,
 
-    // Set up color result channel
-    let (color_tx, color_rx) = mpsc::channel();
+    // Name of the sampler behind every random number, and its seed
+    sampler: String,
+    seed: u32,
 
-    // Measure render speed
-    let start = time::precise_time_ns();
+    // Times every pixel is traced, with the results averaged together
+    passes: u32,
 
-    // Queue up all the pixels whose color needs to be calculated
-    for (real_xpixel, real_ypixel, _) in image.enumerate_pixels() {
-        // enumerate_pixels_mut() iterates from top to bottom and left to right,
-        // rather than bottom to top, left to right. Rather than reworking the
-        // ray calculations, just figure out the pixel coordinates we actually
-        // want to calculate.
-        let xpixel = real_xpixel;
-        let ypixel = height - real_ypixel;
+    // Whether to find the shape seen through the middle of each pixel, which
+    // takes another trip through the scene
+    ids: bool,
+}
 
-        let x = -0.5 + (xpixel as f64) * dx;
-        let y = -0.5 + (ypixel as f64) * dy;
+impl Sampling {
+    // Samples each pixel can take in one pass, given how many each ray takes
+    // up, so the next pass starts after them
+    fn samples_per_pass(&self, per_ray: u32) -> u32 {
+        let points = self.adaptive.map_or(1, |adaptive| {
+            let corners = (1 << adaptive.max_depth) + 1;
+            corners * corners
+        });
+        points * self.lens_samples * per_ray
+    }
+}
 
-        let r = Ray::new(vec3(0.0, 0.0, 0.0), vec3(x, y, IMAGE_PLANE));
+// Traces the image into the film on several threads, a tile at a time, so it
+// can be shown while it's still coming in. Each pass covers every tile once.
+// Setting stop abandons the tiles that haven't been started yet.
+fn render(
+    scene: &Arc<Scene>,
+    integrator: &Arc<Box<Integrator>>,
+    camera: &Arc<Box<Camera>>,
+    film: &Arc<Mutex<Film>>,
+    sampling: &Sampling,
+    stop: &Arc<AtomicBool>,
+) {
+    let (width, height) = {
+        let film = film.lock().unwrap();
+        (film.width, film.height)
+    };
+    let dx = 1.0 / width as f64;
+    let dy = 1.0 / height as f64;
 
-        compute_tx.send((real_xpixel, real_ypixel, r));
+    // Queue up every tile for every pass
+    let (compute_tx, compute_rx) = chan::async();
+    for pass in 0..sampling.passes {
+        for tile in progressive::tiles(width, height, TILE_SIZE) {
+            compute_tx.send((pass, tile));
+        }
     }
     drop(compute_tx);
 
     // Calculate colors
     let mut workers = vec![];
-    for _ in 0..4 {
+    for _ in 0..WORKERS {
         let rx = compute_rx.clone();
-        let tx = color_tx.clone();
-
-        let s = Arc::clone(&shapes);
-        let l = Arc::clone(&lights);
-        let bg = Arc::clone(&background);
-        workers.push(thread::spawn(move || loop {
-            match rx.recv() {
-                Some((xpixel, ypixel, r)) => {
-                    let color = tracer::illuminate(r, &s, &l, &bg, None, 1).color;
-                    tx.send((xpixel, ypixel, color)).unwrap();
+        let film = Arc::clone(film);
+        let stop = Arc::clone(stop);
+
+        let s = Arc::clone(scene);
+        let integrator = Arc::clone(integrator);
+        let camera = Arc::clone(camera);
+        let sampling = sampling.clone();
+        workers.push(thread::spawn(move || {
+            let (name, seed) = (&sampling.sampler, sampling.seed);
+            let per_ray = integrator.samples();
+            let strata = sampling.lens_samples * per_ray;
+            let mut sampler = sampler::from_name(name, strata, seed).unwrap();
+
+            while let Some((pass, tile)) = rx.recv() {
+                if stop.load(Ordering::Relaxed) {
+                    break;
+                }
+
+                let first_sample = pass * sampling.samples_per_pass(per_ray);
+                let mut pixels = vec![];
+
+                // Neighboring pixels in the tile share the corners they
+                // sample when sampling adaptively
+                let mut corners = Corners::new();
+                for real_ypixel in tile.y..tile.y + tile.height {
+                    for xpixel in tile.x..tile.x + tile.width {
+                        // Tiles count rows from the top, but the ray
+                        // calculations count them from the bottom
+                        let ypixel = height - real_ypixel;
+                        let x = -0.5 + (xpixel as f64) * dx;
+                        let y = -0.5 + (ypixel as f64) * dy;
+
+                        let id = if sampling.ids {
+                            camera.ray(x, y, (0.0, 0.0)).and_then(|r| s.pick(&r))
+                        } else {
+                            None
+                        };
+                        sampler.start_pixel(xpixel, real_ypixel, first_sample);
+
+                        // Average rays from across the lens and over the time
+                        // the shutter is open, to blur anything out of focus or
+                        // moving. Points the camera can't see through stay
+                        // black.
+                        let mut color_at = |x: f64, y: f64| {
+                            let mut total = Color::black();
+                            for _ in 0..sampling.lens_samples {
+                                let (u, v) = sampler.next_2d();
+                                let lens = camera.sample_lens(u, v);
+                                let time = sampling.shutter.time(sampler.next_1d());
+                                // Skip the samples the integrator would have
+                                // used when there's no ray to trace
+                                let skip = match camera.ray(x, y, lens) {
+                                    Some(r) => {
+                                        let r = r.at_time(time);
+                                        let radiance = integrator.radiance(&r, &s, &mut *sampler);
+                                        total += Color::from_rgb(&radiance);
+                                        1
+                                    }
+                                    None => per_ray,
+                                };
+                                for _ in 0..skip {
+                                    sampler.next_sample();
+                                }
+                            }
+                            total / sampling.lens_samples as f64
+                        };
+
+                        let (color, points) = match sampling.adaptive {
+                            Some(adaptive) => {
+                                let pixel = (xpixel, ypixel);
+                                adaptive.sample(&mut corners, pixel, (x, y), (dx, dy), color_at)
+                            }
+                            None => (color_at(x, y), 1),
+                        };
+                        pixels.push(Pixel {
+                            color,
+                            id,
+                            rays: points * sampling.lens_samples,
+                        });
+                    }
                 }
-                None => break,
+
+                film.lock().unwrap().add(&tile, &pixels);
             }
         }));
     }
 
-    let num_workers = workers.len();
-
-    // Wait for worker threads to finish
     for worker in workers {
         worker.join().unwrap();
     }
+}
 
-    let compute = time::precise_time_ns();
+// Camera placement to show in the window, along with the films it fills in: a
+// quick preview at a fraction of the resolution, then the full image. Setting
+// stop abandons it for a newer one.
+struct View {
+    camera: Box<Camera>,
+    preview: Arc<Mutex<Film>>,
+    film: Arc<Mutex<Film>>,
+    stop: Arc<AtomicBool>,
+}
 
-    // Render pixels
-    loop {
-        match color_rx.try_recv() {
-            Ok((xpixel, ypixel, color)) => {
-                *image.get_pixel_mut(xpixel, ypixel) = color;
-            }
-            Err(_) => break,
+impl View {
+    fn new(camera: Box<Camera>, width: u32, height: u32) -> View {
+        let preview = Film::new((width / PREVIEW_SCALE).max(1), (height / PREVIEW_SCALE).max(1));
+        View {
+            camera,
+            preview: Arc::new(Mutex::new(preview)),
+            film: Arc::new(Mutex::new(Film::new(width, height))),
+            stop: Arc::new(AtomicBool::new(false)),
         }
     }
+}
 
-    let render = time::precise_time_ns();
-
-    // Set up the window for rendering
-    let mut window: piston_window::PistonWindow =
-        piston_window::WindowSettings::new("RustTracer", [640, 640])
-            .exit_on_esc(true)
-            .build()
-            .unwrap();
-
-    // Generate a texture so the image buffer can be rendered to the screen
-    let texture = piston_window::Texture::from_image(
-        &mut window.factory,
-        &image.convert(),
-        &piston_window::TextureSettings::new(),
-    ).unwrap();
+// Prepares the integrator on a background thread, then renders each view sent
+// from the window in turn. Views that were replaced while they waited are
+// skipped. The thread finishes once the window stops sending views.
+fn render_views(
+    scene: Scene,
+    mut integrator: Box<Integrator>,
+    sampling: Sampling,
+    views: mpsc::Receiver<View>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        integrator.prepare(&scene);
+        let scene = Arc::new(scene);
+        let integrator = Arc::new(integrator);
+
+        // Previews only need a rough idea of the scene, and need it quickly
+        let preview = Sampling {
+            lens_samples: 1,
+            adaptive: None,
+            passes: 1,
+            ids: false,
+            ..sampling.clone()
+        };
+
+        for view in views {
+            if view.stop.load(Ordering::Relaxed) {
+                continue;
+            }
 
-    let display = time::precise_time_ns();
+            let camera = Arc::new(view.camera);
+            render(&scene, &integrator, &camera, &view.preview, &preview, &view.stop);
+            render(&scene, &integrator, &camera, &view.film, &sampling, &view.stop);
+        }
+    })
+}
 
-    println!("Number of threads: {}", num_workers);
-    println!("Time to compute pixels: {} ms", (compute - start) / 1000000);
-    println!("Time to render pixels: {} ms", (render - compute) / 1000000);
-    println!("Time to display result: {} ms", (display - render) / 1000000);
+// Saves a render, splitting it into one file per eye for separate stereo
+// images
+fn save(image: &mut image::RgbImage, path: &str, separate: bool) {
+    let saved = if separate {
+        let (eye, height) = (image.width() / 2, image.height());
+        let left = image::imageops::crop(image, 0, 0, eye, height).to_image();
+        let right = image::imageops::crop(image, eye, 0, eye, height).to_image();
+
+        let (left_path, right_path) = (eye_path(path, "left"), eye_path(path, "right"));
+        left.save(&left_path)
+            .and_then(|_| right.save(&right_path))
+            .map(|_| format!("{} and {}", left_path, right_path))
+    } else {
+        image.save(path).map(|_| path.to_string())
+    };
 
-    // Event loop
-    while let Some(e) = window.next() {
-        window.draw_2d(&e, |c, g| {
-            // Clear the screen
-            piston_window::clear([0.0; 4], g);
+    match saved {
+        Ok(path) => println!("Saved image to {}", path),
+        Err(e) => println!("Failed to save image: {}", e),
+    }
+}
 
-            // Render the traced image to the window
-            piston_window::image(&texture, c.transform, g);
-        });
+// Path for one eye of a stereo pair, with the eye's name added before the
+// extension
+fn eye_path(path: &str, eye: &str) -> String {
+    match path.rfind('.') {
+        Some(dot) => format!("{}_{}{}", &path[..dot], eye, &path[dot..]),
+        None => format!("{}_{}", path, eye),
     }
 }
+
+// Finds the value passed after a command line flag, if any
+fn arg_value(flag: &str) -> Option<String> {
+    let mut args = env::args().skip_while(|arg| arg != flag);
+    args.next().and(args.next())
+}
@@ -0,0 +1,231 @@
+use cgmath::{vec3, Deg, InnerSpace, Quaternion, Rotation, Rotation3, Vector3};
+use tracer::{Intersect, Interval, Shape};
+use ray::Ray;
+
+// Places a shape in the scene: scaled evenly, then rotated, then moved
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Transform {
+    pub translation: Vector3<f64>,
+    pub rotation: Quaternion<f64>,
+    pub scale: f64,
+}
+
+impl Transform {
+    pub fn identity() -> Transform {
+        Transform {
+            translation: vec3(0.0, 0.0, 0.0),
+            rotation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            scale: 1.0,
+        }
+    }
+
+    /// Creates a transform that only moves shapes
+    pub fn translation(translation: Vector3<f64>) -> Transform {
+        Transform {
+            translation,
+            ..Transform::identity()
+        }
+    }
+
+    /// Creates a transform that only turns shapes around an axis through the
+    /// origin, by an angle in degrees
+    pub fn rotation(axis: Vector3<f64>, angle: f64) -> Transform {
+        Transform {
+            rotation: Quaternion::from_axis_angle(axis.normalize(), Deg(angle)),
+            ..Transform::identity()
+        }
+    }
+
+    // Blends toward another transform, from this one at 0 to the other at 1.
+    // Rotations turn the short way around at a steady rate.
+    pub fn interpolate(&self, other: &Transform, amount: f64) -> Transform {
+        // Flipping a quaternion gives the same rotation, the other way around
+        let target = if self.rotation.dot(other.rotation) < 0.0 {
+            -other.rotation
+        } else {
+            other.rotation
+        };
+
+        Transform {
+            translation: self.translation.lerp(other.translation, amount),
+            rotation: self.rotation.slerp(target, amount).normalize(),
+            scale: self.scale + (other.scale - self.scale) * amount,
+        }
+    }
+
+    // Moves a point from the shape's own space into the scene
+    pub fn point(&self, point: Vector3<f64>) -> Vector3<f64> {
+        self.rotation.rotate_vector(point * self.scale) + self.translation
+    }
+
+    // Turns a direction from the shape's own space into the scene
+    pub fn vector(&self, vector: Vector3<f64>) -> Vector3<f64> {
+        self.rotation.rotate_vector(vector)
+    }
+
+    // Turns a direction from the scene back into the shape's own space
+    pub fn inverse_vector(&self, vector: Vector3<f64>) -> Vector3<f64> {
+        self.rotation.invert().rotate_vector(vector)
+    }
+
+    // Moves a ray from the scene into the shape's own space. Distances along
+    // it shrink by the scale.
+    fn inverse_ray(&self, ray: &Ray) -> Ray {
+        let inverse = self.rotation.invert();
+        let origin = inverse.rotate_vector(ray.origin - self.translation) / self.scale;
+        let direction = inverse.rotate_vector(ray.direction());
+
+        Ray::bounded(origin, direction, ray.t_min / self.scale, ray.t_max / self.scale)
+            .at_time(ray.time)
+    }
+
+    // Moves an intersect found in the shape's own space back into the scene
+    fn intersect<'a>(&self, intersect: Intersect<'a>) -> Intersect<'a> {
+        Intersect {
+            distance: intersect.distance * self.scale,
+            point: self.point(intersect.point),
+            normal: self.vector(intersect.normal),
+            ..intersect
+        }
+    }
+}
+
+// Shape that moves while the shutter is open, so it's blurred along its path.
+//
+// The shape is placed by one transform at the start of the frame and another
+// at the end, and each ray sees it wherever it is at the ray's time. Only
+// shapes that stay put can be sampled as lights, so a moving shape that glows
+// only lights the scene when rays happen to hit it.
+pub struct Moving {
+    shape: Box<Shape>,
+    pub start: Transform,
+    pub end: Transform,
+}
+
+impl Moving {
+    pub fn new(shape: Box<Shape>, start: Transform, end: Transform) -> Moving {
+        Moving { shape, start, end }
+    }
+
+    // Where the shape is at a moment during the frame
+    pub fn transform(&self, time: f64) -> Transform {
+        self.start.interpolate(&self.end, time.clamp(0.0, 1.0))
+    }
+}
+
+impl Shape for Moving {
+    fn intersect(&self, ray: &Ray) -> Option<Intersect> {
+        let transform = self.transform(ray.time);
+        self.shape
+            .intersect(&transform.inverse_ray(ray))
+            .map(|intersect| transform.intersect(intersect))
+    }
+
+    fn occluded(&self, ray: &Ray) -> bool {
+        let transform = self.transform(ray.time);
+        self.shape.occluded(&transform.inverse_ray(ray))
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        let transform = self.transform(ray.time);
+        self.shape
+            .intervals(&transform.inverse_ray(ray))
+            .into_iter()
+            .map(|interval| Interval {
+                enter: transform.intersect(interval.enter),
+                exit: transform.intersect(interval.exit),
+            })
+            .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use cgmath::{vec3, InnerSpace};
+    use motion::{Moving, Transform};
+    use tracer::Shape;
+    use sphere::Sphere;
+    use ray::Ray;
+    use light::{Material, Rgb};
+
+    fn sphere() -> Box<Sphere> {
+        let material = Material::new(Rgb::new([255, 255, 255]), (1.0, 1.0, 1.0), 0.0, 0.0, 0.0);
+        Box::new(Sphere::new(vec3(0.0, 0.0, 0.0), 0.5, material))
+    }
+
+    // Tests that transforms blend evenly and turn the short way around
+    #[test]
+    fn interpolate() {
+        let start = Transform::translation(vec3(0.0, 0.0, 2.0));
+        let end = Transform {
+            scale: 3.0,
+            ..Transform::rotation(vec3(0.0, 1.0, 0.0), 90.0)
+        };
+
+        let middle = start.interpolate(&end, 0.5);
+        assert_relative_eq!(vec3(0.0, 0.0, 1.0), middle.translation);
+        assert_relative_eq!(2.0, middle.scale);
+
+        let x = middle.vector(vec3(1.0, 0.0, 0.0));
+        assert_relative_eq!(vec3(1.0, 0.0, -1.0).normalize(), x, epsilon = 1e-9);
+
+        // 350 degrees is the same as turning back by 10, so halfway is -5
+        let almost = Transform::rotation(vec3(0.0, 1.0, 0.0), 350.0);
+        let back = Transform::identity().interpolate(&almost, 0.5);
+        let expected = Transform::rotation(vec3(0.0, 1.0, 0.0), -5.0);
+        assert_relative_eq!(expected.vector(x), back.vector(x), epsilon = 1e-9);
+    }
+
+    // Tests that a moving shape is hit wherever it is at the ray's time
+    #[test]
+    fn moving() {
+        let shape = Moving::new(
+            sphere(),
+            Transform::translation(vec3(0.0, 0.0, 2.0)),
+            Transform::translation(vec3(2.0, 0.0, 2.0)),
+        );
+
+        let r = Ray::new(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0));
+        let hit = shape.intersect(&r).unwrap();
+        assert_relative_eq!(1.5, hit.distance, epsilon = 1e-9);
+        assert_relative_eq!(vec3(0.0, 0.0, -1.0), hit.normal, epsilon = 1e-9);
+        assert!(shape.intersect(&r.at_time(1.0)).is_none());
+
+        let r = Ray::new(vec3(1.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0)).at_time(0.5);
+        let hit = shape.intersect(&r).unwrap();
+        assert_relative_eq!(vec3(1.0, 0.0, 1.5), hit.point, epsilon = 1e-9);
+        assert!(shape.occluded(&r));
+        assert!(!shape.occluded(&r.at_time(0.0)));
+    }
+
+    // Tests that scaling keeps distances along the ray in the scene's units
+    #[test]
+    fn scale() {
+        let shape = Moving::new(
+            sphere(),
+            Transform {
+                scale: 2.0,
+                ..Transform::translation(vec3(0.0, 0.0, 3.0))
+            },
+            Transform {
+                scale: 2.0,
+                ..Transform::translation(vec3(0.0, 0.0, 3.0))
+            },
+        );
+
+        let r = Ray::new(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0));
+        let hit = shape.intersect(&r).unwrap();
+        assert_relative_eq!(2.0, hit.distance, epsilon = 1e-9);
+        assert_relative_eq!(vec3(0.0, 0.0, 2.0), hit.point, epsilon = 1e-9);
+
+        let spans = shape.intervals(&r);
+        assert_eq!(1, spans.len());
+        assert_relative_eq!(4.0, spans[0].exit.distance, epsilon = 1e-9);
+
+        // Bounds are in the scene's units too
+        let short = Ray::bounded(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0), 0.0, 1.5);
+        assert!(shape.intersect(&short).is_none());
+    }
+}
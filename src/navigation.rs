@@ -0,0 +1,236 @@
+use cgmath::{vec3, InnerSpace, Rotation, Vector3};
+use motion::Transform;
+
+// Degrees the camera turns for each pixel the mouse is dragged
+const TURN_SPEED: f64 = 0.3;
+
+// How far the view pans for each pixel the mouse is dragged, as a fraction of
+// the distance to the pivot
+const PAN_SPEED: f64 = 0.002;
+
+// How much closer each notch of the scroll wheel brings the camera
+const ZOOM_STEP: f64 = 1.1;
+
+// Distance the camera flies for each second a key is held
+pub const FLY_SPEED: f64 = 1.0;
+
+// Degrees the camera turns for each second a key is held
+pub const LOOK_SPEED: f64 = 60.0;
+
+// Steepest the camera can look up or down, in degrees. Any further and it
+// would flip over the top.
+const MAX_PITCH: f64 = 89.0;
+
+// Where the camera is while it's moved around in the window.
+//
+// The camera circles a pivot, turned around the vertical axis by the yaw and
+// then tipped up or down by the pitch, at a scale of its starting distance.
+// Orbiting and zooming keep the pivot still. Flying carries the pivot along
+// with the camera, and looking around turns the camera in place by swinging
+// the pivot around it instead. Angles are in degrees.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Navigation {
+    pub pivot: Vector3<f64>,
+    pub yaw: f64,
+    pub pitch: f64,
+    pub scale: f64,
+
+    // Where the camera starts out relative to the pivot
+    offset: Vector3<f64>,
+}
+
+impl Navigation {
+    /// Creates a camera at a position, circling a pivot
+    pub fn new(pivot: Vector3<f64>, position: Vector3<f64>) -> Navigation {
+        Navigation {
+            pivot,
+            yaw: 0.0,
+            pitch: 0.0,
+            scale: 1.0,
+            offset: position - pivot,
+        }
+    }
+
+    // Pose for a camera that starts out relative to the pivot
+    pub fn transform(&self) -> Transform {
+        let yaw = Transform::rotation(vec3(0.0, 1.0, 0.0), self.yaw);
+        let pitch = Transform::rotation(vec3(1.0, 0.0, 0.0), self.pitch);
+        Transform {
+            translation: self.pivot,
+            rotation: yaw.rotation * pitch.rotation,
+            scale: self.scale,
+        }
+    }
+
+    // Where the camera is in the scene
+    pub fn position(&self) -> Vector3<f64> {
+        self.transform().point(self.offset)
+    }
+
+    // Turns the camera around the pivot as the mouse drags the scene, so the
+    // side facing the camera follows the mouse
+    pub fn orbit(&mut self, dx: f64, dy: f64) {
+        self.turn(dx * TURN_SPEED, dy * TURN_SPEED);
+    }
+
+    // Slides the camera and pivot sideways as the mouse drags the scene
+    pub fn pan(&mut self, dx: f64, dy: f64) {
+        let rotation = self.transform().rotation;
+        let right = rotation.rotate_vector(vec3(1.0, 0.0, 0.0));
+        let up = rotation.rotate_vector(vec3(0.0, 1.0, 0.0));
+
+        let speed = PAN_SPEED * self.offset.magnitude() * self.scale;
+        self.pivot += (up * dy - right * dx) * speed;
+    }
+
+    // Moves the camera toward the pivot for scrolling up, or away for down
+    pub fn zoom(&mut self, notches: f64) {
+        self.scale /= ZOOM_STEP.powf(notches);
+    }
+
+    // Moves the camera and pivot together, by distances to the camera's
+    // right, up and ahead
+    pub fn fly(&mut self, right: f64, up: f64, ahead: f64) {
+        self.pivot += self.transform().rotation.rotate_vector(vec3(right, up, ahead));
+    }
+
+    // Turns the camera in place, to the right and up by angles in degrees
+    pub fn look(&mut self, right: f64, up: f64) {
+        let position = self.position();
+        self.turn(right, -up);
+
+        let turned = self.transform();
+        self.pivot = position - turned.rotation.rotate_vector(self.offset * self.scale);
+    }
+
+    // Turns the camera around the pivot. Positive yaw circles it toward its
+    // left, which turns it to the right, and positive pitch raises it to look
+    // down.
+    fn turn(&mut self, yaw: f64, pitch: f64) {
+        self.yaw = (self.yaw + yaw) % 360.0;
+        self.pitch = (self.pitch + pitch).clamp(-MAX_PITCH, MAX_PITCH);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use cgmath::{vec3, Rotation, Vector3};
+    use camera::{Camera, Perspective, Posed};
+    use integrator::{Integrator, Settings};
+    use light::{Material, Rgb};
+    use navigation::Navigation;
+    use sampler::Independent;
+    use scene::Scene;
+    use sphere::Sphere;
+    use tracer::Background;
+    use whitted::Whitted;
+
+    fn navigation() -> Navigation {
+        Navigation::new(vec3(0.0, 0.0, 2.0), vec3(0.0, 0.0, 0.0))
+    }
+
+    // Direction the camera faces, which starts out along +Z
+    fn ahead(navigation: &Navigation) -> Vector3<f64> {
+        navigation.transform().rotation.rotate_vector(vec3(0.0, 0.0, 1.0))
+    }
+
+    // Tests that the starting pose leaves the camera where it was
+    #[test]
+    fn start() {
+        let navigation = navigation();
+        assert_relative_eq!(vec3(0.0, 0.0, 0.0), navigation.position());
+        assert_relative_eq!(vec3(0.0, 0.0, 1.0), ahead(&navigation));
+    }
+
+    // Tests that orbiting circles the pivot while still facing it, and that
+    // the camera can't tip over the top
+    #[test]
+    fn orbit() {
+        let mut navigation = navigation();
+
+        // Dragging the scene to the right swings the camera around to its left
+        navigation.orbit(300.0, 0.0);
+        assert_relative_eq!(vec3(-2.0, 0.0, 2.0), navigation.position(), epsilon = 1e-9);
+        assert_relative_eq!(vec3(1.0, 0.0, 0.0), ahead(&navigation), epsilon = 1e-9);
+
+        // Dragging down raises the camera to look down on the pivot
+        let mut navigation = self::navigation();
+        navigation.orbit(0.0, 100.0);
+        assert!(navigation.position().y > 0.0);
+        assert!(ahead(&navigation).y < 0.0);
+
+        navigation.orbit(0.0, 1000.0);
+        assert_eq!(89.0, navigation.pitch);
+    }
+
+    // Tests that panning and flying move the pivot along with the camera,
+    // and that zooming moves the camera toward the pivot
+    #[test]
+    fn translate() {
+        let mut navigation = navigation();
+        navigation.pan(100.0, 0.0);
+        assert_relative_eq!(vec3(-0.4, 0.0, 2.0), navigation.pivot, epsilon = 1e-9);
+        assert_relative_eq!(vec3(-0.4, 0.0, 0.0), navigation.position(), epsilon = 1e-9);
+
+        let mut navigation = self::navigation();
+        navigation.orbit(300.0, 0.0);
+        navigation.fly(0.0, 0.5, 1.0);
+        assert_relative_eq!(vec3(-1.0, 0.5, 2.0), navigation.position(), epsilon = 1e-9);
+
+        let mut navigation = self::navigation();
+        navigation.zoom(1.0);
+        assert_relative_eq!(vec3(0.0, 0.0, 2.0 - 2.0 / 1.1), navigation.position(), epsilon = 1e-9);
+    }
+
+    // Tests that looking around turns the camera without moving it
+    #[test]
+    fn look() {
+        let mut navigation = navigation();
+        navigation.look(90.0, 0.0);
+        assert_relative_eq!(vec3(0.0, 0.0, 0.0), navigation.position(), epsilon = 1e-9);
+        assert_relative_eq!(vec3(1.0, 0.0, 0.0), ahead(&navigation), epsilon = 1e-9);
+        assert_relative_eq!(vec3(2.0, 0.0, 0.0), navigation.pivot, epsilon = 1e-9);
+
+        navigation.look(0.0, 30.0);
+        assert_relative_eq!(vec3(0.0, 0.0, 0.0), navigation.position(), epsilon = 1e-9);
+        assert!(ahead(&navigation).y > 0.0);
+    }
+
+    // Tests that a mirror seen by a camera orbited away from the origin
+    // reflects what's behind the camera
+    #[test]
+    fn posed_reflection() {
+        // Swing the camera partway around and above the pivot. The posed
+        // camera starts out relative to the pivot.
+        let mut navigation = navigation();
+        navigation.orbit(150.0, 60.0);
+        let camera = Posed {
+            camera: Box::new(Perspective {
+                position: vec3(0.0, 0.0, -2.0),
+                ..Perspective::new()
+            }),
+            transform: navigation.transform(),
+        };
+
+        let r = camera.ray(0.0, 0.0, (0.0, 0.0)).unwrap();
+        assert_relative_eq!(navigation.position(), r.origin, epsilon = 1e-9);
+
+        // Mirror at the pivot, and a red ball as far again behind the camera
+        let mut scene = Scene::new(Background {
+            color: Rgb::new([0, 0, 0]),
+        });
+        let mirror = Material::new(Rgb::new([0, 0, 0]), (0.0, 0.0, 0.0), 1.0, 0.0, 0.0);
+        let red = Material::new(Rgb::new([255, 0, 0]), (1.0, 0.0, 0.0), 0.0, 0.0, 0.0);
+        let behind = navigation.pivot + (r.origin - navigation.pivot) * 2.0;
+        scene.add(Box::new(Sphere::new(navigation.pivot, 0.5, mirror)));
+        scene.add(Box::new(Sphere::new(behind, 0.5, red)));
+
+        let whitted = Whitted::new(&Settings::new());
+        let sampler = &mut Independent::new(0);
+        let color = whitted.radiance(&r, &scene, sampler).color.data;
+        assert!(color[0] > 0);
+        assert_eq!([0, 0], [color[1], color[2]]);
+    }
+}
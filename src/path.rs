@@ -0,0 +1,359 @@
+extern crate std;
+
+use cgmath::{dot, InnerSpace, Vector3};
+use tracer::{transmission_ray, Intersect};
+use integrator::Integrator;
+use sampling::{cosine_hemisphere, cosine_hemisphere_pdf, power_heuristic};
+use color::Color;
+use ray::Ray;
+use scene::Scene;
+use light::{trace_shadow, Rgb};
+use sampler::Sampler;
+
+const SAMPLES: u32 = 64;
+const MAX_DEPTH: u32 = 16;
+
+// Paths always survive this many bounces before Russian roulette can end them
+const ROULETTE_DEPTH: u32 = 3;
+
+// Highest chance of a path surviving Russian roulette, so that paths through
+// surfaces that reflect all of their light still end eventually
+const MAX_SURVIVAL: f64 = 0.95;
+
+// Shadow rays toward a point on a glowing surface stop just short of it, so
+// they aren't blocked by the surface itself
+const SHADOW_EPSILON: f64 = 1e-4;
+
+// Monte Carlo path tracer. Follows random paths of light bouncing around the
+// scene, so light reflected off of one surface lights up the others.
+//
+// Diffuse bounces are cosine weighted. At every diffuse surface the lights are
+// sampled directly (next event estimation), and the background is sampled as
+// a light surrounding the scene, favoring its brightest parts. Paths that
+// escape to the background after a diffuse bounce could also have been found
+// by sampling it directly, so the two are combined with multiple importance
+// sampling.
+//
+// Glowing surfaces are sampled as area lights too, and weighted against
+// diffuse bounces that hit them the same way.
+//
+// Point lights follow the same convention as phong shading: a white surface
+// facing a white light reflects its full diffuse color.
+pub struct PathTracer {
+    // Paths traced through each pixel, averaged together
+    pub samples: u32,
+
+    // Bounces after which a path is always ended
+    pub max_depth: u32,
+}
+
+impl PathTracer {
+    pub fn new() -> PathTracer {
+        PathTracer {
+            samples: SAMPLES,
+            max_depth: MAX_DEPTH,
+        }
+    }
+
+    // Follows one random path from the ray, returning the light carried back
+    // along it
+    fn trace(&self, mut ray: Ray, scene: &Scene, sampler: &mut Sampler) -> Color {
+        let mut radiance = Color::black();
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+
+        // Density of the diffuse bounce that produced the ray. Camera rays and
+        // specular bounces can't be produced by sampling lights, so they don't
+        // need to be weighted against it.
+        let mut bounce_pdf: Option<f64> = None;
+
+        for depth in 0..self.max_depth {
+            let intersect = match scene.intersect(&ray) {
+                Some(intersect) => intersect,
+                None => {
+                    let d = ray.direction();
+                    let weight = bounce_pdf.map_or(1.0, |pdf| {
+                        power_heuristic(pdf, scene.background.pdf(d))
+                    });
+                    radiance += throughput * scene.miss(&ray, depth == 0) * weight;
+                    break;
+                }
+            };
+
+            let d = ray.direction();
+            let material = intersect.color;
+
+            // Light given off by the surface could also have been found by
+            // sampling it from the last diffuse surface
+            let emission = material.emission();
+            if !emission.is_black() {
+                let weight = match (bounce_pdf, intersect.shape) {
+                    (Some(pdf), Some(id)) => {
+                        let cos = dot(intersect.normal, d).abs();
+                        let light_pdf = scene.emitter_pdf(id) * intersect.distance
+                            * intersect.distance / cos;
+                        power_heuristic(pdf, light_pdf)
+                    }
+                    _ => 1.0,
+                };
+                radiance += throughput * emission * weight;
+            }
+
+            // Pick a single kind of bounce in proportion to how much of the
+            // light each one carries, so the weights cancel out
+            let k_r = material.reflection();
+            let k_t = material.transmission();
+            let choice = sampler.next_1d();
+
+            if choice < k_r {
+                let n = intersect.normal;
+                let r = d - n * (2.0 * dot(d, n));
+                ray = Ray::from_surface(intersect.point, n, r).at_time(ray.time);
+                bounce_pdf = None;
+            } else if choice < k_r + k_t {
+                ray = transmission_ray(d, &intersect).at_time(ray.time);
+                bounce_pdf = None;
+            } else {
+                // Shade the side of the surface the ray arrived on
+                let n = if dot(intersect.normal, d) > 0.0 {
+                    -intersect.normal
+                } else {
+                    intersect.normal
+                };
+
+                let (_, k_d, _) = material.phong_constants();
+                let albedo = Color::from_rgb(material.diffuse()) * k_d;
+
+                radiance += throughput * albedo * self.sample_lights(&intersect, n, ray.time, scene, sampler);
+
+                // The cosine and 1 / pi in the diffuse reflectance cancel out
+                // with the density of the cosine weighted sample
+                let (u1, u2) = sampler.next_2d();
+                let direction = cosine_hemisphere(n, u1, u2);
+                bounce_pdf = Some(cosine_hemisphere_pdf(dot(direction, n)));
+                throughput = throughput * albedo;
+                ray = Ray::from_surface(intersect.point, n, direction).at_time(ray.time);
+            }
+
+            if throughput.is_black() {
+                break;
+            }
+
+            // Randomly end dim paths, boosting the ones that survive to make up
+            // for the light lost from the others
+            if depth + 1 >= ROULETTE_DEPTH {
+                let survival = throughput.max_channel().min(MAX_SURVIVAL);
+                if sampler.next_1d() >= survival {
+                    break;
+                }
+                throughput = throughput / survival;
+            }
+        }
+
+        radiance
+    }
+
+    // Estimates the light arriving directly at a diffuse surface, already
+    // divided by pi so it only needs to be multiplied by the albedo
+    fn sample_lights(
+        &self,
+        intersect: &Intersect,
+        n: Vector3<f64>,
+        time: f64,
+        scene: &Scene,
+        sampler: &mut Sampler,
+    ) -> Color {
+        let mut total = Color::black();
+
+        for light in &scene.lights {
+            let (s, _) = light.toward(intersect.point);
+            let cos = dot(s, n);
+            if cos > 0.0 {
+                let color = trace_shadow(intersect.point, n, time, scene, light);
+                total += Color::from_rgb(&color) * cos;
+            }
+        }
+
+        // Pick a point on one of the glowing surfaces, converting its density
+        // by area into a density by direction
+        let pick = sampler.next_1d();
+        let (u1, u2) = sampler.next_2d();
+        if let Some((light, area_pdf)) = scene.sample_emitter(pick, u1, u2) {
+            let offset = light.point - intersect.point;
+            let distance = offset.magnitude();
+            let s = offset / distance;
+            let cos = dot(s, n);
+            let light_cos = dot(light.normal, s).abs();
+
+            if cos > 0.0 && light_cos > 0.0 {
+                let mut shadow = Ray::from_surface(intersect.point, n, s).at_time(time);
+                shadow.t_max = distance * (1.0 - SHADOW_EPSILON);
+
+                if !scene.occluded(&shadow) {
+                    let pdf = area_pdf * distance * distance / light_cos;
+                    let weight = power_heuristic(pdf, cosine_hemisphere_pdf(cos));
+                    total += light.color.emission() * (cos / std::f64::consts::PI / pdf * weight);
+                }
+            }
+        }
+
+        // Treat the background as light surrounding the scene
+        let (u1, u2) = sampler.next_2d();
+        if let Some((direction, pdf)) = scene.background.sample(u1, u2) {
+            let cos = dot(direction, n);
+
+            let shadow = Ray::from_surface(intersect.point, n, direction).at_time(time);
+            if cos > 0.0 && !scene.occluded(&shadow) {
+                let weight = power_heuristic(pdf, cosine_hemisphere_pdf(cos));
+                let background = scene.background.radiance(direction);
+                total += background * (cos / std::f64::consts::PI / pdf * weight);
+            }
+        }
+
+        total
+    }
+}
+
+impl Default for PathTracer {
+    fn default() -> PathTracer {
+        PathTracer::new()
+    }
+}
+
+impl Integrator for PathTracer {
+    // The first path carries on from the numbers the camera ray already used,
+    // and every other one starts a sample of its own
+    fn radiance(&self, ray: &Ray, scene: &Scene, sampler: &mut Sampler) -> Rgb {
+        let mut total = Color::black();
+        for path in 0..self.samples {
+            if path > 0 {
+                sampler.next_sample();
+            }
+            total += self.trace(*ray, scene, sampler);
+        }
+
+        (total / self.samples as f64).to_rgb()
+    }
+
+    fn samples(&self) -> u32 {
+        self.samples
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use cgmath::vec3;
+    use integrator::Integrator;
+    use path::PathTracer;
+    use scene::Scene;
+    use floor::Floor;
+    use sphere::Sphere;
+    use tracer::Background;
+    use environment::{EnvironmentMap, Projection};
+    use color::Color;
+    use ray::Ray;
+    use light::{Light, Material, Rgb};
+    use sampler::Independent;
+
+    fn floor(color: Rgb) -> Box<Floor> {
+        let material = Material::new(color, (1.0, 1.0, 1.0), 0.0, 0.0, 0.0);
+        Box::new(Floor::new(
+            vec3(-1.0, -1.0, 1.0),
+            vec3(-1.0, 1.0, 1.0),
+            vec3(1.0, -1.0, 1.0),
+            vec3(1.0, 1.0, 1.0),
+            material.clone(),
+            material,
+        ))
+    }
+
+    // Tests that a point light matches phong shading when there's nothing
+    // else to bounce off of
+    #[test]
+    fn radiance_direct() {
+        let mut scene = Scene::new(Background {
+            color: Rgb::new([0, 0, 0]),
+        });
+        scene.add(floor(Rgb::new([200, 100, 50])));
+        scene.lights.push(Light::point(vec3(0.0, 0.0, -1.0), Rgb::new([255, 255, 255])));
+        let sampler = &mut Independent::new(0);
+
+        let r = Ray::new(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0));
+        assert_eq!(Rgb::new([200, 100, 50]), PathTracer::new().radiance(&r, &scene, sampler));
+
+        let r = Ray::new(vec3(0.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0));
+        assert_eq!(Rgb::new([0, 0, 0]), PathTracer::new().radiance(&r, &scene, sampler));
+    }
+
+    // Tests that a surface under an even sky reflects its albedo, which only
+    // holds if light sampling and bounces are weighted correctly
+    #[test]
+    fn radiance_sky() {
+        let mut scene = Scene::new(Background {
+            color: Rgb::new([255, 255, 255]),
+        });
+        scene.add(floor(Rgb::new([128, 128, 128])));
+
+        let path = PathTracer {
+            samples: 20000,
+            max_depth: 4,
+        };
+        let sampler = &mut Independent::new(0);
+
+        let r = Ray::new(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0));
+        let color = path.radiance(&r, &scene, sampler).color.data[0];
+        assert!(color >= 124 && color <= 132, "Expected about 128, got {}", color);
+    }
+
+    // Tests that importance sampling an environment map still adds up to the
+    // same light as an even sky of the same brightness
+    #[test]
+    fn radiance_environment() {
+        for &(width, height, projection) in
+            &[(16, 8, Projection::Equirectangular), (16, 12, Projection::Cube)]
+        {
+            let pixels = vec![Color::new(1.0, 1.0, 1.0); width * height];
+            let mut scene = Scene::new(EnvironmentMap::new(pixels, width, height, projection));
+            scene.add(floor(Rgb::new([128, 128, 128])));
+
+            let path = PathTracer {
+                samples: 20000,
+                max_depth: 4,
+            };
+            let sampler = &mut Independent::new(0);
+
+            let r = Ray::new(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0));
+            let color = path.radiance(&r, &scene, sampler).color.data[0];
+            assert!(color >= 124 && color <= 132, "Expected about 128, got {}", color);
+        }
+    }
+
+    // Tests that a glowing sphere lights a white surface by the fraction of
+    // the sky it covers, (r / distance)^2, and that it's seen directly
+    #[test]
+    fn radiance_emitter() {
+        let mut scene = Scene::new(Background {
+            color: Rgb::new([0, 0, 0]),
+        });
+        scene.add(floor(Rgb::new([255, 255, 255])));
+
+        let black = Rgb::new([0, 0, 0]);
+        let lamp = Material::new(black, (0.0, 0.0, 0.0), 0.0, 0.0, 0.0)
+            .emissive(Rgb::new([255, 255, 255]), 8.0);
+        scene.add(Box::new(Sphere::new(vec3(0.0, 0.0, -1.0), 0.5, lamp)));
+
+        let path = PathTracer {
+            samples: 20000,
+            max_depth: 4,
+        };
+        let sampler = &mut Independent::new(0);
+
+        let r = Ray::new(vec3(0.0, 0.0, 0.5), vec3(0.0, 0.0, 1.0));
+        let color = path.radiance(&r, &scene, sampler).color.data[0];
+        assert!(color >= 124 && color <= 132, "Expected about 128, got {}", color);
+
+        let r = Ray::new(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, -1.0));
+        assert_eq!(Rgb::new([255, 255, 255]), path.radiance(&r, &scene, sampler));
+    }
+}
@@ -0,0 +1,270 @@
+extern crate std;
+
+use cgmath::{dot, InnerSpace, Vector3};
+use tracer::transmission_ray;
+use kdtree::{KdTree, Positioned};
+use sampling::uniform_sphere;
+use color::Color;
+use ray::Ray;
+use scene::Scene;
+use light::Light;
+use sampler::{Independent, Sampler};
+
+const PI: f64 = std::f64::consts::PI;
+
+// Resolution of the grid of directions around each light that's checked for
+// shapes worth aiming photons at
+const PROJECTION_ROWS: usize = 128;
+const PROJECTION_COLUMNS: usize = 256;
+
+// Photons can bounce around inside glass for a while, but not forever
+const MAX_BOUNCES: u32 = 16;
+
+const GATHER_COUNT: usize = 50;
+const GATHER_RADIUS: f64 = 0.1;
+
+// Bundle of light that landed on a diffuse surface
+pub struct Photon {
+    pub position: Vector3<f64>,
+
+    // Direction the photon was traveling when it landed
+    pub direction: Vector3<f64>,
+    pub power: Color,
+}
+
+impl Positioned for Photon {
+    fn position(&self) -> Vector3<f64> {
+        self.position
+    }
+}
+
+// Caustic photon map (Jensen). Photons are fired from the lights at the mirror
+// and glass surfaces in the scene, and stored wherever they land on a diffuse
+// surface after bouncing off of or through them. The density of the photons
+// around a point shows how much focused light reaches it, which shadow rays
+// can't find because they travel in a straight line.
+pub struct PhotonMap {
+    photons: KdTree<Photon>,
+
+    // Photons used to estimate the light at each point, and how far away
+    // they're allowed to be
+    pub gather_count: usize,
+    pub gather_radius: f64,
+}
+
+impl PhotonMap {
+    // Traces about the given number of photons, split between the point
+    // lights. Directional lights have no position to fire photons from, so
+    // they don't cast caustics.
+    //
+    // Each light draws its photons' random numbers as if it were a pixel of
+    // its own, so the same scene always stores the same photons.
+    pub fn caustics(scene: &Scene, photons: usize) -> PhotonMap {
+        let mut sampler = Independent::new(0);
+        let mut stored = vec![];
+
+        let lights: Vec<&Light> = scene.lights.iter().filter(|light| !light.directional).collect();
+        let per_light = photons / lights.len().max(1);
+        for (i, light) in lights.into_iter().enumerate() {
+            sampler.start_pixel(i as u32, 0, 0);
+            emit(scene, light, per_light, &mut sampler, &mut stored);
+        }
+
+        PhotonMap {
+            photons: KdTree::new(stored),
+            gather_count: GATHER_COUNT,
+            gather_radius: GATHER_RADIUS,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.photons.is_empty()
+    }
+
+    // Estimates the focused light arriving at a point on a diffuse surface
+    // from the photons around it. Like a light's color in phong shading, it
+    // still needs to be multiplied by the surface's diffuse color.
+    pub fn irradiance(&self, point: Vector3<f64>, normal: Vector3<f64>) -> Color {
+        let nearest = self.photons
+            .nearest(point, self.gather_count, self.gather_radius);
+
+        // The photons are spread over a disk reaching out to the farthest one
+        let radius2 = match nearest.last() {
+            Some(&(distance2, _)) if distance2 > 0.0 => distance2,
+            _ => return Color::black(),
+        };
+
+        // Only photons landing on the same side of the surface light it
+        let total = nearest
+            .iter()
+            .filter(|&&(_, photon)| dot(photon.direction, normal) < 0.0)
+            .fold(Color::black(), |total, &(_, photon)| total + photon.power);
+
+        total / (PI * radius2)
+    }
+}
+
+// Fires photons from a point light, aiming only at the directions where they
+// would hit a mirror or glass surface first. Others could never become part of
+// a caustic.
+fn emit(
+    scene: &Scene,
+    light: &Light,
+    photons: usize,
+    sampler: &mut Sampler,
+    stored: &mut Vec<Photon>,
+) {
+    let cells = projection_cells(scene, light);
+    if cells.is_empty() || photons == 0 {
+        return;
+    }
+
+    // Each photon carries an equal share of the light heading into the cells
+    let cell_angle = 4.0 * PI / (PROJECTION_ROWS * PROJECTION_COLUMNS) as f64;
+    let solid_angle = cell_angle * cells.len() as f64;
+    let power = Color::from_rgb(&light.color) * (solid_angle / photons as f64);
+
+    for i in 0..photons {
+        let (row, column) = cells[i % cells.len()];
+        let (u1, u2) = sampler.next_2d();
+        let direction = uniform_sphere(
+            (row as f64 + u1) / PROJECTION_ROWS as f64,
+            (column as f64 + u2) / PROJECTION_COLUMNS as f64,
+        );
+
+        trace(scene, Ray::new(light.position, direction), power, sampler, stored);
+        sampler.next_sample();
+    }
+}
+
+// Finds the cells in a grid of directions around the light where a mirror or
+// glass surface is the first thing hit, checking a few directions in each
+fn projection_cells(scene: &Scene, light: &Light) -> Vec<(usize, usize)> {
+    let offsets = [(0.25, 0.25), (0.25, 0.75), (0.75, 0.25), (0.75, 0.75)];
+    let mut cells = vec![];
+
+    for row in 0..PROJECTION_ROWS {
+        for column in 0..PROJECTION_COLUMNS {
+            let specular = offsets.iter().any(|&(u, v)| {
+                let direction = uniform_sphere(
+                    (row as f64 + u) / PROJECTION_ROWS as f64,
+                    (column as f64 + v) / PROJECTION_COLUMNS as f64,
+                );
+
+                scene
+                    .intersect(&Ray::new(light.position, direction))
+                    .map_or(false, |intersect| {
+                        intersect.color.reflection() > 0.0 || intersect.color.transmission() > 0.0
+                    })
+            });
+
+            if specular {
+                cells.push((row, column));
+            }
+        }
+    }
+
+    cells
+}
+
+// Follows a photon through the scene, storing it if it lands on a diffuse
+// surface after at least one mirror or glass bounce
+fn trace(
+    scene: &Scene,
+    mut ray: Ray,
+    mut power: Color,
+    sampler: &mut Sampler,
+    stored: &mut Vec<Photon>,
+) {
+    let mut specular = false;
+
+    for bounce in 0..MAX_BOUNCES {
+        let intersect = match scene.intersect(&ray) {
+            Some(intersect) => intersect,
+            None => return,
+        };
+
+        // Point lights don't fall off with distance in phong shading, so
+        // photons don't either. Spreading out from the light still thins them
+        // out, so scale them back up to match the light at the first surface.
+        if bounce == 0 {
+            power = power * (intersect.distance * intersect.distance);
+        }
+
+        // Choose what happens to the photon in proportion to the material's
+        // constants, so its power stays the same
+        let d = ray.direction();
+        let k_r = intersect.color.reflection();
+        let k_t = intersect.color.transmission();
+        let choice = sampler.next_1d();
+
+        if choice < k_r {
+            let n = intersect.normal;
+            ray = Ray::from_surface(intersect.point, n, d - n * (2.0 * dot(d, n))).at_time(ray.time);
+            specular = true;
+        } else if choice < k_r + k_t {
+            ray = transmission_ray(d, &intersect).at_time(ray.time);
+            specular = true;
+        } else {
+            if specular {
+                stored.push(Photon {
+                    position: intersect.point,
+                    direction: d.normalize(),
+                    power,
+                });
+            }
+            return;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use cgmath::vec3;
+    use photon::PhotonMap;
+    use scene::Scene;
+    use sphere::Sphere;
+    use floor::Floor;
+    use tracer::Background;
+    use light::{Light, Material, Rgb};
+
+    // Tests that a glass ball focuses light into a bright spot below it, and
+    // that there's no caustic off to the side
+    #[test]
+    fn caustics() {
+        let mut scene = Scene::new(Background {
+            color: Rgb::new([0, 0, 0]),
+        });
+
+        let floor = Material::new(Rgb::new([255, 255, 255]), (1.0, 1.0, 1.0), 0.0, 0.0, 0.0);
+        scene.add(Box::new(Floor::new(
+            vec3(-5.0, -5.0, 0.0),
+            vec3(-5.0, 5.0, 0.0),
+            vec3(5.0, -5.0, 0.0),
+            vec3(5.0, 5.0, 0.0),
+            floor.clone(),
+            floor,
+        )));
+
+        let glass = Material::new(Rgb::new([255, 255, 255]), (0.0, 0.0, 0.0), 0.0, 1.0, 1.5);
+        scene.add(Box::new(Sphere::new(vec3(0.0, 0.0, -1.0), 0.5, glass)));
+
+        scene.lights.push(Light::point(vec3(0.0, 0.0, -5.0), Rgb::new([255, 255, 255])));
+
+        let map = PhotonMap::caustics(&scene, 20000);
+        assert!(!map.is_empty());
+
+        // Light falling straight on the floor would be white, so the focused
+        // light must be brighter
+        let up = vec3(0.0, 0.0, -1.0);
+        let focus = map.irradiance(vec3(0.0, 0.0, 0.0), up);
+        assert!(focus.r > 1.0, "Caustic should be bright, got {:?}", focus);
+
+        assert!(map.irradiance(vec3(3.0, 3.0, 0.0), up).is_black());
+
+        // Photons land on the top of the floor, so they don't light the bottom
+        assert!(map.irradiance(vec3(0.0, 0.0, 0.0), -up).is_black());
+    }
+}
@@ -0,0 +1,257 @@
+// Real root finding for the polynomials that come out of shape intersections.
+//
+// Closed form quartic solutions (Ferrari) lose most of their precision when
+// roots are close together, which is exactly what happens when a ray grazes a
+// surface. Instead, roots are isolated between the critical points of the
+// polynomial, which are the roots of its derivative found the same way, and
+// each bracket is then narrowed with a safeguarded Newton iteration.
+
+const MAX_ITERATIONS: u32 = 100;
+
+// Relative size of a polynomial value that's treated as zero at a critical
+// point. This catches double roots, which don't change sign.
+const DOUBLE_ROOT_EPSILON: f64 = 1e-10;
+
+// Finds the real roots of a x^4 + b x^3 + c x^2 + d x + e = 0, sorted from
+// smallest to largest
+pub fn solve_quartic(a: f64, b: f64, c: f64, d: f64, e: f64) -> Vec<f64> {
+    real_roots(&[a, b, c, d, e])
+}
+
+// Finds the real roots of a polynomial of any degree, sorted from smallest to
+// largest. Coefficients start with the highest power of x.
+pub fn real_roots(coefficients: &[f64]) -> Vec<f64> {
+    // Leading zeros would make the polynomial a lower degree
+    let first = coefficients
+        .iter()
+        .position(|&c| c != 0.0)
+        .unwrap_or(coefficients.len());
+    let coefficients = &coefficients[first..];
+
+    match coefficients.len() {
+        0 | 1 => vec![],
+        2 => vec![-coefficients[1] / coefficients[0]],
+        3 => solve_quadratic(coefficients[0], coefficients[1], coefficients[2]),
+        _ => isolate_roots(coefficients),
+    }
+}
+
+// Evaluates the polynomial at x using Horner's method
+pub fn evaluate(coefficients: &[f64], x: f64) -> f64 {
+    coefficients.iter().fold(0.0, |result, &c| result * x + c)
+}
+
+// Calculates the coefficients of the first derivative
+fn derivative(coefficients: &[f64]) -> Vec<f64> {
+    let degree = coefficients.len() - 1;
+    coefficients[..degree]
+        .iter()
+        .enumerate()
+        .map(|(i, &c)| c * (degree - i) as f64)
+        .collect()
+}
+
+// Quadratic formula, rearranged to avoid cancellation when b^2 is much larger
+// than 4ac
+fn solve_quadratic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    let discriminant = b * b - 4.0 * a * c;
+
+    if discriminant < -DOUBLE_ROOT_EPSILON * b * b {
+        vec![]
+    } else if discriminant <= 0.0 {
+        vec![-b / (2.0 * a)]
+    } else {
+        let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+
+        // b == 0 makes q zero when c is also zero, leaving a single root at 0
+        if q == 0.0 {
+            return vec![0.0];
+        }
+
+        let (x1, x2) = (q / a, c / q);
+        if x1 < x2 {
+            vec![x1, x2]
+        } else {
+            vec![x2, x1]
+        }
+    }
+}
+
+// Brackets each root between consecutive critical points, then narrows them
+// down. Between two critical points the polynomial is monotonic, so there's at
+// most one root in each bracket.
+fn isolate_roots(coefficients: &[f64]) -> Vec<f64> {
+    let slope = derivative(coefficients);
+
+    // Cauchy's bound: every root lies within this distance of zero
+    let bound = 1.0
+        + coefficients[1..]
+            .iter()
+            .map(|c| (c / coefficients[0]).abs())
+            .fold(0.0, f64::max);
+
+    let mut points = vec![-bound];
+    points.extend(
+        real_roots(&slope)
+            .into_iter()
+            .filter(|&x| x > -bound && x < bound),
+    );
+    points.push(bound);
+
+    let mut roots: Vec<f64> = vec![];
+    let push = |x: f64, roots: &mut Vec<f64>| {
+        let duplicate = roots
+            .last()
+            .map_or(false, |&last| (x - last).abs() <= 1e-9 * (1.0 + x.abs()));
+        if !duplicate {
+            roots.push(x);
+        }
+    };
+
+    // Values within rounding error of zero at a critical point are snapped to
+    // zero, otherwise a double root can look like two nearby sign changes
+    let values: Vec<f64> = points
+        .iter()
+        .map(|&x| {
+            let value = evaluate(coefficients, x);
+            if is_double_root(coefficients, x, value) {
+                0.0
+            } else {
+                value
+            }
+        })
+        .collect();
+
+    for i in 0..points.len() {
+        if values[i] == 0.0 {
+            push(points[i], &mut roots);
+        }
+
+        if i + 1 < points.len() && values[i] * values[i + 1] < 0.0 {
+            let root = refine(coefficients, &slope, points[i], points[i + 1], values[i]);
+            push(root, &mut roots);
+        }
+    }
+
+    roots
+}
+
+// Checks whether the value at a critical point is close enough to zero to be a
+// root, relative to the size of the terms that were summed to produce it
+fn is_double_root(coefficients: &[f64], x: f64, value: f64) -> bool {
+    let scale = coefficients
+        .iter()
+        .fold(0.0, |result, &c| result * x.abs() + c.abs());
+    value.abs() <= DOUBLE_ROOT_EPSILON * scale
+}
+
+// Narrows a bracket with a sign change down to the root. Newton steps are used
+// while they stay inside the bracket, falling back to bisection otherwise.
+fn refine(coefficients: &[f64], slope: &[f64], lo: f64, hi: f64, f_lo: f64) -> f64 {
+    let (mut lo, mut hi) = (lo, hi);
+    let rising = f_lo < 0.0;
+    let mut x = 0.5 * (lo + hi);
+
+    for _ in 0..MAX_ITERATIONS {
+        let f = evaluate(coefficients, x);
+        if f == 0.0 {
+            return x;
+        }
+
+        // Shrink the bracket around the root
+        if (f < 0.0) == rising {
+            lo = x;
+        } else {
+            hi = x;
+        }
+
+        let df = evaluate(slope, x);
+        let newton = x - f / df;
+        let next = if df != 0.0 && newton > lo && newton < hi {
+            newton
+        } else {
+            0.5 * (lo + hi)
+        };
+
+        if (next - x).abs() <= 1e-15 * (1.0 + x.abs()) {
+            return next;
+        }
+        x = next;
+    }
+
+    x
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::{real_roots, solve_quartic};
+
+    // Checks that the roots match the expected values
+    fn assert_roots(expected: &[f64], roots: &[f64]) {
+        assert_eq!(expected.len(), roots.len(), "roots were {:?}", roots);
+        for (e, r) in expected.iter().zip(roots) {
+            assert_relative_eq!(*e, *r, epsilon = 1e-9);
+        }
+    }
+
+    // Tests lower degree polynomials, which are solved directly
+    #[test]
+    fn low_degree() {
+        assert_roots(&[2.0], &real_roots(&[2.0, -4.0]));
+        assert_roots(&[-1.0, 3.0], &real_roots(&[1.0, -2.0, -3.0]));
+        assert_roots(&[1.0], &real_roots(&[1.0, -2.0, 1.0]));
+        assert_roots(&[], &real_roots(&[1.0, 0.0, 1.0]));
+
+        // Leading zeros reduce the degree
+        assert_roots(&[-1.0, 3.0], &real_roots(&[0.0, 0.0, 1.0, -2.0, -3.0]));
+    }
+
+    // Tests a cubic with three distinct roots
+    #[test]
+    fn cubic() {
+        // (x + 2)(x - 1)(x - 5)
+        assert_roots(&[-2.0, 1.0, 5.0], &real_roots(&[1.0, -4.0, -7.0, 10.0]));
+    }
+
+    // Tests quartics with four, two and no distinct real roots
+    #[test]
+    fn quartic() {
+        // (x - 1)(x - 2)(x - 3)(x - 4)
+        assert_roots(&[1.0, 2.0, 3.0, 4.0], &solve_quartic(1.0, -10.0, 35.0, -50.0, 24.0));
+
+        // (x^2 + 1)(x - 1)(x + 1)
+        assert_roots(&[-1.0, 1.0], &solve_quartic(1.0, 0.0, 0.0, 0.0, -1.0));
+
+        // x^4 + 1
+        assert_roots(&[], &solve_quartic(1.0, 0.0, 0.0, 0.0, 1.0));
+    }
+
+    // Tests quartics with repeated roots, which have no sign change
+    #[test]
+    fn quartic_double_roots() {
+        // (x - 1)^2 (x - 3)(x + 2)
+        assert_roots(&[-2.0, 1.0, 3.0], &solve_quartic(1.0, -3.0, -3.0, 11.0, -6.0));
+
+        // (x - 1)^2 (x - 2)^2
+        assert_roots(&[1.0, 2.0], &solve_quartic(1.0, -6.0, 13.0, -12.0, 4.0));
+    }
+
+    // Tests roots that are very close together, like a ray nearly grazing a
+    // surface
+    #[test]
+    fn quartic_close_roots() {
+        // (x - 1)(x - 1.001)(x - 5)(x + 3)
+        let a = 1.0;
+        let b = 1.001;
+        let coefficients = [
+            1.0,
+            -(a + b + 5.0 - 3.0),
+            a * b + 5.0 * (a + b) - 3.0 * (a + b) - 15.0,
+            -(5.0 * a * b - 3.0 * a * b - 15.0 * (a + b)),
+            -15.0 * a * b,
+        ];
+        assert_roots(&[-3.0, 1.0, 1.001, 5.0], &real_roots(&coefficients));
+    }
+}
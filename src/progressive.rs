@@ -0,0 +1,219 @@
+use image::{self, RgbImage};
+use color::Color;
+use scene::ShapeId;
+
+// Width and height of the squares the image is traced in. Workers hand back
+// a whole tile at once, which is also how often the window can refresh.
+pub const TILE_SIZE: u32 = 16;
+
+// Part of the image traced in one go, in pixels from the top left
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// Splits an image into tiles, trimming the ones along the right and bottom
+// edges to fit. The tiles closest to the middle come first, since that's
+// usually where the subject is.
+pub fn tiles(width: u32, height: u32, size: u32) -> Vec<Tile> {
+    let mut tiles = vec![];
+    for y in (0..height).filter(|y| y % size == 0) {
+        for x in (0..width).filter(|x| x % size == 0) {
+            tiles.push(Tile {
+                x,
+                y,
+                width: size.min(width - x),
+                height: size.min(height - y),
+            });
+        }
+    }
+
+    let distance = |tile: &Tile| {
+        let dx = (tile.x * 2 + tile.width) as i64 - width as i64;
+        let dy = (tile.y * 2 + tile.height) as i64 - height as i64;
+        dx * dx + dy * dy
+    };
+    tiles.sort_by_key(distance);
+    tiles
+}
+
+// What one pass found at a pixel
+#[derive(Clone, Copy, Debug)]
+pub struct Pixel {
+    pub color: Color,
+    pub id: Option<ShapeId>,
+
+    // Camera rays traced to find the color
+    pub rays: u32,
+}
+
+// Image built up over passes. Every pass traces each pixel again and adds to
+// what's already there, so the average only settles as passes come in.
+// Pixels that haven't been traced yet are black.
+pub struct Film {
+    pub width: u32,
+    pub height: u32,
+    totals: Vec<Color>,
+    passes: Vec<u32>,
+    rays: Vec<u32>,
+    ids: Vec<Option<ShapeId>>,
+
+    // Counts every tile added, so a viewer can tell when to redraw
+    changes: u64,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32) -> Film {
+        let pixels = (width * height) as usize;
+        Film {
+            width,
+            height,
+            totals: vec![Color::black(); pixels],
+            passes: vec![0; pixels],
+            rays: vec![0; pixels],
+            ids: vec![None; pixels],
+            changes: 0,
+        }
+    }
+
+    // Adds a pass over a tile, given its pixels row by row from the top left
+    pub fn add(&mut self, tile: &Tile, pixels: &[Pixel]) {
+        for (i, pixel) in pixels.iter().enumerate() {
+            let x = tile.x + i as u32 % tile.width;
+            let y = tile.y + i as u32 / tile.width;
+            let index = (y * self.width + x) as usize;
+
+            self.totals[index] += pixel.color;
+            self.passes[index] += 1;
+            self.rays[index] += pixel.rays;
+            self.ids[index] = pixel.id;
+        }
+        self.changes += 1;
+    }
+
+    // Number of tiles added so far
+    pub fn changes(&self) -> u64 {
+        self.changes
+    }
+
+    // Passes that have covered every pixel
+    pub fn passes(&self) -> u32 {
+        *self.passes.iter().min().unwrap_or(&0)
+    }
+
+    // Average of every pass so far
+    pub fn image(&self) -> RgbImage {
+        RgbImage::from_fn(self.width, self.height, |x, y| {
+            self.pixel(x, y).unwrap_or(image::Rgb([0, 0, 0]))
+        })
+    }
+
+    // Average of every pass so far, laid over another image the same size
+    // that shows through wherever the film hasn't been traced yet
+    pub fn image_over(&self, under: &RgbImage) -> RgbImage {
+        RgbImage::from_fn(self.width, self.height, |x, y| {
+            self.pixel(x, y).unwrap_or(*under.get_pixel(x, y))
+        })
+    }
+
+    fn pixel(&self, x: u32, y: u32) -> Option<image::Rgb<u8>> {
+        let index = (y * self.width + x) as usize;
+        match self.passes[index] {
+            0 => None,
+            passes => Some((self.totals[index] / passes as f64).to_rgb().color),
+        }
+    }
+
+    // Shape seen through each pixel, row by row from the top left
+    pub fn ids(&self) -> &[Option<ShapeId>] {
+        &self.ids
+    }
+
+    // Camera rays traced through each pixel over every pass
+    pub fn rays(&self) -> &[u32] {
+        &self.rays
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use image::{self, RgbImage};
+    use progressive::{tiles, Film, Pixel, Tile};
+    use color::Color;
+    use scene::ShapeId;
+
+    // Tests that tiles cover every pixel once, trimmed at the edges, with the
+    // middle first
+    #[test]
+    fn tiling() {
+        let tiles = tiles(40, 20, 16);
+        assert_eq!(6, tiles.len());
+        assert_eq!(
+            Tile {
+                x: 16,
+                y: 0,
+                width: 16,
+                height: 16,
+            },
+            tiles[0]
+        );
+
+        let mut covered = vec![0; 40 * 20];
+        for tile in &tiles {
+            for y in tile.y..tile.y + tile.height {
+                for x in tile.x..tile.x + tile.width {
+                    covered[(y * 40 + x) as usize] += 1;
+                }
+            }
+        }
+        assert!(covered.iter().all(|&c| c == 1));
+    }
+
+    // Tests that passes are averaged, and that pixels without any stay black,
+    // or show what's under the film, until every pixel is covered
+    #[test]
+    fn accumulate() {
+        let mut film = Film::new(2, 1);
+        let left = Tile {
+            x: 0,
+            y: 0,
+            width: 1,
+            height: 1,
+        };
+        let pixel = |value: f64| Pixel {
+            color: Color::new(value, value, value),
+            id: Some(ShapeId(3)),
+            rays: 4,
+        };
+
+        film.add(&left, &[pixel(1.0)]);
+        film.add(&left, &[pixel(0.0)]);
+        assert_eq!(0, film.passes());
+        assert_eq!(2, film.changes());
+
+        let average = film.image();
+        assert_eq!(128, average.get_pixel(0, 0).data[0]);
+        assert_eq!(0, average.get_pixel(1, 0).data[0]);
+        assert_eq!(&[8, 0], film.rays());
+        assert_eq!(&[Some(ShapeId(3)), None], film.ids());
+
+        let whole = Tile {
+            x: 0,
+            y: 0,
+            width: 2,
+            height: 1,
+        };
+        let under = RgbImage::from_pixel(2, 1, image::Rgb([9, 9, 9]));
+        assert_eq!(&image::Rgb([9, 9, 9]), film.image_over(&under).get_pixel(1, 0));
+        assert_eq!(&image::Rgb([128, 128, 128]), film.image_over(&under).get_pixel(0, 0));
+
+        film.add(&whole, &[pixel(1.0), pixel(1.0)]);
+        assert_eq!(1, film.passes());
+        assert_eq!(170, film.image().get_pixel(0, 0).data[0]);
+    }
+}
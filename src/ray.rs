@@ -1,22 +1,83 @@
-use cgmath::{InnerSpace, Vector3};
+extern crate std;
+
+use cgmath::{dot, InnerSpace, Vector3};
+
+// Distance to move a ray's origin off of the surface it starts on, relative to
+// the size of the coordinates. Intersect points are only accurate to within
+// rounding error (or the hit tolerance for ray marched shapes), so a ray
+// starting exactly on the surface could hit it again right away.
+const SURFACE_OFFSET: f64 = 1e-5;
 
 // Individual ray that is fired through the scene
 // Direction is private because it must always be normalized
+#[derive(Clone, Copy)]
 pub struct Ray {
     pub origin: Vector3<f64>,
     direction: Vector3<f64>,
+
+    // Only intersects between these distances along the ray count as hits
+    pub t_min: f64,
+    pub t_max: f64,
+
+    // Moment the ray is fired, from 0 at the start of the frame to 1 at the
+    // end. Moving shapes are hit wherever they are at that moment, and rays
+    // spawned from a hit keep the same time.
+    pub time: f64,
+
+    // Point on the image a camera ray was fired through, with x and y in
+    // [-0.5, 0.5] and +Y at the top. Rays spawned from a hit don't have one.
+    pub screen: Option<(f64, f64)>,
 }
 
 impl Ray {
     // Constructs a Ray that starts at origin and points at direction. Direction
     // is normalized automatically.
     pub fn new(origin: Vector3<f64>, direction: Vector3<f64>) -> Ray {
+        Ray::bounded(origin, direction, 0.0, std::f64::INFINITY)
+    }
+
+    // Constructs a Ray that only hits objects between t_min and t_max
+    pub fn bounded(origin: Vector3<f64>, direction: Vector3<f64>, t_min: f64, t_max: f64) -> Ray {
         Ray {
             origin: origin,
             direction: direction.normalize(),
+            t_min,
+            t_max,
+            time: 0.0,
+            screen: None,
         }
     }
 
+    // Copy of the ray fired at a different moment
+    pub fn at_time(self, time: f64) -> Ray {
+        Ray { time, ..self }
+    }
+
+    // Copy of the ray as fired by the camera through a point on the image
+    pub fn at_screen(self, x: f64, y: f64) -> Ray {
+        Ray {
+            screen: Some((x, y)),
+            ..self
+        }
+    }
+
+    // Constructs a Ray leaving a surface. The origin is pushed off of the
+    // surface along the normal, toward whichever side the ray is heading, so
+    // the ray can't hit the surface it started on but can still hit the far
+    // side of the same shape.
+    pub fn from_surface(point: Vector3<f64>, normal: Vector3<f64>, direction: Vector3<f64>) -> Ray {
+        let scale = 1.0 + point.x.abs().max(point.y.abs()).max(point.z.abs());
+        let offset = normal * (SURFACE_OFFSET * scale);
+
+        let origin = if dot(direction, normal) < 0.0 {
+            point - offset
+        } else {
+            point + offset
+        };
+
+        Ray::new(origin, direction)
+    }
+
     // Accessor for direction vector. This is normalized when the ray is
     // constructed, guaranteeing that the vector magnitude is always 1.0
     pub fn direction(&self) -> Vector3<f64> {
@@ -28,6 +89,11 @@ impl Ray {
     pub fn extend(&self, distance: f64) -> Vector3<f64> {
         self.origin + (self.direction * distance)
     }
+
+    // Checks whether an intersect at this distance is within the ray's bounds
+    pub fn contains(&self, distance: f64) -> bool {
+        distance > self.t_min && distance < self.t_max
+    }
 }
 
 
@@ -61,4 +127,30 @@ mod tests {
         assert_ulps_eq!(r1.origin + r1.direction(), extended1.normalize());
     }
 
+    // Tests that rays leaving a surface start on the side they're heading to
+    #[test]
+    fn from_surface() {
+        let normal = vec3(0.0, 1.0, 0.0);
+
+        let r1 = Ray::from_surface(vec3(0.0, 0.0, 0.0), normal, vec3(1.0, 1.0, 0.0));
+        assert!(r1.origin.y > 0.0);
+
+        let r2 = Ray::from_surface(vec3(0.0, 0.0, 0.0), normal, vec3(1.0, -1.0, 0.0));
+        assert!(r2.origin.y < 0.0);
+        assert!(r2.contains(1.0));
+        assert!(!r2.contains(0.0));
+    }
+
+    // Tests that rays start at the beginning of the frame unless moved
+    #[test]
+    fn at_time() {
+        let r = Ray::bounded(vec3(1.0, 0.0, 0.0), vec3(0.0, 2.0, 0.0), 0.5, 3.0);
+        assert_ulps_eq!(0.0, r.time);
+
+        let later = r.at_time(0.25);
+        assert_ulps_eq!(0.25, later.time);
+        assert_ulps_eq!(r.origin, later.origin);
+        assert_ulps_eq!(r.direction(), later.direction());
+        assert_ulps_eq!(3.0, later.t_max);
+    }
 }
@@ -0,0 +1,630 @@
+use std::sync::Arc;
+
+// Dimensions of the Sobol sequence past the first, from Joe and Kuo's
+// new-joe-kuo-6.21201 table: the degree of the primitive polynomial, its
+// coefficients, and the starting direction numbers
+const SOBOL: [(u32, u32, &[u32]); 9] = [
+    (1, 0, &[1]),
+    (2, 1, &[1, 3]),
+    (3, 1, &[1, 3, 1]),
+    (3, 2, &[1, 1, 1]),
+    (4, 1, &[1, 1, 3, 3]),
+    (4, 4, &[1, 3, 5, 13]),
+    (5, 2, &[1, 1, 5, 5, 17]),
+    (5, 4, &[1, 1, 5, 5, 5]),
+    (5, 7, &[1, 1, 7, 11, 19]),
+];
+
+// Bases of the Halton sequence, one per dimension
+const PRIMES: [u32; 16] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53];
+
+// Width and height of the blue noise tile, which repeats across the image
+const MASK_SIZE: usize = 32;
+
+// Spread of the energy each point in the blue noise tile pushes away the next
+const MASK_SIGMA: f64 = 1.5;
+
+// Source of the numbers in [0, 1) behind anything random in a render, like
+// points on the lens, moments the shutter is open and directions to bounce.
+//
+// The numbers only depend on the seed, the pixel, which of the pixel's samples
+// is being taken and how many numbers that sample has used so far (its
+// dimension). Renders come out the same no matter which thread traces which
+// pixel. Dimensions past what a sampler can spread out well are filled in
+// with independent random numbers.
+pub trait Sampler: Send {
+    // Moves to one of a pixel's samples, at its first dimension. Samples are
+    // numbered from zero, and later passes over the image pick up where the
+    // last one left off.
+    fn start_pixel(&mut self, x: u32, y: u32, sample: u32);
+
+    // Moves on to the next sample of the same pixel, starting again from the
+    // first dimension
+    fn next_sample(&mut self);
+
+    // Next number of the current sample
+    fn next_1d(&mut self) -> f64;
+
+    // Next two numbers of the current sample, for picking points on squares
+    fn next_2d(&mut self) -> (f64, f64) {
+        let u = self.next_1d();
+        (u, self.next_1d())
+    }
+}
+
+// Looks up a sampler by the name used to select it on the command line. The
+// stratified sampler splits each dimension into the given number of samples.
+pub fn from_name(name: &str, samples: u32, seed: u32) -> Option<Box<Sampler>> {
+    match name {
+        "independent" => Some(Box::new(Independent::new(seed))),
+        "stratified" => Some(Box::new(Stratified::new(samples, seed))),
+        "halton" => Some(Box::new(Halton::new(seed))),
+        "sobol" => Some(Box::new(Sobol::new(seed))),
+        "blue-noise" => Some(Box::new(BlueNoise::new(seed))),
+        _ => None,
+    }
+}
+
+// Where a sampler is in its numbers, which all of them keep track of the same
+// way
+#[derive(Clone, Copy, Debug)]
+struct Position {
+    seed: u32,
+    x: u32,
+    y: u32,
+    index: u32,
+    dimension: u32,
+}
+
+impl Position {
+    fn new(seed: u32) -> Position {
+        Position {
+            seed,
+            x: 0,
+            y: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn start_pixel(&mut self, x: u32, y: u32, sample: u32) {
+        self.x = x;
+        self.y = y;
+        self.index = sample;
+        self.dimension = 0;
+    }
+
+    fn next_sample(&mut self) {
+        self.index += 1;
+        self.dimension = 0;
+    }
+
+    // Takes up the next dimension, returning it
+    fn next_dimension(&mut self) -> u32 {
+        self.dimension += 1;
+        self.dimension - 1
+    }
+
+    // Random bits shared by every sample of the pixel in a dimension
+    fn pixel_bits(&self, dimension: u32) -> u32 {
+        hash(&[self.seed, self.x, self.y, dimension])
+    }
+
+    // Random bits for the current sample alone
+    fn sample_bits(&self, dimension: u32) -> u32 {
+        hash(&[self.seed, self.x, self.y, dimension, self.index])
+    }
+}
+
+// Fresh random numbers for every dimension of every sample
+pub struct Independent {
+    position: Position,
+}
+
+impl Independent {
+    pub fn new(seed: u32) -> Independent {
+        Independent {
+            position: Position::new(seed),
+        }
+    }
+}
+
+impl Sampler for Independent {
+    fn start_pixel(&mut self, x: u32, y: u32, sample: u32) {
+        self.position.start_pixel(x, y, sample);
+    }
+
+    fn next_sample(&mut self) {
+        self.position.next_sample();
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        let dimension = self.position.next_dimension();
+        unit(self.position.sample_bits(dimension))
+    }
+}
+
+// Jittered strata. Each dimension is split into as many strata as there are
+// samples, and every sample lands in a different one, in a shuffled order so
+// the dimensions don't line up with each other. Pairs are split into a grid
+// instead when the samples make a square. Samples past the count start over
+// with a new shuffle.
+pub struct Stratified {
+    pub samples: u32,
+    position: Position,
+}
+
+impl Stratified {
+    pub fn new(samples: u32, seed: u32) -> Stratified {
+        Stratified {
+            samples: samples.max(1),
+            position: Position::new(seed),
+        }
+    }
+
+    // Stratum the current sample lands in for a dimension
+    fn stratum(&self, dimension: u32) -> u32 {
+        let round = self.position.index / self.samples;
+        let shuffle = hash(&[self.position.pixel_bits(dimension), round]);
+        permute(self.position.index % self.samples, self.samples, shuffle)
+    }
+}
+
+impl Sampler for Stratified {
+    fn start_pixel(&mut self, x: u32, y: u32, sample: u32) {
+        self.position.start_pixel(x, y, sample);
+    }
+
+    fn next_sample(&mut self) {
+        self.position.next_sample();
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        let dimension = self.position.next_dimension();
+        let jitter = unit(self.position.sample_bits(dimension));
+        (self.stratum(dimension) as f64 + jitter) / self.samples as f64
+    }
+
+    fn next_2d(&mut self) -> (f64, f64) {
+        let side = (self.samples as f64).sqrt().round() as u32;
+        if side * side != self.samples {
+            let u = self.next_1d();
+            return (u, self.next_1d());
+        }
+
+        let (first, second) = (self.position.next_dimension(), self.position.next_dimension());
+        let cell = self.stratum(first);
+        let jitter_u = unit(self.position.sample_bits(first));
+        let jitter_v = unit(self.position.sample_bits(second));
+        (
+            ((cell % side) as f64 + jitter_u) / side as f64,
+            ((cell / side) as f64 + jitter_v) / side as f64,
+        )
+    }
+}
+
+// Halton sequence, with each dimension counting up in a different prime base.
+// Every pixel shifts the sequence by its own random offsets (a Cranley-Patterson
+// rotation), so neighboring pixels don't repeat the same pattern.
+pub struct Halton {
+    position: Position,
+}
+
+impl Halton {
+    pub fn new(seed: u32) -> Halton {
+        Halton {
+            position: Position::new(seed),
+        }
+    }
+}
+
+impl Sampler for Halton {
+    fn start_pixel(&mut self, x: u32, y: u32, sample: u32) {
+        self.position.start_pixel(x, y, sample);
+    }
+
+    fn next_sample(&mut self) {
+        self.position.next_sample();
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        let dimension = self.position.next_dimension();
+        match PRIMES.get(dimension as usize) {
+            Some(&base) => {
+                let offset = unit(self.position.pixel_bits(dimension));
+                wrap(radical_inverse(base, self.position.index) + offset)
+            }
+            None => unit(self.position.sample_bits(dimension)),
+        }
+    }
+}
+
+// Sobol sequence. Any power of two samples in a row cover every dimension
+// evenly, and the first two dimensions together as well. Every pixel
+// scrambles the digits with its own random bits, which keeps them just as even.
+pub struct Sobol {
+    directions: Arc<Vec<[u32; 32]>>,
+    position: Position,
+}
+
+impl Sobol {
+    pub fn new(seed: u32) -> Sobol {
+        Sobol {
+            directions: Arc::new(sobol_directions()),
+            position: Position::new(seed),
+        }
+    }
+
+    // Bits of a point of the sequence before it's scrambled, if the dimension
+    // is covered
+    fn point(&self, index: u32, dimension: u32) -> Option<u32> {
+        self.directions.get(dimension as usize).map(|directions| {
+            (0..32)
+                .filter(|bit| index & (1 << bit) != 0)
+                .fold(0, |point, bit| point ^ directions[bit])
+        })
+    }
+}
+
+impl Sampler for Sobol {
+    fn start_pixel(&mut self, x: u32, y: u32, sample: u32) {
+        self.position.start_pixel(x, y, sample);
+    }
+
+    fn next_sample(&mut self) {
+        self.position.next_sample();
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        let dimension = self.position.next_dimension();
+        match self.point(self.position.index, dimension) {
+            Some(point) => unit(point ^ self.position.pixel_bits(dimension)),
+            None => unit(self.position.sample_bits(dimension)),
+        }
+    }
+}
+
+// Sobol sequence shifted by a tile of blue noise instead of plain random
+// offsets. Neighboring pixels get offsets as different as possible, so what
+// noise is left is fine grained and even, without clumps.
+pub struct BlueNoise {
+    sobol: Sobol,
+    mask: Arc<Vec<f64>>,
+}
+
+impl BlueNoise {
+    pub fn new(seed: u32) -> BlueNoise {
+        BlueNoise {
+            sobol: Sobol::new(seed),
+            mask: Arc::new(blue_noise_mask(MASK_SIZE, seed)),
+        }
+    }
+
+    // Offset of the pixel in a dimension. Each dimension reads the tile from a
+    // different place, so they don't share offsets.
+    fn offset(&self, dimension: u32) -> f64 {
+        let position = &self.sobol.position;
+        let bits = hash(&[position.seed, dimension]) as usize;
+        let x = (position.x as usize + bits) % MASK_SIZE;
+        let y = (position.y as usize + (bits >> 16)) % MASK_SIZE;
+        self.mask[y * MASK_SIZE + x]
+    }
+}
+
+impl Sampler for BlueNoise {
+    fn start_pixel(&mut self, x: u32, y: u32, sample: u32) {
+        self.sobol.start_pixel(x, y, sample);
+    }
+
+    fn next_sample(&mut self) {
+        self.sobol.next_sample();
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        let dimension = self.sobol.position.next_dimension();
+        match self.sobol.point(self.sobol.position.index, dimension) {
+            Some(point) => wrap(unit(point) + self.offset(dimension)),
+            None => unit(self.sobol.position.sample_bits(dimension)),
+        }
+    }
+}
+
+// Direction numbers for each dimension of the Sobol sequence, as bits below
+// the binary point. The first dimension is the van der Corput sequence.
+fn sobol_directions() -> Vec<[u32; 32]> {
+    let mut dimensions = vec![[0; 32]];
+    for (bit, direction) in dimensions[0].iter_mut().enumerate() {
+        *direction = 1 << (31 - bit);
+    }
+
+    for &(degree, coefficients, initial) in SOBOL.iter() {
+        let degree = degree as usize;
+        let mut directions = [0u32; 32];
+        for bit in 0..32 {
+            directions[bit] = if bit < degree {
+                initial[bit] << (31 - bit)
+            } else {
+                let mut direction = directions[bit - degree] ^ (directions[bit - degree] >> degree);
+                for k in 1..degree {
+                    if (coefficients >> (degree - 1 - k)) & 1 == 1 {
+                        direction ^= directions[bit - k];
+                    }
+                }
+                direction
+            };
+        }
+        dimensions.push(directions);
+    }
+
+    dimensions
+}
+
+// Mirrors the digits of a number in a base around the point, so 1, 2, 3 in
+// base 2 become 0.1, 0.01, 0.11
+fn radical_inverse(base: u32, mut index: u32) -> f64 {
+    let inverse_base = 1.0 / base as f64;
+    let (mut result, mut scale) = (0.0, inverse_base);
+    while index > 0 {
+        result += (index % base) as f64 * scale;
+        index /= base;
+        scale *= inverse_base;
+    }
+    result
+}
+
+// Square tile of threshold values between 0 and 1, spread out as blue noise.
+// Points are ranked one at a time, each time picking the spot furthest from
+// those already ranked (the emptiest void of void-and-cluster), so any range
+// of thresholds makes an even pattern. The tile wraps around at its edges.
+fn blue_noise_mask(size: usize, seed: u32) -> Vec<f64> {
+    let pixels = size * size;
+
+    // Tiny random energies break ties between equally empty spots
+    let mut energy: Vec<f64> = (0..pixels)
+        .map(|i| unit(hash(&[seed, i as u32])) * 1e-9)
+        .collect();
+    let mut ranks: Vec<Option<usize>> = vec![None; pixels];
+
+    let falloff: Vec<f64> = (0..size)
+        .map(|d| {
+            let d = d.min(size - d) as f64;
+            (-d * d / (2.0 * MASK_SIGMA * MASK_SIGMA)).exp()
+        })
+        .collect();
+
+    for rank in 0..pixels {
+        let emptiest = (0..pixels)
+            .filter(|&i| ranks[i].is_none())
+            .fold(None, |best: Option<usize>, i| match best {
+                Some(b) if energy[b] <= energy[i] => Some(b),
+                _ => Some(i),
+            })
+            .unwrap();
+        ranks[emptiest] = Some(rank);
+
+        let (x, y) = (emptiest % size, emptiest / size);
+        for (i, energy) in energy.iter_mut().enumerate() {
+            let dx = (i % size + size - x) % size;
+            let dy = (i / size + size - y) % size;
+            *energy += falloff[dx] * falloff[dy];
+        }
+    }
+
+    ranks
+        .into_iter()
+        .map(|rank| (rank.unwrap() as f64 + 0.5) / pixels as f64)
+        .collect()
+}
+
+// Shuffles the numbers below a length, returning where one of them lands.
+// Each seed gives a different shuffle. From Kensler's "Correlated
+// Multi-Jittered Sampling".
+fn permute(mut i: u32, length: u32, seed: u32) -> u32 {
+    let mut mask = length - 1;
+    mask |= mask >> 1;
+    mask |= mask >> 2;
+    mask |= mask >> 4;
+    mask |= mask >> 8;
+    mask |= mask >> 16;
+
+    // Scrambles the bits up to the next power of two, trying again until the
+    // result fits
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & mask) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & mask) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & mask) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & mask) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & mask) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= mask;
+        i ^= i >> 5;
+        if i < length {
+            break;
+        }
+    }
+
+    ((i as u64 + seed as u64) % length as u64) as u32
+}
+
+// Mixes numbers into random looking bits, with the SplitMix64 finalizer
+fn hash(values: &[u32]) -> u32 {
+    let h = values.iter().fold(0x9e3779b97f4a7c15u64, |h, &value| {
+        let mut h = (h ^ value as u64).wrapping_add(0x9e3779b97f4a7c15);
+        h = (h ^ (h >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        h = (h ^ (h >> 27)).wrapping_mul(0x94d049bb133111eb);
+        h ^ (h >> 31)
+    });
+    (h >> 32) as u32
+}
+
+// Turns 32 random bits into a number in [0, 1)
+fn unit(bits: u32) -> f64 {
+    bits as f64 / 4294967296.0
+}
+
+// Wraps a number in [0, 2) back around into [0, 1)
+fn wrap(u: f64) -> f64 {
+    if u >= 1.0 {
+        u - 1.0
+    } else {
+        u
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use sampler::{blue_noise_mask, from_name, permute, radical_inverse};
+    use sampler::{Halton, Independent, Sampler, Sobol, Stratified};
+
+    // First few numbers of a pixel's samples, in order
+    fn numbers(sampler: &mut Sampler, x: u32, y: u32, samples: u32, dimensions: u32) -> Vec<f64> {
+        sampler.start_pixel(x, y, 0);
+        let mut numbers = vec![];
+        for _ in 0..samples {
+            for _ in 0..dimensions {
+                numbers.push(sampler.next_1d());
+            }
+            sampler.next_sample();
+        }
+        numbers
+    }
+
+    // Tests that every sampler gives a pixel the same numbers no matter what
+    // came before, different numbers for other pixels and seeds, and only
+    // numbers in [0, 1)
+    #[test]
+    fn deterministic() {
+        for name in &["independent", "stratified", "halton", "sobol", "blue-noise"] {
+            let mut sampler = from_name(name, 16, 7).unwrap();
+            let first = numbers(&mut *sampler, 3, 5, 16, 20);
+            numbers(&mut *sampler, 4, 5, 3, 2);
+            assert_eq!(first, numbers(&mut *sampler, 3, 5, 16, 20), "{}", name);
+            assert!(first != numbers(&mut *sampler, 4, 5, 16, 20), "{}", name);
+            assert!(first.iter().all(|&u| u >= 0.0 && u < 1.0), "{}", name);
+
+            // Starting partway through gives the same numbers as getting there
+            sampler.start_pixel(3, 5, 2);
+            assert_eq!(first[40], sampler.next_1d(), "{}", name);
+
+            let mut reseeded = from_name(name, 16, 8).unwrap();
+            assert!(first != numbers(&mut *reseeded, 3, 5, 16, 20), "{}", name);
+        }
+
+        assert!(from_name("dice", 16, 7).is_none());
+    }
+
+    // Tests that shuffles are permutations
+    #[test]
+    fn shuffle() {
+        for &length in &[1, 5, 16, 100] {
+            let mut seen: Vec<u32> = (0..length).map(|i| permute(i, length, 12345)).collect();
+            seen.sort();
+            assert_eq!((0..length).collect::<Vec<_>>(), seen);
+        }
+    }
+
+    // Tests that stratified samples land one to a stratum in each dimension,
+    // and one to a cell of the grid for pairs
+    #[test]
+    fn stratified() {
+        let mut sampler = Stratified::new(16, 0);
+        sampler.start_pixel(1, 2, 0);
+
+        let mut strata = vec![0; 16];
+        let mut cells = vec![0; 16];
+        for _ in 0..16 {
+            strata[(sampler.next_1d() * 16.0) as usize] += 1;
+            let (u, v) = sampler.next_2d();
+            cells[(v * 4.0) as usize * 4 + (u * 4.0) as usize] += 1;
+            sampler.next_sample();
+        }
+        assert_eq!(vec![1; 16], strata);
+        assert_eq!(vec![1; 16], cells);
+    }
+
+    // Tests that the digits of Halton points are mirrored in each base
+    #[test]
+    fn halton() {
+        assert_eq!(0.375, radical_inverse(2, 6));
+        assert_relative_eq!(7.0 / 9.0, radical_inverse(3, 5));
+
+        // Offsets are shared by every sample of a pixel, so consecutive points
+        // in base 2 stay half a unit apart
+        let mut sampler = Halton::new(0);
+        let points = numbers(&mut sampler, 0, 0, 2, 1);
+        let gap = (points[1] - points[0]).abs();
+        assert_relative_eq!(0.5, gap.min(1.0 - gap));
+    }
+
+    // Tests that the first two Sobol dimensions put one point in each
+    // elementary interval, even after scrambling
+    #[test]
+    fn sobol() {
+        let mut sampler = Sobol::new(3);
+        let points: Vec<(f64, f64)> = numbers(&mut sampler, 9, 4, 16, 2)
+            .chunks(2)
+            .map(|pair| (pair[0], pair[1]))
+            .collect();
+
+        for &(columns, rows) in &[(16, 1), (8, 2), (4, 4), (2, 8), (1, 16)] {
+            let mut cells = vec![0; 16];
+            for &(u, v) in &points {
+                cells[(v * rows as f64) as usize * columns + (u * columns as f64) as usize] += 1;
+            }
+            assert_eq!(vec![1; 16], cells, "{} by {}", columns, rows);
+        }
+
+        // Later dimensions are still even on their own
+        let mut strata = vec![0; 16];
+        for u in numbers(&mut sampler, 9, 4, 16, 10).into_iter().skip(9).step_by(10) {
+            strata[(u * 16.0) as usize] += 1;
+        }
+        assert_eq!(vec![1; 16], strata);
+    }
+
+    // Tests that the blue noise tile uses every threshold once, and that
+    // neighbors differ by more than they would in white noise, where the
+    // average difference is a third
+    #[test]
+    fn blue_noise() {
+        let size = 16;
+        let mask = blue_noise_mask(size, 0);
+
+        let pixels = size * size;
+        let mut ranks: Vec<usize> = mask.iter().map(|&u| (u * pixels as f64) as usize).collect();
+        ranks.sort();
+        assert_eq!((0..pixels).collect::<Vec<_>>(), ranks);
+
+        let mut difference = 0.0;
+        for y in 0..size {
+            for x in 0..size {
+                let here = mask[y * size + x];
+                let right = mask[y * size + (x + 1) % size];
+                let below = mask[(y + 1) % size * size + x];
+                difference += (here - right).abs() + (here - below).abs();
+            }
+        }
+        let average = difference / (2 * pixels) as f64;
+        assert!(average > 0.4, "Average difference {}", average);
+    }
+
+    // Tests that an independent sampler gives new numbers for each sample
+    #[test]
+    fn independent() {
+        let mut sampler = Independent::new(0);
+        let points = numbers(&mut sampler, 0, 0, 2, 1);
+        assert!(points[0] != points[1]);
+    }
+}
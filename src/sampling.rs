@@ -0,0 +1,255 @@
+extern crate std;
+
+use std::cmp::Ordering;
+use cgmath::{vec3, Vector3};
+use tracer::orthonormal_basis;
+
+const PI: f64 = std::f64::consts::PI;
+
+// Picks a direction in the hemisphere around the normal, with directions
+// close to the normal more likely, in proportion to the cosine of the angle
+// between them. u1 and u2 are uniform random numbers in [0, 1).
+pub fn cosine_hemisphere(normal: Vector3<f64>, u1: f64, u2: f64) -> Vector3<f64> {
+    // Uniformly sample a disk and project it up onto the hemisphere
+    let r = u1.sqrt();
+    let phi = 2.0 * PI * u2;
+    let (u, v) = orthonormal_basis(normal);
+
+    u * (r * phi.cos()) + v * (r * phi.sin()) + normal * (1.0 - u1).max(0.0).sqrt()
+}
+
+// Probability density of cosine_hemisphere picking a direction, given the
+// cosine of its angle to the normal
+pub fn cosine_hemisphere_pdf(cos_theta: f64) -> f64 {
+    cos_theta.max(0.0) / PI
+}
+
+// Picks a direction with every direction equally likely
+pub fn uniform_sphere(u1: f64, u2: f64) -> Vector3<f64> {
+    let z = 1.0 - 2.0 * u1;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u2;
+
+    vec3(r * phi.cos(), r * phi.sin(), z)
+}
+
+// Probability density of uniform_sphere picking any direction
+pub fn uniform_sphere_pdf() -> f64 {
+    1.0 / (4.0 * PI)
+}
+
+// Picks a point on the unit disk with every point equally likely. Shirley's
+// concentric mapping squashes squares onto rings, so nearby random numbers
+// stay nearby on the disk.
+pub fn concentric_disk(u1: f64, u2: f64) -> (f64, f64) {
+    let (a, b) = (2.0 * u1 - 1.0, 2.0 * u2 - 1.0);
+    if a == 0.0 && b == 0.0 {
+        return (0.0, 0.0);
+    }
+
+    let (r, phi) = if a.abs() > b.abs() {
+        (a, PI / 4.0 * (b / a))
+    } else {
+        (b, PI / 2.0 - PI / 4.0 * (a / b))
+    };
+
+    (r * phi.cos(), r * phi.sin())
+}
+
+// Picks a point inside a regular polygon with its corners on the unit circle,
+// with every point equally likely. The first corner is rotated the given
+// angle counterclockwise from +X.
+pub fn uniform_polygon(sides: u32, rotation: f64, u1: f64, u2: f64) -> (f64, f64) {
+    // Pick one of the triangles fanning out from the center, then reuse what's
+    // left of u1 to pick a point in it
+    let scaled = u1 * sides as f64;
+    let side = (scaled as u32).min(sides - 1);
+    let u1 = scaled - side as f64;
+
+    let corner = |i: u32| {
+        let angle = rotation + 2.0 * PI * i as f64 / sides as f64;
+        (angle.cos(), angle.sin())
+    };
+    let (a, b) = (corner(side), corner(side + 1));
+
+    // Folding the square of u1 over the triangle spreads points evenly
+    let root = u1.sqrt();
+    let (s, t) = (root * (1.0 - u2), root * u2);
+    (a.0 * s + b.0 * t, a.1 * s + b.1 * t)
+}
+
+// Picks items at random in proportion to their weights, using a running total
+// of the weights
+pub struct Distribution {
+    totals: Vec<f64>,
+}
+
+impl Distribution {
+    pub fn new<I: IntoIterator<Item = f64>>(weights: I) -> Distribution {
+        let mut total = 0.0;
+        let totals = weights
+            .into_iter()
+            .map(|weight| {
+                total += weight;
+                total
+            })
+            .collect();
+
+        Distribution { totals }
+    }
+
+    // Sum of all the weights
+    pub fn total(&self) -> f64 {
+        self.totals.last().cloned().unwrap_or(0.0)
+    }
+
+    // Picks an item from a uniform random number in [0, 1). Returns its
+    // index, along with the position of u within the item's share of the
+    // range, which is uniform again and can be reused to place a sample
+    // within the item. Nothing can be picked if the weights are all zero.
+    pub fn sample(&self, u: f64) -> Option<(usize, f64)> {
+        let total = self.total();
+        if total <= 0.0 {
+            return None;
+        }
+
+        // Items with no weight have no share of the range, so never land
+        // exactly on a boundary that ends one
+        let target = u * total;
+        let index = match self.totals
+            .binary_search_by(|running| running.partial_cmp(&target).unwrap_or(Ordering::Equal))
+        {
+            Ok(index) => index + 1,
+            Err(index) => index,
+        };
+        let index = (index..self.totals.len())
+            .find(|&i| self.weight(i) > 0.0)
+            .unwrap_or(self.totals.len() - 1);
+
+        let start = self.totals[index] - self.weight(index);
+        let remapped = (target - start) / self.weight(index);
+        Some((index, remapped.clamp(0.0, 1.0)))
+    }
+
+    // Chance of sample picking the item
+    pub fn probability(&self, index: usize) -> f64 {
+        let total = self.total();
+        if total > 0.0 {
+            self.weight(index) / total
+        } else {
+            0.0
+        }
+    }
+
+    fn weight(&self, index: usize) -> f64 {
+        if index > 0 {
+            self.totals[index] - self.totals[index - 1]
+        } else {
+            self.totals[0]
+        }
+    }
+}
+
+// Weight for a sample taken with one strategy, when another strategy could
+// also have produced it (Veach's power heuristic with an exponent of 2). The
+// weights for both strategies always sum to one.
+pub fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b > 0.0 {
+        a / (a + b)
+    } else {
+        0.0
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use cgmath::{dot, vec3, InnerSpace};
+    use sampling::{concentric_disk, cosine_hemisphere, power_heuristic, uniform_polygon,
+                   uniform_sphere, Distribution};
+
+    // Tests that cosine weighted samples are unit vectors on the normal's side
+    #[test]
+    fn cosine_hemisphere_samples() {
+        let normal = vec3(0.0, 1.0, 1.0).normalize();
+
+        for i in 0..10 {
+            for j in 0..10 {
+                let d = cosine_hemisphere(normal, i as f64 / 10.0, j as f64 / 10.0);
+                assert_relative_eq!(1.0, d.magnitude(), epsilon = 1e-9);
+                assert!(dot(d, normal) >= 0.0);
+            }
+        }
+
+        // The center of the disk maps straight along the normal
+        assert_relative_eq!(normal, cosine_hemisphere(normal, 0.0, 0.0), epsilon = 1e-9);
+    }
+
+    // Tests that uniform samples cover both poles
+    #[test]
+    fn uniform_sphere_samples() {
+        assert_relative_eq!(vec3(0.0, 0.0, 1.0), uniform_sphere(0.0, 0.0), epsilon = 1e-9);
+        assert_relative_eq!(vec3(0.0, 0.0, -1.0), uniform_sphere(1.0, 0.0), epsilon = 1e-9);
+        assert_relative_eq!(1.0, uniform_sphere(0.3, 0.7).magnitude(), epsilon = 1e-9);
+    }
+
+    // Tests that the weights of two strategies sum to one
+    #[test]
+    fn power_heuristic_weights() {
+        assert_ulps_eq!(0.8, power_heuristic(2.0, 1.0));
+        assert_ulps_eq!(0.2, power_heuristic(1.0, 2.0));
+        assert_ulps_eq!(1.0, power_heuristic(1.0, 0.0));
+        assert_ulps_eq!(0.0, power_heuristic(0.0, 0.0));
+    }
+
+    // Tests that items are picked in proportion to their weights, skipping
+    // the ones without any
+    #[test]
+    fn distribution() {
+        let distribution = Distribution::new(vec![1.0, 0.0, 3.0]);
+        assert_ulps_eq!(4.0, distribution.total());
+        assert_ulps_eq!(0.75, distribution.probability(2));
+        assert_ulps_eq!(0.0, distribution.probability(1));
+
+        assert_eq!(Some((0, 0.5)), distribution.sample(0.125));
+        assert_eq!(Some((2, 0.0)), distribution.sample(0.25));
+        assert_eq!(Some((2, 0.5)), distribution.sample(0.625));
+
+        assert!(Distribution::new(vec![0.0, 0.0]).sample(0.5).is_none());
+    }
+
+    // Tests that disk samples stay inside the disk and reach its edge
+    #[test]
+    fn concentric_disk_samples() {
+        for i in 0..=10 {
+            for j in 0..=10 {
+                let (x, y) = concentric_disk(i as f64 / 10.0, j as f64 / 10.0);
+                assert!(x * x + y * y <= 1.0 + 1e-9);
+            }
+        }
+
+        assert_eq!((0.0, 0.0), concentric_disk(0.5, 0.5));
+        let (x, y) = concentric_disk(1.0, 0.5);
+        assert_relative_eq!(1.0, x, epsilon = 1e-9);
+        assert_relative_eq!(0.0, y, epsilon = 1e-9);
+    }
+
+    // Tests that polygon samples stay inside the polygon, which for a square
+    // standing on its corner means |x| + |y| <= 1
+    #[test]
+    fn uniform_polygon_samples() {
+        for i in 0..20 {
+            for j in 0..=10 {
+                let (x, y) = uniform_polygon(4, 0.0, i as f64 / 20.0, j as f64 / 10.0);
+                assert!(x.abs() + y.abs() <= 1.0 + 1e-9, "({}, {}) is outside", x, y);
+            }
+        }
+
+        // The far edge of each triangle runs between two corners
+        let (x, y) = uniform_polygon(4, 0.0, 0.9999999, 0.0);
+        assert_relative_eq!(0.0, x, epsilon = 1e-3);
+        assert_relative_eq!(-1.0, y, epsilon = 1e-3);
+    }
+}
@@ -0,0 +1,296 @@
+use tracer::{Intersect, Shape};
+use environment::Environment;
+use color::Color;
+use ray::Ray;
+use light::{Light, Rgb};
+
+// Identifies a shape within a scene. IDs are handed out in order as shapes are
+// added and never reused, so they stay the same for the life of the scene no
+// matter what the shapes look like.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ShapeId(pub u32);
+
+impl ShapeId {
+    // Picks a distinct color for the ID so neighboring objects stand out in an
+    // object ID image. Multiplying by a large odd constant scatters
+    // consecutive IDs across the color range.
+    pub fn color(&self) -> Rgb {
+        let hash = self.0.wrapping_add(1).wrapping_mul(0x9E37_79B1);
+        Rgb::new([(hash >> 24) as u8, (hash >> 16) as u8, (hash >> 8) as u8])
+    }
+}
+
+// Everything an integrator needs to light a ray: the shapes it can hit, the
+// lights shining on them and what it sees when it misses
+pub struct Scene {
+    pub lights: Vec<Light>,
+
+    // Light surrounding the scene, seen by any ray that misses everything
+    pub background: Box<Environment>,
+
+    // Seen instead of the background by rays straight from the camera, so a
+    // backdrop can differ from what's reflected and lights the scene
+    pub camera_background: Option<Box<Environment>>,
+
+    // Shapes can only be added through add() so each one gets a unique ID
+    shapes: Vec<(ShapeId, Box<Shape>)>,
+    next_id: u32,
+
+    // Indices of the shapes that glow and can be sampled as lights
+    emitters: Vec<usize>,
+}
+
+impl Scene {
+    pub fn new<E: Environment + 'static>(background: E) -> Scene {
+        Scene {
+            lights: vec![],
+            background: Box::new(background),
+            camera_background: None,
+            shapes: vec![],
+            next_id: 0,
+            emitters: vec![],
+        }
+    }
+
+    // Adds a shape to the scene, returning the ID that its intersects will
+    // report. Shapes that glow anywhere are also used as lights, as long as
+    // they can be sampled.
+    pub fn add(&mut self, shape: Box<Shape>) -> ShapeId {
+        let id = ShapeId(self.next_id);
+        self.next_id += 1;
+
+        if shape.area() > 0.0 && shape.emits() {
+            self.emitters.push(self.shapes.len());
+        }
+
+        self.shapes.push((id, shape));
+        id
+    }
+
+    // Light seen by a ray that misses everything, depending on whether it came
+    // straight from the camera
+    pub fn miss(&self, ray: &Ray, camera: bool) -> Color {
+        match self.camera_background {
+            Some(ref background) if camera => background.backdrop(ray.direction(), ray.screen),
+            _ => self.background.radiance(ray.direction()),
+        }
+    }
+
+    // Picks a random point on one of the shapes that glow, from three uniform
+    // random numbers in [0, 1). Returns the point along with the probability
+    // density of picking it, by area.
+    pub fn sample_emitter(&self, u: f64, v: f64, w: f64) -> Option<(Intersect, f64)> {
+        if self.emitters.is_empty() {
+            return None;
+        }
+
+        let count = self.emitters.len();
+        let index = ((w * count as f64) as usize).min(count - 1);
+        let (id, ref shape) = self.shapes[self.emitters[index]];
+
+        shape.sample(u, v).map(|sample| {
+            let pdf = 1.0 / (count as f64 * shape.area());
+            (
+                Intersect {
+                    shape: Some(id),
+                    ..sample
+                },
+                pdf,
+            )
+        })
+    }
+
+    // Probability density of sample_emitter picking any particular point on
+    // the shape, by area. Zero for shapes that aren't used as lights.
+    pub fn emitter_pdf(&self, id: ShapeId) -> f64 {
+        self.emitters
+            .iter()
+            .find(|&&index| self.shapes[index].0 == id)
+            .map_or(0.0, |&index| {
+                1.0 / (self.emitters.len() as f64 * self.shapes[index].1.area())
+            })
+    }
+
+    // Of all shapes that intersect with this ray, select the closest one within
+    // the ray's bounds.
+    pub fn intersect(&self, r: &Ray) -> Option<Intersect> {
+        self.shapes
+            .iter()
+            .filter_map(|&(id, ref shape)| {
+                shape.intersect(r).map(|intersect| Intersect {
+                    shape: Some(id),
+                    ..intersect
+                })
+            })
+            .min_by(|first, second| {
+                first.distance.partial_cmp(&second.distance).unwrap()
+            })
+    }
+
+    // Checks whether any shape blocks the ray within its bounds. Stops at the
+    // first shape found, so it's cheaper than intersect when the closest hit
+    // doesn't matter.
+    pub fn occluded(&self, r: &Ray) -> bool {
+        self.shapes.iter().any(|&(_, ref shape)| shape.occluded(r))
+    }
+
+    // Finds which shape is visible along the ray, if any
+    pub fn pick(&self, r: &Ray) -> Option<ShapeId> {
+        self.intersect(r).and_then(|intersect| intersect.shape)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::f64::consts::PI;
+    use cgmath::{vec3, InnerSpace};
+    use scene::Scene;
+    use tracer::Background;
+    use sphere::Sphere;
+    use floor::Floor;
+    use ray::Ray;
+    use light::{Material, Rgb};
+
+    fn empty() -> Scene {
+        Scene::new(Background {
+            color: Rgb::new([0, 0, 0]),
+        })
+    }
+
+    fn floor(z: f64, color: Rgb) -> Box<Floor> {
+        Box::new(Floor::new(
+            vec3(-1.0, -1.0, z),
+            vec3(-1.0, 1.0, z),
+            vec3(1.0, -1.0, z),
+            vec3(1.0, 1.0, z),
+            Material::new(color.clone(), (1.0, 1.0, 1.0), 0.0, 0.0, 0.0),
+            Material::new(color, (1.0, 1.0, 1.0), 0.0, 0.0, 0.0),
+        ))
+    }
+
+    // Tests that the closest shape is selected
+    #[test]
+    fn intersect_ordering() {
+        let mut scene = empty();
+        scene.add(floor(1.0, Rgb::new([255, 0, 0])));
+        scene.add(floor(2.0, Rgb::new([0, 255, 0])));
+
+        let r = Ray::new(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0));
+
+        let intersect = scene
+            .intersect(&r)
+            .expect("Both of these objects should intersect");
+
+        assert_ulps_eq!(1.0, intersect.distance);
+    }
+
+    // Tests that shapes outside of the ray's bounds are skipped
+    #[test]
+    fn intersect_bounds() {
+        let mut scene = empty();
+        scene.add(floor(1.0, Rgb::new([255, 0, 0])));
+        scene.add(floor(2.0, Rgb::new([0, 255, 0])));
+
+        // Stop before the farther shape
+        let r = Ray::bounded(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0), 0.0, 1.5);
+        let intersect = scene.intersect(&r).expect("f1 should intersect");
+        assert_ulps_eq!(1.0, intersect.distance);
+
+        // Start past the closest shape
+        let r = Ray::bounded(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0), 1.5, 10.0);
+        let intersect = scene.intersect(&r).expect("f2 should intersect");
+        assert_ulps_eq!(2.0, intersect.distance);
+
+        // Stop before either shape
+        let r = Ray::bounded(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0), 0.0, 0.5);
+        assert!(scene.intersect(&r).is_none());
+    }
+
+    // Tests that only shapes within the ray's bounds block it
+    #[test]
+    fn occluded_bounds() {
+        let mut scene = empty();
+        scene.add(floor(1.0, Rgb::new([255, 0, 0])));
+
+        let r = Ray::bounded(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0), 0.0, 2.0);
+        assert!(scene.occluded(&r));
+
+        let r = Ray::bounded(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0), 0.0, 0.5);
+        assert!(!scene.occluded(&r));
+    }
+
+    // Tests that identical shapes with different materials are told apart
+    #[test]
+    fn pick() {
+        let geometry = (vec3(0.0, 0.0, 2.0), 0.5);
+        let red = Material::new(Rgb::new([255, 0, 0]), (1.0, 1.0, 1.0), 0.0, 0.0, 0.0);
+        let green = Material::new(Rgb::new([0, 255, 0]), (1.0, 1.0, 1.0), 0.0, 0.0, 0.0);
+
+        let mut scene = empty();
+        let first = scene.add(Box::new(Sphere::new(geometry.0, geometry.1, red)));
+        let second = scene.add(Box::new(Sphere::new(geometry.0, geometry.1, green)));
+        assert!(first != second);
+
+        // The first shape wins ties
+        let r = Ray::new(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0));
+        assert_eq!(Some(first), scene.pick(&r));
+
+        let r = Ray::new(vec3(0.0, 2.0, 0.0), vec3(0.0, 0.0, 1.0));
+        assert_eq!(None, scene.pick(&r));
+    }
+
+    // Tests that only glowing shapes are sampled as lights, each picked as
+    // often as the others
+    #[test]
+    fn sample_emitter() {
+        let mut scene = empty();
+        scene.add(floor(1.0, Rgb::new([255, 0, 0])));
+        assert!(scene.sample_emitter(0.5, 0.5, 0.5).is_none());
+
+        let black = Rgb::new([0, 0, 0]);
+        let white = Rgb::new([255, 255, 255]);
+        let lamp = Material::new(black, (0.0, 0.0, 0.0), 0.0, 0.0, 0.0).emissive(white, 2.0);
+        let small = scene.add(Box::new(Sphere::new(vec3(0.0, 0.0, 2.0), 0.5, lamp.clone())));
+        let large = scene.add(Box::new(Sphere::new(vec3(0.0, 0.0, 4.0), 1.0, lamp)));
+
+        let (light, pdf) = scene
+            .sample_emitter(0.5, 0.5, 0.25)
+            .expect("The small sphere should be sampled");
+        assert_eq!(Some(small), light.shape);
+        assert_relative_eq!(0.5, (light.point - vec3(0.0, 0.0, 2.0)).magnitude(), epsilon = 1e-9);
+        assert_relative_eq!(1.0 / (2.0 * PI), pdf, epsilon = 1e-9);
+        assert_relative_eq!(pdf, scene.emitter_pdf(small), epsilon = 1e-9);
+
+        let (light, _) = scene
+            .sample_emitter(0.5, 0.5, 0.75)
+            .expect("The large sphere should be sampled");
+        assert_eq!(Some(large), light.shape);
+        assert_relative_eq!(1.0 / (8.0 * PI), scene.emitter_pdf(large), epsilon = 1e-9);
+    }
+
+    // Tests that a checkerboard is used as a light when either of its colors
+    // glows, even if the middle of it doesn't
+    #[test]
+    fn checkerboard_emitter() {
+        let plain = Material::new(Rgb::new([0, 0, 0]), (1.0, 1.0, 1.0), 0.0, 0.0, 0.0);
+        let lamp = plain.clone().emissive(Rgb::new([255, 255, 255]), 2.0);
+
+        let material = |glows: bool| if glows { lamp.clone() } else { plain.clone() };
+
+        for &first in &[false, true] {
+            let mut scene = empty();
+            let checkerboard = Floor::new(
+                vec3(-1.0, -1.0, 1.0),
+                vec3(-1.0, 1.0, 1.0),
+                vec3(1.0, -1.0, 1.0),
+                vec3(1.0, 1.0, 1.0),
+                material(first),
+                material(!first),
+            );
+            scene.add(Box::new(checkerboard));
+            assert!(scene.sample_emitter(0.5, 0.5, 0.5).is_some());
+        }
+    }
+}
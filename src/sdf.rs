@@ -0,0 +1,473 @@
+extern crate std;
+
+use cgmath::{dot, vec2, vec3, InnerSpace, Vector3};
+use tracer::{Intersect, Shape};
+use ray::Ray;
+use light::Material;
+
+const MAX_STEPS: u32 = 256;
+const MAX_DISTANCE: f64 = 100.0;
+const EPSILON: f64 = 1e-6;
+
+// Signed distance function describing a shape implicitly. Returns the distance
+// from the point to the closest surface, which is negative inside the shape.
+// Implementations can underestimate the distance, but never overestimate it,
+// or the ray marcher will step through the surface.
+pub trait Sdf: Send + Sync {
+    fn distance(&self, point: Vector3<f64>) -> f64;
+}
+
+// Shape rendered by sphere tracing a distance function. Each step along the
+// ray moves forward by the distance to the closest surface, which can't skip
+// over anything, until the ray is close enough to count as a hit.
+pub struct DistanceField {
+    pub sdf: Box<Sdf>,
+    pub color: Material,
+
+    // Limits on how long to march before deciding the ray missed
+    pub max_steps: u32,
+    pub max_distance: f64,
+
+    // How close the ray needs to get to the surface to count as a hit
+    pub epsilon: f64,
+
+    // Fraction of each step to actually take. Operations like twisting stretch
+    // space and make the distance an overestimate, which a smaller step
+    // compensates for.
+    pub step_scale: f64,
+}
+
+impl DistanceField {
+    pub fn new(sdf: Box<Sdf>, color: Material) -> DistanceField {
+        DistanceField {
+            sdf,
+            color,
+            max_steps: MAX_STEPS,
+            max_distance: MAX_DISTANCE,
+            epsilon: EPSILON,
+            step_scale: 1.0,
+        }
+    }
+
+    // Estimates the surface normal from the gradient of the distance function,
+    // using central differences along each axis
+    fn normal_at(&self, p: Vector3<f64>) -> Vector3<f64> {
+        let h = self.epsilon;
+        let dx = vec3(h, 0.0, 0.0);
+        let dy = vec3(0.0, h, 0.0);
+        let dz = vec3(0.0, 0.0, h);
+
+        vec3(
+            self.sdf.distance(p + dx) - self.sdf.distance(p - dx),
+            self.sdf.distance(p + dy) - self.sdf.distance(p - dy),
+            self.sdf.distance(p + dz) - self.sdf.distance(p - dz),
+        ).normalize()
+    }
+}
+
+impl Shape for DistanceField {
+    fn intersect(&self, ray: &Ray) -> Option<Intersect> {
+        let mut distance = ray.t_min;
+        let max_distance = f64::min(self.max_distance, ray.t_max);
+
+        // Rays leaving the surface start within epsilon of it, so they have to
+        // march clear of the surface before a hit counts
+        let mut escaped = false;
+
+        for _ in 0..self.max_steps {
+            let point = ray.extend(distance);
+
+            // Rays starting inside the shape march out by the same rule
+            let step = self.sdf.distance(point).abs();
+
+            if step >= self.epsilon {
+                escaped = true;
+            } else if escaped && distance > ray.t_min {
+                return Some(Intersect {
+                    distance,
+                    point,
+                    normal: self.normal_at(point),
+
+                    // Distance fields have no natural surface parameterization
+                    uv: vec2(0.0, 0.0),
+                    color: &self.color,
+                    shape: None,
+                });
+            }
+
+            distance += f64::max(step, self.epsilon) * self.step_scale;
+            if distance > max_distance {
+                break;
+            }
+        }
+
+        None
+    }
+
+}
+
+
+// Box centered on the origin with rounded edges. The half extents measure from
+// the center to each face before rounding, so the radius grows the box.
+pub struct RoundBox {
+    pub half_extents: Vector3<f64>,
+    pub radius: f64,
+}
+
+impl RoundBox {
+    pub fn new(half_extents: Vector3<f64>, radius: f64) -> RoundBox {
+        RoundBox {
+            half_extents,
+            radius,
+        }
+    }
+}
+
+impl Sdf for RoundBox {
+    fn distance(&self, p: Vector3<f64>) -> f64 {
+        let q = vec3(
+            p.x.abs() - self.half_extents.x,
+            p.y.abs() - self.half_extents.y,
+            p.z.abs() - self.half_extents.z,
+        );
+
+        let outside = vec3(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0)).magnitude();
+        let inside = q.x.max(q.y.max(q.z)).min(0.0);
+        outside + inside - self.radius
+    }
+}
+
+// Cylinder with hemispheres on both ends, running between two points
+pub struct Capsule {
+    pub a: Vector3<f64>,
+    pub b: Vector3<f64>,
+    pub radius: f64,
+}
+
+impl Capsule {
+    pub fn new(a: Vector3<f64>, b: Vector3<f64>, radius: f64) -> Capsule {
+        Capsule { a, b, radius }
+    }
+}
+
+impl Sdf for Capsule {
+    fn distance(&self, p: Vector3<f64>) -> f64 {
+        // Closest point on the segment between the end points
+        let pa = p - self.a;
+        let ba = self.b - self.a;
+        let h = (dot(pa, ba) / dot(ba, ba)).clamp(0.0, 1.0);
+        (pa - ba * h).magnitude() - self.radius
+    }
+}
+
+// Torus centered on the origin, lying flat around the Y axis
+pub struct Torus {
+    pub major_r: f64,
+    pub minor_r: f64,
+}
+
+impl Torus {
+    pub fn new(major_r: f64, minor_r: f64) -> Torus {
+        Torus { major_r, minor_r }
+    }
+}
+
+impl Sdf for Torus {
+    fn distance(&self, p: Vector3<f64>) -> f64 {
+        let ring = vec2(p.x, p.z).magnitude() - self.major_r;
+        vec2(ring, p.y).magnitude() - self.minor_r
+    }
+}
+
+// Mandelbulb fractal centered on the origin. The classic shape uses a power of
+// 8 and roughly fills the unit sphere. More iterations bring out finer detail.
+pub struct Mandelbulb {
+    pub power: f64,
+    pub iterations: u32,
+}
+
+impl Mandelbulb {
+    pub fn new(power: f64, iterations: u32) -> Mandelbulb {
+        Mandelbulb { power, iterations }
+    }
+}
+
+impl Sdf for Mandelbulb {
+    /// Distance estimate from the escape-time iteration, tracking the running
+    /// derivative: 0.5 * ln(r) * r / dr
+    fn distance(&self, p: Vector3<f64>) -> f64 {
+        let mut z = p;
+        let mut dr = 1.0;
+        let mut r = z.magnitude();
+
+        for _ in 0..self.iterations {
+            // The iteration is undefined at the origin, which is solidly inside
+            if r < 1e-12 || r > 2.0 {
+                break;
+            }
+
+            // Raise to the power in spherical coordinates
+            let theta = (z.z / r).acos() * self.power;
+            let phi = z.y.atan2(z.x) * self.power;
+            dr = r.powf(self.power - 1.0) * self.power * dr + 1.0;
+
+            let zr = r.powf(self.power);
+            z = vec3(
+                theta.sin() * phi.cos(),
+                phi.sin() * theta.sin(),
+                theta.cos(),
+            ) * zr + p;
+            r = z.magnitude();
+        }
+
+        if r < 1e-12 {
+            0.0
+        } else {
+            0.5 * r.ln() * r / dr
+        }
+    }
+}
+
+
+// Moves a distance function away from the origin
+pub struct Translate {
+    pub inner: Box<Sdf>,
+    pub offset: Vector3<f64>,
+}
+
+impl Translate {
+    pub fn new(inner: Box<Sdf>, offset: Vector3<f64>) -> Translate {
+        Translate { inner, offset }
+    }
+}
+
+impl Sdf for Translate {
+    fn distance(&self, p: Vector3<f64>) -> f64 {
+        self.inner.distance(p - self.offset)
+    }
+}
+
+// Blends two shapes together, filling in the crease where they meet. The
+// smoothing distance k controls how far the blend reaches.
+pub struct SmoothUnion {
+    pub a: Box<Sdf>,
+    pub b: Box<Sdf>,
+    pub k: f64,
+}
+
+impl SmoothUnion {
+    pub fn new(a: Box<Sdf>, b: Box<Sdf>, k: f64) -> SmoothUnion {
+        SmoothUnion { a, b, k }
+    }
+}
+
+impl Sdf for SmoothUnion {
+    fn distance(&self, p: Vector3<f64>) -> f64 {
+        let (d1, d2) = (self.a.distance(p), self.b.distance(p));
+        let h = (0.5 + 0.5 * (d2 - d1) / self.k).clamp(0.0, 1.0);
+        mix(d2, d1, h) - self.k * h * (1.0 - h)
+    }
+}
+
+// Carves the cut shape out of the base shape, rounding off the edges of the
+// cut. The smoothing distance k controls how far the rounding reaches.
+pub struct SmoothSubtraction {
+    pub base: Box<Sdf>,
+    pub cut: Box<Sdf>,
+    pub k: f64,
+}
+
+impl SmoothSubtraction {
+    pub fn new(base: Box<Sdf>, cut: Box<Sdf>, k: f64) -> SmoothSubtraction {
+        SmoothSubtraction { base, cut, k }
+    }
+}
+
+impl Sdf for SmoothSubtraction {
+    fn distance(&self, p: Vector3<f64>) -> f64 {
+        let (base, cut) = (self.base.distance(p), self.cut.distance(p));
+        let h = (0.5 - 0.5 * (base + cut) / self.k).clamp(0.0, 1.0);
+        mix(base, -cut, h) + self.k * h * (1.0 - h)
+    }
+}
+
+// Repeats a shape forever in a grid. The inner shape should fit within a
+// single cell centered on the origin. A period of zero on an axis leaves that
+// axis unrepeated.
+pub struct Repeat {
+    pub inner: Box<Sdf>,
+    pub period: Vector3<f64>,
+}
+
+impl Repeat {
+    pub fn new(inner: Box<Sdf>, period: Vector3<f64>) -> Repeat {
+        Repeat { inner, period }
+    }
+}
+
+impl Sdf for Repeat {
+    fn distance(&self, p: Vector3<f64>) -> f64 {
+        /// Wraps a single coordinate into the cell around the origin
+        fn wrap(x: f64, period: f64) -> f64 {
+            if period > 0.0 {
+                x - period * (x / period).round()
+            } else {
+                x
+            }
+        }
+
+        self.inner.distance(vec3(
+            wrap(p.x, self.period.x),
+            wrap(p.y, self.period.y),
+            wrap(p.z, self.period.z),
+        ))
+    }
+}
+
+// Twists a shape around the Y axis by an angle in radians per unit of height.
+// Twisting overestimates distances, so the shape usually needs a smaller step
+// scale.
+pub struct Twist {
+    pub inner: Box<Sdf>,
+    pub rate: f64,
+}
+
+impl Twist {
+    pub fn new(inner: Box<Sdf>, rate: f64) -> Twist {
+        Twist { inner, rate }
+    }
+}
+
+impl Sdf for Twist {
+    fn distance(&self, p: Vector3<f64>) -> f64 {
+        let angle = self.rate * p.y;
+        let (sin, cos) = angle.sin_cos();
+        self.inner
+            .distance(vec3(cos * p.x - sin * p.z, p.y, sin * p.x + cos * p.z))
+    }
+}
+
+// Linear interpolation from a to b
+fn mix(a: f64, b: f64, t: f64) -> f64 {
+    a * (1.0 - t) + b * t
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use cgmath::vec3;
+    use tracer::Shape;
+    use ray::Ray;
+    use light::{Material, Rgb};
+    use super::*;
+
+    fn material() -> Material {
+        Material::new(Rgb::new([255, 255, 0]), (1.0, 1.0, 1.0), 0.0, 0.0, 0.0)
+    }
+
+    // Tests marching to the face of a box, and out of it from the inside
+    #[test]
+    fn intersect_box() {
+        let field = DistanceField::new(
+            Box::new(Translate::new(
+                Box::new(RoundBox::new(vec3(0.5, 0.5, 0.5), 0.0)),
+                vec3(0.0, 0.0, 2.0),
+            )),
+            material(),
+        );
+
+        let r = Ray::new(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0));
+        let intersect = field.intersect(&r).expect("Ray should hit the box");
+        assert_relative_eq!(1.5, intersect.distance, epsilon = 1e-5);
+        assert_relative_eq!(vec3(0.0, 0.0, -1.0), intersect.normal, epsilon = 1e-5);
+
+        let r = Ray::new(vec3(0.0, 0.0, 2.0), vec3(0.0, 1.0, 0.0));
+        let intersect = field
+            .intersect(&r)
+            .expect("Ray should leave through the top of the box");
+        assert_relative_eq!(0.5, intersect.distance, epsilon = 1e-5);
+        assert_relative_eq!(vec3(0.0, 1.0, 0.0), intersect.normal, epsilon = 1e-5);
+
+        let r = Ray::new(vec3(0.0, 1.0, 0.0), vec3(0.0, 0.0, 1.0));
+        assert!(field.intersect(&r).is_none());
+    }
+
+    // Tests distances to the built in primitives
+    #[test]
+    fn primitives() {
+        let rounded = RoundBox::new(vec3(0.5, 0.5, 0.5), 0.1);
+        assert_relative_eq!(0.4, rounded.distance(vec3(0.0, 1.0, 0.0)), epsilon = 1e-9);
+
+        let capsule = Capsule::new(vec3(0.0, -1.0, 0.0), vec3(0.0, 1.0, 0.0), 0.25);
+        assert_relative_eq!(0.75, capsule.distance(vec3(1.0, 0.0, 0.0)), epsilon = 1e-9);
+        assert_relative_eq!(0.75, capsule.distance(vec3(0.0, 2.0, 0.0)), epsilon = 1e-9);
+
+        let torus = Torus::new(1.0, 0.25);
+        assert_relative_eq!(-0.25, torus.distance(vec3(1.0, 0.0, 0.0)), epsilon = 1e-9);
+        assert_relative_eq!(0.75, torus.distance(vec3(0.0, 0.0, 0.0)), epsilon = 1e-9);
+    }
+
+    // Tests that marching finds the outside of the Mandelbulb
+    #[test]
+    fn intersect_mandelbulb() {
+        let field = DistanceField::new(Box::new(Mandelbulb::new(8.0, 12)), material());
+        assert!(field.sdf.distance(vec3(0.0, 0.0, -3.0)) > 0.0);
+
+        let r = Ray::new(vec3(0.0, 0.0, -3.0), vec3(0.0, 0.0, 1.0));
+        let intersect = field.intersect(&r).expect("Ray should hit the fractal");
+        assert!(intersect.distance > 1.5 && intersect.distance < 3.0);
+    }
+
+    // Tests that smooth operations match the sharp versions away from the seam
+    #[test]
+    fn smooth_operations() {
+        let union = SmoothUnion::new(
+            Box::new(Translate::new(Box::new(Torus::new(1.0, 0.25)), vec3(-2.0, 0.0, 0.0))),
+            Box::new(Translate::new(Box::new(Torus::new(1.0, 0.25)), vec3(2.0, 0.0, 0.0))),
+            0.1,
+        );
+        assert_relative_eq!(-0.25, union.distance(vec3(-3.0, 0.0, 0.0)), epsilon = 1e-9);
+
+        // Where the shapes are equally close, the blend bulges out
+        assert!(union.distance(vec3(0.0, 0.0, 0.0)) < 0.75);
+
+        let carved = SmoothSubtraction::new(
+            Box::new(RoundBox::new(vec3(1.0, 1.0, 1.0), 0.0)),
+            Box::new(Capsule::new(vec3(0.0, -2.0, 0.0), vec3(0.0, 2.0, 0.0), 0.5)),
+            0.1,
+        );
+
+        // The center is hollowed out, but the corners are still solid
+        assert!(carved.distance(vec3(0.0, 0.0, 0.0)) > 0.0);
+        assert!(carved.distance(vec3(0.9, 0.0, 0.9)) < 0.0);
+    }
+
+    // Tests that repeated and twisted domains still march correctly
+    #[test]
+    fn domain_operations() {
+        let field = DistanceField::new(
+            Box::new(Repeat::new(
+                Box::new(RoundBox::new(vec3(0.25, 0.25, 0.25), 0.0)),
+                vec3(2.0, 0.0, 2.0),
+            )),
+            material(),
+        );
+
+        // A copy of the box sits every two units along X
+        let r = Ray::new(vec3(4.0, 0.0, -1.0), vec3(0.0, 0.0, 1.0));
+        let intersect = field.intersect(&r).expect("Ray should hit a copy");
+        assert_relative_eq!(0.75, intersect.distance, epsilon = 1e-5);
+
+        let twisted = Twist::new(Box::new(RoundBox::new(vec3(1.0, 1.0, 0.1), 0.0)), 0.0);
+        assert_relative_eq!(0.9, twisted.distance(vec3(0.0, 0.0, 1.0)), epsilon = 1e-9);
+
+        // A quarter turn per unit swaps the thin axis at y = 1
+        let twisted = Twist::new(
+            Box::new(RoundBox::new(vec3(1.0, 2.0, 0.1), 0.0)),
+            std::f64::consts::PI / 2.0,
+        );
+        assert!(twisted.distance(vec3(0.0, 1.0, 0.5)) < 0.0);
+        assert!(twisted.distance(vec3(0.5, 1.0, 0.0)) > 0.0);
+    }
+}
@@ -0,0 +1,272 @@
+extern crate std;
+
+use cgmath::{dot, vec3, InnerSpace, Vector3};
+use environment::Environment;
+use color::Color;
+use light::Light;
+
+const PI: f64 = std::f64::consts::PI;
+
+// The model is fit to clear skies between these turbidities
+const MIN_TURBIDITY: f64 = 2.0;
+const MAX_TURBIDITY: f64 = 10.0;
+
+// Converts the model's luminance, in kcd/m^2, so that a midday sky lights a
+// white surface to about a third of the brightness of the sun
+const SKY_SCALE: f64 = 0.03;
+
+// Wavelengths of the red, green and blue channels in micrometers, used to
+// work out how much sunlight the atmosphere lets through
+const WAVELENGTHS: [f64; 3] = [0.65, 0.57, 0.475];
+
+// Analytic daylight sky (Preetham, Shirley & Smits, "A Practical Analytic Model
+// for Daylight"). The brightness and color of the sky are fit to the angle from
+// the zenith and the angle from the sun with Perez's formula, for a sun
+// anywhere from the horizon to straight overhead. Turbidity is how hazy the air
+// is: 2 is a very clear day, and higher values wash the sky out toward white.
+//
+// The sun itself isn't part of the sky. It's lit separately by the directional
+// light from sun(), which has the color of sunlight after passing through the
+// same atmosphere.
+pub struct Sky {
+    // Direction toward the sun
+    pub sun_direction: Vector3<f64>,
+    pub turbidity: f64,
+
+    // Light coming from below the horizon, where the model doesn't apply
+    pub ground: Color,
+
+    // Perez coefficients A to E for luminance and the x and y chromaticities
+    perez: [[f64; 5]; 3],
+
+    // Luminance and chromaticity at the zenith, divided by what Perez's
+    // formula gives there so the rest of the sky can be scaled to match
+    zenith: [f64; 3],
+}
+
+impl Sky {
+    /// Creates a sky with the sun at an elevation above the horizon and an
+    /// azimuth clockwise from +Z when looking down, both in degrees
+    pub fn new(elevation: f64, azimuth: f64, turbidity: f64) -> Sky {
+        let elevation = elevation.clamp(0.0, 90.0).to_radians();
+        let azimuth = azimuth.to_radians();
+        let turbidity = turbidity.clamp(MIN_TURBIDITY, MAX_TURBIDITY);
+
+        let sun_direction = vec3(
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+            elevation.cos() * azimuth.cos(),
+        );
+
+        let t = turbidity;
+        let perez = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+
+        let theta_s = PI / 2.0 - elevation;
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+
+        // Chromaticity is a polynomial in the turbidity and the sun's angle
+        let chromaticity = |matrix: [[f64; 4]; 3]| {
+            let angles = [theta_s.powi(3), theta_s.powi(2), theta_s, 1.0];
+            let row = |r: [f64; 4]| r.iter().zip(angles.iter()).map(|(a, b)| a * b).sum::<f64>();
+            t * t * row(matrix[0]) + t * row(matrix[1]) + row(matrix[2])
+        };
+        let x = chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let y = chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+
+        let zenith = [luminance, x, y];
+        let mut normalized = [0.0; 3];
+        for ((value, &zenith), &perez) in normalized.iter_mut().zip(&zenith).zip(&perez) {
+            *value = zenith / perez_formula(perez, 1.0, theta_s.cos());
+        }
+
+        Sky {
+            sun_direction,
+            turbidity,
+            ground: Color::black(),
+            perez,
+            zenith: normalized,
+        }
+    }
+
+    // Directional light for the sun, dimmed and reddened by the air it
+    // passes through on the way down. Low suns pass through much more air.
+    pub fn sun(&self) -> Light {
+        let zenith_angle = self.sun_direction.y.clamp(0.0, 1.0).acos();
+
+        // Relative optical mass (Kasten), which accounts for the curve of
+        // the atmosphere near the horizon
+        let mass = 1.0
+            / (zenith_angle.cos()
+                + 0.15 * (93.885 - zenith_angle.to_degrees()).powf(-1.253));
+
+        // Rayleigh scattering off of air molecules and Angstrom's formula for
+        // scattering off of haze
+        let beta = 0.04608 * self.turbidity - 0.04586;
+        let transmittance = |lambda: f64| {
+            let rayleigh = (-0.008735 * lambda.powf(-4.08) * mass).exp();
+            let aerosol = (-beta * lambda.powf(-1.3) * mass).exp();
+            rayleigh * aerosol
+        };
+
+        let color = Color::new(
+            transmittance(WAVELENGTHS[0]),
+            transmittance(WAVELENGTHS[1]),
+            transmittance(WAVELENGTHS[2]),
+        );
+        Light::directional(self.sun_direction, color.to_rgb())
+    }
+}
+
+impl Environment for Sky {
+    fn radiance(&self, direction: Vector3<f64>) -> Color {
+        let d = direction.normalize();
+        if d.y < 0.0 {
+            return self.ground;
+        }
+
+        // The formula blows up right at the horizon
+        let cos_theta = d.y.max(0.01);
+        let cos_gamma = dot(d, self.sun_direction);
+
+        let mut values = [0.0; 3];
+        for ((value, &zenith), &perez) in values.iter_mut().zip(&self.zenith).zip(&self.perez) {
+            *value = zenith * perez_formula(perez, cos_theta, cos_gamma);
+        }
+        let [luminance, x, y] = values;
+
+        // xyY to XYZ to linear sRGB
+        let big_x = x / y * luminance;
+        let big_z = (1.0 - x - y) / y * luminance;
+        let channel = |r: f64, g: f64, b: f64| (r * big_x + g * luminance + b * big_z).max(0.0);
+
+        Color::new(
+            channel(3.2406, -1.5372, -0.4986),
+            channel(-0.9689, 1.8758, 0.0415),
+            channel(0.0557, -0.2040, 1.0570),
+        ) * SKY_SCALE
+    }
+
+    // Spreads samples evenly over the sky, leaving out the ground
+    fn sample(&self, u: f64, v: f64) -> Option<(Vector3<f64>, f64)> {
+        let y = 1.0 - u;
+        let r = (1.0 - y * y).max(0.0).sqrt();
+        let phi = 2.0 * PI * v;
+
+        Some((vec3(r * phi.cos(), y, r * phi.sin()), 1.0 / (2.0 * PI)))
+    }
+
+    fn pdf(&self, direction: Vector3<f64>) -> f64 {
+        if direction.y > 0.0 {
+            1.0 / (2.0 * PI)
+        } else {
+            0.0
+        }
+    }
+}
+
+// Perez's sky distribution, relative to the zenith, from the cosines of the
+// angles to the zenith and to the sun
+fn perez_formula(coefficients: [f64; 5], cos_theta: f64, cos_gamma: f64) -> f64 {
+    let [a, b, c, d, e] = coefficients;
+    let gamma = cos_gamma.clamp(-1.0, 1.0).acos();
+
+    (1.0 + a * (b / cos_theta).exp()) * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use cgmath::{vec3, InnerSpace};
+    use environment::Environment;
+    use sky::{Sky, SKY_SCALE};
+    use color::Color;
+
+    // Tests that elevation and azimuth point the sun the right way
+    #[test]
+    fn sun_direction() {
+        assert_relative_eq!(vec3(0.0, 1.0, 0.0), Sky::new(90.0, 30.0, 3.0).sun_direction, epsilon = 1e-9);
+        assert_relative_eq!(vec3(0.0, 0.0, 1.0), Sky::new(0.0, 0.0, 3.0).sun_direction, epsilon = 1e-9);
+        assert_relative_eq!(vec3(1.0, 0.0, 0.0), Sky::new(0.0, 90.0, 3.0).sun_direction, epsilon = 1e-9);
+
+        let sun = Sky::new(45.0, 0.0, 3.0).sun();
+        assert!(sun.directional);
+        assert_relative_eq!(vec3(0.0, 1.0, 1.0).normalize(), sun.position, epsilon = 1e-9);
+    }
+
+    // Tests that the zenith matches the model's luminance there, that the sky
+    // is brighter around the sun and that the ground is left alone
+    #[test]
+    fn radiance() {
+        let sky = Sky::new(60.0, 0.0, 3.0);
+
+        // Zenith luminance for a turbidity of 3 with the sun 30 degrees away
+        let zenith = sky.radiance(vec3(0.0, 1.0, 0.0));
+        assert_relative_eq!(10.41 * SKY_SCALE, zenith.luminance(), epsilon = 0.01 * SKY_SCALE);
+
+        // A clear sky is blue overhead
+        assert!(zenith.b > zenith.r);
+
+        let near_sun = sky.radiance(vec3(0.0, 0.8, 0.6));
+        let away = sky.radiance(vec3(0.0, 0.8, -0.6));
+        assert!(near_sun.luminance() > 2.0 * away.luminance());
+
+        assert_eq!(Color::black(), sky.radiance(vec3(0.3, -0.5, 0.1)));
+    }
+
+    // Tests that sunlight dims and reddens as the sun sets
+    #[test]
+    fn sun_color() {
+        let noon = Sky::new(80.0, 0.0, 3.0).sun().color.color.data;
+        let sunset = Sky::new(3.0, 0.0, 3.0).sun().color.color.data;
+
+        assert!(sunset[1] < noon[1]);
+        let ratio = |c: [u8; 3]| c[0] as f64 / c[2].max(1) as f64;
+        assert!(ratio(sunset) > 2.0 * ratio(noon));
+    }
+
+    // Tests that only the sky above the horizon is sampled
+    #[test]
+    fn sample() {
+        let sky = Sky::new(30.0, 45.0, 4.0);
+
+        for i in 0..10 {
+            let (direction, pdf) = sky.sample(i as f64 / 10.0, 0.37).unwrap();
+            assert!(direction.y > 0.0);
+            assert_relative_eq!(1.0, direction.magnitude(), epsilon = 1e-9);
+            assert_ulps_eq!(pdf, sky.pdf(direction));
+        }
+        assert_ulps_eq!(0.0, sky.pdf(vec3(0.0, -1.0, 0.0)));
+    }
+}
@@ -1,7 +1,9 @@
-use cgmath::{dot, InnerSpace, Vector3};
-use tracer::{Intersect, Shape};
+extern crate std;
+
+use cgmath::{dot, vec2, InnerSpace, Vector3};
+use tracer::{Intersect, Interval, Shape};
+use sampling::uniform_sphere;
 use ray::Ray;
-use std::any::Any;
 use light::Material;
 
 pub struct Sphere {
@@ -14,25 +16,15 @@ impl Sphere {
     pub fn new(center: Vector3<f64>, r: f64, color: Material) -> Sphere {
         Sphere { center, r, color }
     }
-}
-
-impl PartialEq for Sphere {
-    // Shapes really shouldn't be overlapping. If two different objects have the
-    // same coordinates and radius but different materials, we have a bigger
-    // problem.
-    fn eq(&self, other: &Sphere) -> bool {
-        ulps_eq!(self.center, other.center) && ulps_eq!(self.r, other.r)
-    }
-}
 
-impl Shape for Sphere {
     /// Sphere intersection formula comes from CG II slides
     /// (2-2b-rt-basics-4.pdf). \omega is the distance from the origin of the ray
     /// to the intersect point.
     ///
     /// \omega = (-B \pm \sqrt{B^2 - 4 * C}) / 2
     ///
-    fn intersect(&self, ray: &Ray) -> Option<Intersect> {
+    /// Returns both solutions, nearest first, even if they're behind the ray.
+    fn solve(&self, ray: &Ray) -> Option<(f64, f64)> {
         // B=2 * (dx(x_o −x_c)+dy(y_o −y_c)+dz(z_o −z_c))
         // which is just the dot product
         // B = 2 * (d . (origin - center))
@@ -51,41 +43,78 @@ impl Shape for Sphere {
         if partial < 0.0 {
             None
         } else {
-            let d1 = (-b + partial) / 2.0;
-            let d2 = (-b - partial) / 2.0;
-
-            // There are two solutions, so return the smallest positive result.
-            // The larger value would be the far side of the sphere.
-            let distance = if d1 < 0.0 {
-                d2
-            } else if d2 < 0.0 {
-                d1
-            } else {
-                f64::min(d1, d2)
-            };
-
-            let point = ray.extend(distance);
-            let normal = (point - self.center).normalize();
-
-            Some(Intersect {
-                distance,
-                point,
-                normal,
-                color: &self.color,
-                shape: self,
-            })
+            let root = partial.sqrt();
+            Some(((-b - root) / 2.0, (-b + root) / 2.0))
+        }
+    }
+
+    // Builds the intersect at a distance along the ray
+    fn intersect_at(&self, ray: &Ray, distance: f64) -> Intersect {
+        let point = ray.extend(distance);
+        let normal = (point - self.center).normalize();
+
+        // Longitude around the Y axis and latitude from the south pole
+        let uv = vec2(
+            0.5 + normal.z.atan2(normal.x) / (2.0 * std::f64::consts::PI),
+            0.5 + normal.y.clamp(-1.0, 1.0).asin() / std::f64::consts::PI,
+        );
+
+        Intersect {
+            distance,
+            point,
+            normal,
+            uv,
+            color: &self.color,
+            shape: None,
         }
     }
+}
 
-    fn eq(&self, other: &Shape) -> bool {
-        other
-            .as_any()
-            .downcast_ref::<Self>()
-            .map_or(false, |x| x == self)
+impl Shape for Sphere {
+    fn intersect(&self, ray: &Ray) -> Option<Intersect> {
+        self.solve(ray).and_then(|(d1, d2)| {
+            // There are two solutions, so return the nearest one within the
+            // ray's bounds. The larger value would be the far side of the
+            // sphere.
+            if ray.contains(d1) {
+                Some(self.intersect_at(ray, d1))
+            } else if ray.contains(d2) {
+                Some(self.intersect_at(ray, d2))
+            } else {
+                None
+            }
+        })
+    }
+
+    // Either root within the bounds blocks the ray, so there's no need to
+    // build the intersect
+    fn occluded(&self, ray: &Ray) -> bool {
+        self.solve(ray)
+            .map_or(false, |(d1, d2)| ray.contains(d1) || ray.contains(d2))
     }
 
-    fn as_any(&self) -> &Any {
-        self
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        self.solve(ray).map_or(vec![], |(d1, d2)| {
+            vec![
+                Interval {
+                    enter: self.intersect_at(ray, d1),
+                    exit: self.intersect_at(ray, d2),
+                },
+            ]
+        })
+    }
+
+    fn area(&self) -> f64 {
+        4.0 * std::f64::consts::PI * self.r * self.r
+    }
+
+    fn sample(&self, u: f64, v: f64) -> Option<Intersect> {
+        let direction = uniform_sphere(u, v);
+        Some(self.intersect_at(&Ray::new(self.center, direction), self.r))
+    }
+
+    fn emits(&self) -> bool {
+        !self.color.emission().is_black()
     }
 }
 
@@ -129,4 +158,26 @@ mod tests {
         assert_eq!(&color, intersect.color.diffuse());
         assert_ulps_eq!(1.0, intersect.distance);
     }
+
+    // Tests that the interval covers the whole sphere, even behind the ray
+    #[test]
+    fn intervals() {
+        let color = Rgb::new([255, 255, 0]);
+
+        let sphere = Sphere::new(vec3(0.0, 0.0, 1.0), 0.5, Material::new(color.clone(), (1.0, 1.0, 1.0), 0.0, 0.0, 0.0));
+
+        let r = Ray::new(vec3(0.0, 0.0, 1.0), vec3(0.0, 0.0, 1.0));
+        let intervals = sphere.intervals(&r);
+        assert_eq!(1, intervals.len());
+        assert_ulps_eq!(-0.5, intervals[0].enter.distance);
+        assert_ulps_eq!(0.5, intervals[0].exit.distance);
+
+        let intersect = sphere
+            .intersect(&r)
+            .expect("Ray should intersect with the far side of the sphere");
+        assert_ulps_eq!(0.5, intersect.distance);
+
+        let r = Ray::new(vec3(0.0, 1.0, 0.0), vec3(0.0, 0.0, 1.0));
+        assert!(sphere.intervals(&r).is_empty());
+    }
 }
@@ -0,0 +1,187 @@
+use cgmath::{dot, Vector3};
+use camera::Camera;
+use ray::Ray;
+
+// Distance between the eyes, about the average for adults if the scene is in
+// meters
+pub const EYE_SEPARATION: f64 = 0.064;
+
+// Distance at which the eyes' views line up by default, around the middle of
+// the scene
+pub const CONVERGENCE: f64 = 2.0;
+
+// How the two eyes share one image
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Layout {
+    // Left eye on the left half, right eye on the right half
+    SideBySide,
+
+    // Left eye on the top half, right eye on the bottom half
+    OverUnder,
+}
+
+// Left and right eye views through another camera, for viewing in VR.
+//
+// Each eye is moved half of the separation away from the camera along its
+// baseline. Rather than turning the eyes in, their views are sheared (off-axis
+// projection) so that everything at the convergence distance lines up in both
+// images and appears at the depth of the screen. Nearer things pop out of it
+// and further things sink behind it.
+pub struct Stereo {
+    pub camera: Box<Camera>,
+
+    // Distance between the eyes (interpupillary distance)
+    pub separation: f64,
+
+    // Distance from the camera to where the eyes' views line up
+    pub convergence: f64,
+
+    pub layout: Layout,
+}
+
+impl Stereo {
+    pub fn new(camera: Box<Camera>, layout: Layout) -> Stereo {
+        Stereo {
+            camera,
+            separation: EYE_SEPARATION,
+            convergence: CONVERGENCE,
+            layout,
+        }
+    }
+
+    // Ray from one eye, with offset along the baseline in the same units as
+    // the separation. Negative offsets are the left eye.
+    pub fn eye(&self, offset: f64, x: f64, y: f64, lens: (f64, f64)) -> Option<Ray> {
+        self.camera.ray(x, y, lens).map(|r| {
+            let d = r.direction();
+            let baseline = self.camera.baseline(d);
+
+            // Shearing sideways in proportion to depth leaves the plane at the
+            // convergence distance in place
+            let forward = self.camera.forward(d);
+            let shear = offset * dot(d, forward) / self.convergence;
+
+            let mut eye = Ray::new(r.origin + baseline * offset, d - baseline * shear);
+            eye.screen = r.screen;
+            eye
+        })
+    }
+}
+
+impl Camera for Stereo {
+    // Splits the image between the eyes, and maps the point into the eye's
+    // half of it
+    fn ray(&self, x: f64, y: f64, lens: (f64, f64)) -> Option<Ray> {
+        let half = self.separation / 2.0;
+        match self.layout {
+            Layout::SideBySide if x < 0.0 => self.eye(-half, x * 2.0 + 0.5, y, lens),
+            Layout::SideBySide => self.eye(half, x * 2.0 - 0.5, y, lens),
+            Layout::OverUnder if y >= 0.0 => self.eye(-half, x, y * 2.0 - 0.5, lens),
+            Layout::OverUnder => self.eye(half, x, y * 2.0 + 0.5, lens),
+        }
+    }
+
+    fn sample_lens(&self, u: f64, v: f64) -> (f64, f64) {
+        self.camera.sample_lens(u, v)
+    }
+
+    fn baseline(&self, direction: Vector3<f64>) -> Vector3<f64> {
+        self.camera.baseline(direction)
+    }
+
+    fn forward(&self, direction: Vector3<f64>) -> Vector3<f64> {
+        self.camera.forward(direction)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use cgmath::{vec3, InnerSpace};
+    use environment::Projection;
+    use motion::Transform;
+    use camera::{Camera, Panorama, Perspective, Posed};
+    use stereo::{Layout, Stereo};
+
+    // Tests that the eyes sit either side of the camera and that their views
+    // line up at the convergence distance
+    #[test]
+    fn convergence() {
+        let stereo = Stereo {
+            separation: 0.1,
+            convergence: 3.0,
+            ..Stereo::new(Box::new(Perspective::new()), Layout::SideBySide)
+        };
+
+        let left = stereo.eye(-0.05, 0.1, 0.2, (0.0, 0.0)).unwrap();
+        let right = stereo.eye(0.05, 0.1, 0.2, (0.0, 0.0)).unwrap();
+        assert_relative_eq!(vec3(-0.05, 0.0, 0.0), left.origin);
+        assert_relative_eq!(vec3(0.05, 0.0, 0.0), right.origin);
+
+        let target = vec3(0.6, 1.2, 3.0);
+        for r in &[left, right] {
+            let distance = (target - r.origin).magnitude();
+            assert_relative_eq!(target, r.extend(distance), epsilon = 1e-9);
+        }
+    }
+
+    // Tests that a camera looking down still has its eyes line up at the
+    // convergence distance in front of it, rather than out level with it
+    #[test]
+    fn convergence_pitched() {
+        let transform = Transform::rotation(vec3(1.0, 0.0, 0.0), 60.0);
+        let camera = Posed {
+            camera: Box::new(Perspective::new()),
+            transform,
+        };
+        let stereo = Stereo {
+            separation: 0.1,
+            convergence: 3.0,
+            ..Stereo::new(Box::new(camera), Layout::SideBySide)
+        };
+
+        let left = stereo.eye(-0.05, 0.1, 0.2, (0.0, 0.0)).unwrap();
+        let right = stereo.eye(0.05, 0.1, 0.2, (0.0, 0.0)).unwrap();
+
+        let target = transform.vector(vec3(0.6, 1.2, 3.0));
+        for r in &[left, right] {
+            let distance = (target - r.origin).magnitude();
+            assert_relative_eq!(target, r.extend(distance), epsilon = 1e-9);
+        }
+    }
+
+    // Tests that each half of the image goes to the right eye
+    #[test]
+    fn layout() {
+        let stereo = Stereo::new(Box::new(Perspective::new()), Layout::SideBySide);
+        let left = stereo.ray(-0.25, 0.0, (0.0, 0.0)).unwrap();
+        let right = stereo.ray(0.25, 0.0, (0.0, 0.0)).unwrap();
+        assert!(left.origin.x < 0.0 && right.origin.x > 0.0);
+        assert_relative_eq!(left.direction(), stereo.eye(-0.032, 0.0, 0.0, (0.0, 0.0)).unwrap().direction());
+
+        let stereo = Stereo::new(Box::new(Perspective::new()), Layout::OverUnder);
+        let top = stereo.ray(0.0, 0.25, (0.0, 0.0)).unwrap();
+        let bottom = stereo.ray(0.0, -0.25, (0.0, 0.0)).unwrap();
+        assert!(top.origin.x < 0.0 && bottom.origin.x > 0.0);
+        assert_relative_eq!(0.0, top.direction().y, epsilon = 1e-9);
+    }
+
+    // Tests that panorama eyes stay level as the view turns around
+    #[test]
+    fn panorama() {
+        let camera = Panorama {
+            position: vec3(0.0, 0.0, 0.0),
+            projection: Projection::Equirectangular,
+        };
+        let stereo = Stereo::new(Box::new(camera), Layout::OverUnder);
+
+        // Looking along +X, the right eye is toward -Z
+        let right = stereo.eye(0.032, 0.25, 0.0, (0.0, 0.0)).unwrap();
+        assert_relative_eq!(vec3(0.0, 0.0, -0.032), right.origin, epsilon = 1e-9);
+
+        // Straight up, both eyes meet
+        let up = stereo.eye(0.032, 0.0, 0.5, (0.0, 0.0)).unwrap();
+        assert_relative_eq!(vec3(0.0, 0.0, 0.0), up.origin, epsilon = 1e-9);
+    }
+}
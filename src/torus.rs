@@ -0,0 +1,240 @@
+extern crate std;
+
+use cgmath::{dot, vec2, InnerSpace, Vector3};
+use tracer::{orthonormal_basis, pair_intervals, Intersect, Interval, Shape};
+use polynomial::solve_quartic;
+use ray::Ray;
+use light::Material;
+
+// Ring shaped like a donut, lying flat in the plane perpendicular to the axis.
+// The major radius runs from the center to the middle of the tube, and the
+// minor radius is the radius of the tube itself.
+pub struct Torus {
+    pub center: Vector3<f64>,
+    pub axis: Vector3<f64>,
+    pub major_r: f64,
+    pub minor_r: f64,
+    pub color: Material,
+    u: Vector3<f64>,
+    v: Vector3<f64>,
+}
+
+impl Torus {
+    pub fn new(
+        center: Vector3<f64>,
+        axis: Vector3<f64>,
+        major_r: f64,
+        minor_r: f64,
+        color: Material,
+    ) -> Torus {
+        let axis = axis.normalize();
+        let (u, v) = orthonormal_basis(axis);
+
+        Torus {
+            center,
+            axis,
+            major_r,
+            minor_r,
+            color,
+            u,
+            v,
+        }
+    }
+
+    // Distance along the ray to the sphere enclosing the whole torus. Starting
+    // the quartic from here keeps the coefficients small for distant rays.
+    fn bounding_distance(&self, ray: &Ray) -> Option<f64> {
+        let bound = self.major_r + self.minor_r;
+        let offset = ray.origin - self.center;
+
+        let b = dot(ray.direction(), offset);
+        let c = dot(offset, offset) - bound * bound;
+        let discriminant = b * b - c;
+
+        if discriminant < 0.0 {
+            None
+        } else if c <= 0.0 {
+            // Already inside the sphere
+            Some(0.0)
+        } else if -b - discriminant.sqrt() >= 0.0 {
+            Some(-b - discriminant.sqrt())
+        } else {
+            None
+        }
+    }
+
+    /// Torus intersection expands
+    /// (|P|^2 + R^2 - r^2)^2 - 4R^2 (P_u^2 + P_v^2) = 0
+    /// with P = O + tD into a quartic in t. Because D is normalized, the
+    /// leading coefficient is always 1.
+    ///
+    /// The quartic is solved relative to a starting distance along the ray,
+    /// returning every crossing sorted by distance from the ray's origin.
+    fn crossings(&self, ray: &Ray, start: f64) -> Vec<f64> {
+        let o = ray.extend(start) - self.center;
+        let d = ray.direction();
+        let o_a = dot(o, self.axis);
+        let d_a = dot(d, self.axis);
+
+        let r2 = self.major_r * self.major_r;
+        let e = dot(o, o) - r2 - self.minor_r * self.minor_r;
+        let f = dot(o, d);
+
+        solve_quartic(
+            1.0,
+            4.0 * f,
+            2.0 * e + 4.0 * f * f + 4.0 * r2 * d_a * d_a,
+            4.0 * f * e + 8.0 * r2 * o_a * d_a,
+            e * e - 4.0 * r2 * (self.minor_r * self.minor_r - o_a * o_a),
+        ).into_iter()
+            .map(|t| t + start)
+            .collect()
+    }
+
+    // Builds the intersect at a distance along the ray
+    fn intersect_at(&self, ray: &Ray, distance: f64) -> Intersect {
+        let point = ray.extend(distance);
+        let p = point - self.center;
+
+        // Closest point on the circle running through the middle of the
+        // tube. The normal points straight out from there.
+        let height = dot(p, self.axis);
+        let flat = p - self.axis * height;
+        let ring = flat.normalize() * self.major_r;
+        let normal = (p - ring).normalize();
+
+        // Angle around the axis, then angle around the tube
+        let around = dot(flat, self.v).atan2(dot(flat, self.u));
+        let tube = height.atan2(flat.magnitude() - self.major_r);
+
+        Intersect {
+            distance,
+            point,
+            normal,
+            uv: vec2(
+                0.5 + around / (2.0 * std::f64::consts::PI),
+                0.5 + tube / (2.0 * std::f64::consts::PI),
+            ),
+            color: &self.color,
+            shape: None,
+        }
+    }
+}
+
+impl Shape for Torus {
+    fn intersect(&self, ray: &Ray) -> Option<Intersect> {
+        let start = match self.bounding_distance(ray) {
+            Some(start) => start,
+            None => return None,
+        };
+
+        // Crossings are sorted, so the first one in front of the ray is closest
+        self.crossings(ray, start)
+            .into_iter()
+            .find(|&distance| ray.contains(distance))
+            .map(|distance| self.intersect_at(ray, distance))
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        // Solve from the point on the line closest to the center, since the
+        // crossings may be on either side of the ray's origin
+        let start = dot(self.center - ray.origin, ray.direction());
+        let bound = self.major_r + self.minor_r;
+        if (ray.extend(start) - self.center).magnitude() > bound {
+            return vec![];
+        }
+
+        pair_intervals(
+            self.crossings(ray, start)
+                .into_iter()
+                .map(|distance| self.intersect_at(ray, distance))
+                .collect(),
+        )
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use cgmath::vec3;
+    use tracer::Shape;
+    use torus::Torus;
+    use ray::Ray;
+    use light::{Material, Rgb};
+
+    fn torus() -> Torus {
+        Torus::new(
+            vec3(0.0, 0.0, 5.0),
+            vec3(0.0, 1.0, 0.0),
+            1.0,
+            0.25,
+            Material::new(Rgb::new([255, 255, 0]), (1.0, 1.0, 1.0), 0.0, 0.0, 0.0),
+        )
+    }
+
+    // Tests rays hitting the outside of the ring and one through the hole
+    #[test]
+    fn intersect() {
+        let torus = torus();
+
+        let r = Ray::new(vec3(5.0, 0.0, 5.0), vec3(-1.0, 0.0, 0.0));
+        let intersect = torus
+            .intersect(&r)
+            .expect("Ray should intersect with the tube");
+        assert_relative_eq!(3.75, intersect.distance, epsilon = 1e-9);
+        assert_relative_eq!(vec3(1.0, 0.0, 0.0), intersect.normal, epsilon = 1e-9);
+
+        // Straight down the axis passes through the hole
+        let r = Ray::new(vec3(0.0, 5.0, 5.0), vec3(0.0, -1.0, 0.0));
+        assert!(torus.intersect(&r).is_none());
+
+        // Across the hole, hitting the outside of the near side of the ring
+        let r = Ray::new(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0));
+        let intersect = torus
+            .intersect(&r)
+            .expect("Ray should intersect with the ring");
+        assert_relative_eq!(3.75, intersect.distance, epsilon = 1e-9);
+    }
+
+    // Tests rays that just touch the surface, which give repeated roots
+    #[test]
+    fn intersect_grazing() {
+        let torus = torus();
+
+        // Tangent to the outside edge of the ring
+        let r = Ray::new(vec3(1.25, 0.0, 0.0), vec3(0.0, 0.0, 1.0));
+        let intersect = torus
+            .intersect(&r)
+            .expect("Ray should graze the outside of the ring");
+        assert_relative_eq!(5.0, intersect.distance, epsilon = 1e-6);
+        assert_relative_eq!(vec3(1.0, 0.0, 0.0), intersect.normal, epsilon = 1e-6);
+
+        // Just outside the ring
+        let r = Ray::new(vec3(1.2501, 0.0, 0.0), vec3(0.0, 0.0, 1.0));
+        assert!(torus.intersect(&r).is_none());
+
+        // Skimming across the top, touching the tube on both sides of the hole
+        let r = Ray::new(vec3(-5.0, 0.25, 5.0), vec3(1.0, 0.0, 0.0));
+        let intersect = torus
+            .intersect(&r)
+            .expect("Ray should graze the top of the tube");
+        assert_relative_eq!(4.0, intersect.distance, epsilon = 1e-6);
+        assert_relative_eq!(vec3(0.0, 1.0, 0.0), intersect.normal, epsilon = 1e-6);
+    }
+
+    // Tests rays that start inside the tube, which should hit the far wall
+    #[test]
+    fn intersect_inside() {
+        let torus = torus();
+
+        let r = Ray::new(vec3(0.0, 0.0, 4.0), vec3(0.0, 0.0, -1.0));
+        let intersect = torus
+            .intersect(&r)
+            .expect("Ray should hit the inside of the tube");
+        assert_relative_eq!(0.25, intersect.distance, epsilon = 1e-9);
+
+        // The normal still points out of the tube, away from the ray origin
+        assert_relative_eq!(vec3(0.0, 0.0, -1.0), intersect.normal, epsilon = 1e-9);
+    }
+}
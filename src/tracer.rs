@@ -1,16 +1,16 @@
 extern crate std;
 
-use cgmath::{dot, InnerSpace, Vector3};
+use std::cmp::Ordering;
+use cgmath::{dot, vec3, InnerSpace, Vector2, Vector3};
 use ray::Ray;
-use light::{phong, Light, Material, Rgb};
-use std::any::Any;
-use std::borrow::Borrow;
+use scene::ShapeId;
+use light::{Material, Rgb};
 
 
-const MAX_DEPTH: u8 = 5;
 const ETA_AIR: f64 = 1.0;
 
 // Represents the intersection of a Ray with an object
+#[derive(Clone)]
 pub struct Intersect<'a> {
     // Distance from the origin where the intersect occurs
     pub distance: f64,
@@ -21,141 +21,104 @@ pub struct Intersect<'a> {
     // Normal vector from the surface of the shape at this intersect
     pub normal: Vector3<f64>,
 
+    // Surface coordinates of the intersect, with both components in [0, 1]
+    pub uv: Vector2<f64>,
+
     // Material of the object where the intersect occurs
     pub color: &'a Material,
 
-    // Shape that the ray intersects
-    pub shape: &'a Shape,
+    // Scene's ID for the shape that was hit. Shapes leave this empty; the
+    // scene fills it in.
+    pub shape: Option<ShapeId>,
 }
 
 // Trait for objects that can be placed in the raytracer scene
 pub trait Shape: Send + Sync {
     // If the Ray intersects the shape, returns the distance from the Ray's
-    // origin and the color at that point.
+    // origin and the color at that point. Only the closest intersect within
+    // the Ray's bounds counts.
     fn intersect(&self, ray: &Ray) -> Option<Intersect>;
 
-    // Used to downcast and check equality
-    fn eq(&self, other: &Shape) -> bool;
-    fn as_any(&self) -> &Any;
-}
+    // Checks whether anything on the shape is within the Ray's bounds. Shapes
+    // can override this to skip building the intersect, or to stop at the
+    // first hit instead of searching for the closest.
+    fn occluded(&self, ray: &Ray) -> bool {
+        self.intersect(ray).is_some()
+    }
 
-impl<'a, 'b> PartialEq<Shape + 'b> for Shape + 'a {
-    fn eq(&self, other: &(Shape + 'b)) -> bool {
-        Shape::eq(self, other)
+    // Lists every span along the ray's line that's inside the shape, sorted by
+    // distance. Distances are negative for spans behind the ray's origin.
+    // Shapes that don't enclose a volume have no spans.
+    fn intervals(&self, _ray: &Ray) -> Vec<Interval> {
+        vec![]
     }
-}
 
+    // Surface area of the shape, if it can be sampled
+    fn area(&self) -> f64 {
+        0.0
+    }
 
-// Objects that can be placed in a scene
-pub struct Background {
-    pub color: Rgb,
-}
+    // Picks a point on the surface from two uniform random numbers in [0, 1),
+    // with every point equally likely. The distance of the intersect means
+    // nothing. Shapes that glow can only be sampled as lights if they can
+    // pick points; otherwise they're only found by rays that happen to hit
+    // them.
+    fn sample(&self, _u: f64, _v: f64) -> Option<Intersect> {
+        None
+    }
 
-// Of all shapes that intersect with this ray, select the closest one that's in
-// front of the starting point.
-pub fn shape_intersect<'a>(
-    r: &Ray,
-    shapes: &'a Vec<Box<Shape>>,
-    exclude: Option<&Shape>,
-) -> Option<Intersect<'a>> {
-    shapes
-        .iter()
-        .filter(|&shape| {
-            exclude.map_or(true, |e| {
-                let s: &'a Shape = shape.borrow();
-                &e != &s
-            })
-        })
-        .filter_map(|shape| shape.intersect(&r))
-        .filter(|intersect| intersect.distance >= 0.0)
-        .min_by(|first, second| {
-            first.distance.partial_cmp(&second.distance).unwrap()
-        })
+    // Whether any part of the surface glows, going by the materials it's made
+    // of. Only shapes that can be sampled need to say.
+    fn emits(&self) -> bool {
+        false
+    }
 }
 
-// The main tracer function. Fires the ray into the scene, calculating the
-// objects it intersects and the final output color
-pub fn illuminate(
-    r: Ray,
-    shapes: &Vec<Box<Shape>>,
-    lights: &Vec<Light>,
-    background: &Background,
-    last_shape: Option<&Shape>,
-    depth: u8,
-) -> Rgb {
-    match shape_intersect(&r, shapes, last_shape) {
-        Some(intersect) => {
-            let k_r = intersect.color.reflection();
-            let k_t = intersect.color.transmission();
-
-            let local = phong(
-                &intersect,
-                shapes,
-                lights,
-                (r.direction() - r.origin).normalize(),
-            );
-
-            let reflection = if depth < MAX_DEPTH && k_r > 0.0 {
-                Some(reflect(&intersect, depth, shapes, lights, background) * k_r)
-            } else {
-                None
-            };
-
-            let transmission = if depth < MAX_DEPTH && k_t > 0.0 {
-                Some(transmit(r.direction(), &intersect, depth, shapes, lights, background) * k_t)
-            } else {
-                None
-            };
+// Section of a ray that's inside a solid shape, bounded by where the ray
+// enters and leaves the surface
+#[derive(Clone)]
+pub struct Interval<'a> {
+    pub enter: Intersect<'a>,
+    pub exit: Intersect<'a>,
+}
 
-            [reflection, transmission]
-                .to_vec()
-                .into_iter()
-                .filter_map(|c| c)
-                .fold(local, |result, color| result + color)
-        }
-        None => background.color.clone(),
+// Pairs up every surface crossing along a ray into intervals. Closed surfaces
+// are always crossed an even number of times, alternating between entering
+// and leaving the shape.
+pub fn pair_intervals(mut hits: Vec<Intersect>) -> Vec<Interval> {
+    hits.sort_by(|first, second| {
+        first
+            .distance
+            .partial_cmp(&second.distance)
+            .unwrap_or(Ordering::Equal)
+    });
+
+    let mut hits = hits.into_iter();
+    let mut intervals = vec![];
+    while let (Some(enter), Some(exit)) = (hits.next(), hits.next()) {
+        intervals.push(Interval { enter, exit });
     }
+    intervals
 }
 
-fn reflect(
-    intersect: &Intersect,
-    depth: u8,
-    shapes: &Vec<Box<Shape>>,
-    lights: &Vec<Light>,
-    background: &Background,
-) -> Rgb {
-    let i = intersect.point;
-    let n = intersect.normal;
-    let r = i - 2.0 * (n * dot(i, n));
 
-    let ray = Ray::new(intersect.point, r);
+// Builds two unit vectors perpendicular to the axis and to each other. Shapes
+// with an axis of symmetry use these to measure angles around that axis.
+pub fn orthonormal_basis(axis: Vector3<f64>) -> (Vector3<f64>, Vector3<f64>) {
+    let helper = if axis.x.abs() < 0.9 {
+        vec3(1.0, 0.0, 0.0)
+    } else {
+        vec3(0.0, 1.0, 0.0)
+    };
 
-    illuminate(
-        ray,
-        shapes,
-        lights,
-        background,
-        Some(intersect.shape),
-        depth + 1,
-    )
+    let u = axis.cross(helper).normalize();
+    let v = axis.cross(u);
+    (u, v)
 }
 
-fn transmit(
-    d: Vector3<f64>,
-    intersect: &Intersect,
-    depth: u8,
-    shapes: &Vec<Box<Shape>>,
-    lights: &Vec<Light>,
-    background: &Background,
-) -> Rgb {
-    illuminate(
-        transmission_ray(d, intersect),
-        shapes,
-        lights,
-        background,
-        Some(intersect.shape),
-        depth + 1,
-    )
+// Objects that can be placed in a scene
+pub struct Background {
+    pub color: Rgb,
 }
 
 // Calculates the ray transmitted through an object
@@ -183,103 +146,5 @@ pub fn transmission_ray(d: Vector3<f64>, intersect: &Intersect) -> Ray {
         (d * n_it) + (n * (n_it * dot(-d, n) - discriminant.sqrt()))
     };
 
-    Ray::new(intersect.point, t)
-}
-
-
-
-#[cfg(test)]
-mod tests {
-
-    use cgmath::vec3;
-    use ray::Ray;
-    use tracer::Shape;
-    use floor::Floor;
-    use light::{Material, Rgb};
-    use super::shape_intersect;
-    use std::borrow::Borrow;
-
-    // Tests that the closest shape is selected
-    #[test]
-    fn intersect_ordering() {
-        let color1 = Rgb::new([255, 0, 0]);
-        let color2 = Rgb::new([0, 255, 0]);
-
-        let f1 = Floor::new(
-            vec3(-1.0, -1.0, 1.0),
-            vec3(-1.0, 1.0, 1.0),
-            vec3(1.0, -1.0, 1.0),
-            vec3(1.0, 1.0, 1.0),
-            Material::new(color1.clone(), (1.0, 1.0, 1.0), 0.0, 0.0, 0.0),
-            Material::new(color1.clone(), (1.0, 1.0, 1.0), 0.0, 0.0, 0.0),
-        );
-
-        let f2 = Floor::new(
-            vec3(-1.0, -1.0, 2.0),
-            vec3(-1.0, 1.0, 2.0),
-            vec3(1.0, -1.0, 2.0),
-            vec3(1.0, 1.0, 2.0),
-            Material::new(color2.clone(), (1.0, 1.0, 1.0), 0.0, 0.0, 0.0),
-            Material::new(color2.clone(), (1.0, 1.0, 1.0), 0.0, 0.0, 0.0),
-        );
-
-        let shapes: Vec<Box<Shape>> = vec![Box::new(f1), Box::new(f2)];
-
-        let r = Ray::new(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0));
-
-        let intersect =
-            shape_intersect(&r, &shapes, None).expect("Both of these objects should intersect");
-
-        assert_ulps_eq!(1.0, intersect.distance);
-    }
-
-    // Tests that a shape is excluded if specified
-    #[test]
-    fn intersect_exclude() {
-        let color1 = Rgb::new([255, 0, 0]);
-        let color2 = Rgb::new([0, 255, 0]);
-
-        let f1: Box<Shape> = Box::new(Floor::new(
-            vec3(-1.0, -1.0, 1.0),
-            vec3(-1.0, 1.0, 1.0),
-            vec3(1.0, -1.0, 1.0),
-            vec3(1.0, 1.0, 1.0),
-            Material::new(color1.clone(), (1.0, 1.0, 1.0), 0.0, 0.0, 0.0),
-            Material::new(color1.clone(), (1.0, 1.0, 1.0), 0.0, 0.0, 0.0),
-        ));
-
-        let f2: Box<Shape> = Box::new(Floor::new(
-            vec3(-1.0, -1.0, 2.0),
-            vec3(-1.0, 1.0, 2.0),
-            vec3(1.0, -1.0, 2.0),
-            vec3(1.0, 1.0, 2.0),
-            Material::new(color2.clone(), (1.0, 1.0, 1.0), 0.0, 0.0, 0.0),
-            Material::new(color2.clone(), (1.0, 1.0, 1.0), 0.0, 0.0, 0.0),
-        ));
-
-        let mut shapes: Vec<Box<Shape>> = vec![f1, f2];
-
-        let r = Ray::new(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0));
-
-        {
-            let s2: &Shape = shapes[1].borrow();
-            // Exclude a shape that already isn't closest
-            let intersect = shape_intersect(&r, &shapes, Some(s2)).expect("f1 should intersect");
-            assert_ulps_eq!(1.0, intersect.distance);
-        }
-
-        {
-            let s1: &Shape = shapes[0].borrow();
-            // Exclude the closest shape
-            let intersect = shape_intersect(&r, &shapes, Some(s1)).expect("f2 should intersect");
-            assert_ulps_eq!(2.0, intersect.distance);
-        }
-
-        // Exclude the only shape
-        shapes.remove(1);
-        let s1: &Shape = shapes[0].borrow();
-        let intersect = shape_intersect(&r, &shapes, Some(s1));
-        assert!(intersect.is_none());
-    }
-
+    Ray::from_surface(intersect.point, intersect.normal, t)
 }
@@ -0,0 +1,242 @@
+use cgmath::dot;
+use tracer::{transmission_ray, Intersect};
+use integrator::{Integrator, Settings};
+use ao::AmbientOcclusion;
+use photon::PhotonMap;
+use color::Color;
+use ray::Ray;
+use scene::Scene;
+use light::{phong, Rgb};
+use sampler::Sampler;
+
+const MAX_DEPTH: u8 = 5;
+
+// Classic recursive ray tracing. Surfaces are lit directly with phong shading,
+// and perfect mirror reflection and refraction rays are followed until they
+// run out of depth.
+pub struct Whitted {
+    pub max_depth: u8,
+
+    // Darkens the ambient light in corners and crevices
+    pub ambient_occlusion: AmbientOcclusion,
+
+    // Photons used to build the caustic map when the render is prepared
+    pub caustic_photons: usize,
+    caustics: Option<PhotonMap>,
+}
+
+impl Whitted {
+    pub fn new(settings: &Settings) -> Whitted {
+        Whitted {
+            max_depth: MAX_DEPTH,
+            ambient_occlusion: settings.ambient_occlusion.clone(),
+            caustic_photons: settings.caustic_photons,
+            caustics: None,
+        }
+    }
+
+    // Calculates the color seen by a ray that has already bounced depth - 1
+    // times
+    fn illuminate(&self, r: &Ray, scene: &Scene, sampler: &mut Sampler, depth: u8) -> Rgb {
+        match scene.intersect(r) {
+            Some(intersect) => {
+                let k_r = intersect.color.reflection();
+                let k_t = intersect.color.transmission();
+
+                // Ambient light comes from the side of the surface the ray hit.
+                // There's no need to check what blocks it if there isn't any.
+                let n = if dot(intersect.normal, r.direction()) > 0.0 {
+                    -intersect.normal
+                } else {
+                    intersect.normal
+                };
+                let (k_a, k_d, _) = intersect.color.phong_constants();
+                let visibility = if k_a > 0.0 {
+                    self.ambient_occlusion.visibility(intersect.point, n, r.time, scene, sampler)
+                } else {
+                    0.0
+                };
+
+                let local = phong(
+                    &intersect,
+                    r.time,
+                    scene,
+                    r.direction(),
+                    visibility,
+                ) + intersect.color.emission().to_rgb();
+
+                // Light focused onto the surface by mirrors and glass, which
+                // reflects diffusely like the light in phong shading
+                let caustic = match self.caustics {
+                    Some(ref caustics) if k_d > 0.0 => {
+                        let irradiance = caustics.irradiance(intersect.point, n);
+                        Some((Color::from_rgb(intersect.color.diffuse()) * irradiance * k_d).to_rgb())
+                    }
+                    _ => None,
+                };
+
+                let reflection = if depth < self.max_depth && k_r > 0.0 {
+                    Some(self.reflect(r, &intersect, scene, sampler, depth) * k_r)
+                } else {
+                    None
+                };
+
+                let transmission = if depth < self.max_depth && k_t > 0.0 {
+                    Some(self.transmit(r, &intersect, scene, sampler, depth) * k_t)
+                } else {
+                    None
+                };
+
+                [caustic, reflection, transmission]
+                    .to_vec()
+                    .into_iter()
+                    .filter_map(|c| c)
+                    .fold(local, |result, color| result + color)
+            }
+            None => scene.miss(r, depth == 1).to_rgb(),
+        }
+    }
+
+    fn reflect(
+        &self,
+        r: &Ray,
+        intersect: &Intersect,
+        scene: &Scene,
+        sampler: &mut Sampler,
+        depth: u8,
+    ) -> Rgb {
+        let d = r.direction();
+        let n = intersect.normal;
+        let reflected = d - 2.0 * n * dot(d, n);
+
+        let ray = Ray::from_surface(intersect.point, n, reflected).at_time(r.time);
+
+        self.illuminate(&ray, scene, sampler, depth + 1)
+    }
+
+    fn transmit(
+        &self,
+        r: &Ray,
+        intersect: &Intersect,
+        scene: &Scene,
+        sampler: &mut Sampler,
+        depth: u8,
+    ) -> Rgb {
+        let ray = transmission_ray(r.direction(), intersect).at_time(r.time);
+
+        self.illuminate(&ray, scene, sampler, depth + 1)
+    }
+}
+
+impl Integrator for Whitted {
+    fn prepare(&mut self, scene: &Scene) {
+        // Scenes without mirrors or glass in view of a point light don't store
+        // any photons, so there's nothing to look up
+        self.caustics = if self.caustic_photons > 0 && !scene.lights.is_empty() {
+            Some(PhotonMap::caustics(scene, self.caustic_photons))
+                .filter(|caustics| !caustics.is_empty())
+        } else {
+            None
+        };
+    }
+
+    fn radiance(&self, ray: &Ray, scene: &Scene, sampler: &mut Sampler) -> Rgb {
+        self.illuminate(ray, scene, sampler, 1)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use cgmath::{vec3, Vector3};
+    use integrator::Integrator;
+    use integrator::Settings;
+    use whitted::Whitted;
+    use scene::Scene;
+    use sphere::Sphere;
+    use tracer::Background;
+    use ray::Ray;
+    use light::{Light, Material, Rgb};
+    use sampler::Independent;
+
+    // Tests that rays see the background when they miss and the object's
+    // ambient color when there are no lights or occluders
+    #[test]
+    fn radiance() {
+        let background = Rgb::new([0, 175, 215]);
+        let mut scene = Scene::new(Background {
+            color: background.clone(),
+        });
+        scene.add(Box::new(Sphere::new(
+            vec3(0.0, 0.0, 2.0),
+            0.5,
+            Material::new(Rgb::new([200, 100, 0]), (1.0, 0.0, 0.0), 0.0, 0.0, 0.0),
+        )));
+
+        let whitted = Whitted::new(&Settings::new());
+        let sampler = &mut Independent::new(0);
+
+        let r = Ray::new(vec3(0.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0));
+        assert_eq!(background, whitted.radiance(&r, &scene, sampler));
+
+        let r = Ray::new(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0));
+        assert_eq!(Rgb::new([60, 30, 0]), whitted.radiance(&r, &scene, sampler));
+    }
+
+    // Tests that a backdrop is only seen by the camera, while reflections
+    // see the background
+    #[test]
+    fn radiance_camera_background() {
+        let background = Rgb::new([0, 175, 215]);
+        let mut scene = Scene::new(Background {
+            color: background.clone(),
+        });
+        scene.camera_background = Some(Box::new(Background {
+            color: Rgb::new([255, 255, 255]),
+        }));
+
+        // Mirror facing back at the camera, with nothing else lighting it
+        let mirror = Material::new(Rgb::new([0, 0, 0]), (0.0, 0.0, 0.0), 1.0, 0.0, 0.0);
+        scene.add(Box::new(Sphere::new(vec3(0.0, 0.0, 2.0), 0.5, mirror)));
+
+        let whitted = Whitted::new(&Settings::new());
+        let sampler = &mut Independent::new(0);
+
+        let r = Ray::new(vec3(0.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0));
+        assert_eq!(Rgb::new([255, 255, 255]), whitted.radiance(&r, &scene, sampler));
+
+        let r = Ray::new(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0));
+        assert_eq!(background, whitted.radiance(&r, &scene, sampler));
+    }
+
+    // Shiny mirror ahead of a point, lit from that point, with a red ball
+    // behind it that only shows up in the mirror
+    fn mirror_scene(origin: Vector3<f64>) -> Scene {
+        let mut scene = Scene::new(Background {
+            color: Rgb::new([0, 0, 0]),
+        });
+        let mirror = Material::new(Rgb::new([0, 0, 0]), (0.0, 0.0, 0.5), 0.5, 0.0, 0.0);
+        let red = Material::new(Rgb::new([255, 0, 0]), (1.0, 0.0, 0.0), 0.0, 0.0, 0.0);
+        scene.add(Box::new(Sphere::new(origin + vec3(0.0, 0.0, 2.0), 0.5, mirror)));
+        scene.add(Box::new(Sphere::new(origin + vec3(0.0, 0.0, -2.0), 0.5, red)));
+        scene.lights.push(Light::point(origin + vec3(0.0, 0.0, 0.5), Rgb::new([255, 255, 255])));
+        scene
+    }
+
+    // Tests that reflections and highlights stay the same when the camera
+    // and the scene move together, away from the origin
+    #[test]
+    fn radiance_moved_camera() {
+        let whitted = Whitted::new(&Settings::new());
+        let sampler = &mut Independent::new(0);
+
+        let r = Ray::new(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0));
+        let expected = whitted.radiance(&r, &mirror_scene(vec3(0.0, 0.0, 0.0)), sampler);
+        assert!(expected.color.data[0] > expected.color.data[1]);
+
+        let origin = vec3(3.0, -1.0, 2.0);
+        let r = Ray::new(origin, vec3(0.0, 0.0, 1.0));
+        assert_eq!(expected, whitted.radiance(&r, &mirror_scene(origin), sampler));
+    }
+}